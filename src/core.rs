@@ -8,6 +8,8 @@ pub use instructions::*;
 pub use types::*;
 pub use value::*;
 
+use std::collections::BTreeMap;
+
 #[derive(Default, Clone, Debug)]
 pub struct Module {
     pub types: Vec<FuncType>,
@@ -19,6 +21,29 @@ pub struct Module {
     pub funcs: Vec<Func>,
     pub start: Option<Idx<FuncIdx>>,
     pub elements: Vec<Element>,
+    pub datas: Vec<Data>,
+    pub data_count: Option<u32>,
+    pub customs: Vec<Custom>,
+    pub names: Names,
+}
+
+/// A custom section, which is retained as is except for the `name` section,
+/// which is also parsed into [`Module::names`].
+#[derive(Clone, Debug)]
+pub struct Custom {
+    pub name: Name,
+    pub data: Vec<u8>,
+    /// The id of the last non-custom section preceding this one, or `None` if
+    /// it precedes all of them.
+    pub after: Option<u8>,
+}
+
+/// Debug names from the `name` custom section, keyed by index.
+#[derive(Default, Clone, Debug)]
+pub struct Names {
+    pub module: Option<Name>,
+    pub funcs: BTreeMap<u32, Name>,
+    pub locals: BTreeMap<u32, BTreeMap<u32, Name>>,
 }
 
 #[derive(Clone, Debug)]
@@ -30,7 +55,7 @@ pub struct Import {
 
 #[derive(Clone, Debug)]
 pub enum ImportDesc {
-    Func(Idx<FuncIdx>),
+    Func(Idx<TypeIdx>),
     Table(TableType),
     Memory(Limits),
     Global(GlobalType),
@@ -65,8 +90,24 @@ pub struct Global {
 #[derive(Clone, Debug)]
 pub struct Func {
     pub type_id: Idx<TypeIdx>,
-    pub locals: Vec<ValueType>,
+    pub locals: Locals,
     pub body: Expression,
+    /// The encoded locals and body if their decoding was deferred, in which
+    /// case `locals` and `body` are empty.
+    pub raw: Option<RawBody>,
+}
+
+/// The declared locals of a function as runs of locals of the same type, in
+/// the order in which they appear in the binary.
+pub type Locals = Vec<(u32, ValueType)>;
+
+/// The encoding of the locals and body of a function, as found in the code
+/// section.
+#[derive(Clone, Debug)]
+pub struct RawBody {
+    /// Offset of the first byte in the module.
+    pub offset: usize,
+    pub bytes: Vec<u8>,
 }
 
 #[derive(Clone, Debug)]
@@ -76,6 +117,21 @@ pub struct Element {
     pub mode: ElementMode,
 }
 
+#[derive(Clone, Debug)]
+pub struct Data {
+    pub init: Vec<u8>,
+    pub mode: DataMode,
+}
+
+#[derive(Clone, Debug)]
+pub enum DataMode {
+    Active {
+        memory: Idx<MemIdx>,
+        offset: Expression,
+    },
+    Passive,
+}
+
 #[derive(Clone, Debug)]
 pub enum ElementMode {
     Active {
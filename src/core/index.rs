@@ -33,7 +33,7 @@ impl<T> From<Idx<T>> for u32 {
 
 impl<T> Clone for Idx<T> {
     fn clone(&self) -> Self {
-        Self::new(self.index)
+        *self
     }
 }
 
@@ -1,5 +1,5 @@
 use super::{
-    DataIdx, FuncIdx, FuncType, GlobalIdx, Idx, LabelIdx, LocalIdx, RefType, TableIdx, TypeIdx,
+    DataIdx, ElemIdx, FuncIdx, GlobalIdx, Idx, LabelIdx, LocalIdx, RefType, TableIdx, TypeIdx,
     ValueType,
 };
 
@@ -10,7 +10,7 @@ pub struct Expression {
 
 #[derive(Clone, Debug)]
 pub enum BlockType {
-    Type(Idx<FuncType>),
+    Type(Idx<TypeIdx>),
     ValType(Option<ValueType>),
 }
 
@@ -20,23 +20,6 @@ pub struct MemArg {
     pub offset: u32,
 }
 
-#[derive(Clone)]
-pub struct OpCode {
-    code: u8,
-}
-
-impl std::fmt::Debug for OpCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:#x}", self.code)
-    }
-}
-
-impl From<u8> for OpCode {
-    fn from(code: u8) -> Self {
-        OpCode { code }
-    }
-}
-
 #[derive(Clone, Debug)]
 pub enum Instruction {
     // control instructions
@@ -82,7 +65,20 @@ pub enum Instruction {
     GlobalSet(Idx<GlobalIdx>),
 
     // table instructions
-    // TODO: add
+    TableGet(Idx<TableIdx>),
+    TableSet(Idx<TableIdx>),
+    TableSize(Idx<TableIdx>),
+    TableGrow(Idx<TableIdx>),
+    TableFill(Idx<TableIdx>),
+    TableCopy {
+        dst: Idx<TableIdx>,
+        src: Idx<TableIdx>,
+    },
+    TableInit {
+        elem: Idx<ElemIdx>,
+        table: Idx<TableIdx>,
+    },
+    ElemDrop(Idx<ElemIdx>),
 
     // memory instructions
     I32Load(MemArg),
@@ -132,7 +128,50 @@ pub enum Instruction {
     I64Extend16S,
     I64Extend32S,
     I64Eqz,
-    Numeric(OpCode), // TODO: fix
+
+    F32Const(f32),
+    F32UnOp(FUnOp),
+    F32BinOp(FBinOp),
+    F32RelOp(FRelOp),
+
+    F64Const(f64),
+    F64UnOp(FUnOp),
+    F64BinOp(FBinOp),
+    F64RelOp(FRelOp),
+
+    I32WrapI64,
+    I32TruncF32S,
+    I32TruncF32U,
+    I32TruncF64S,
+    I32TruncF64U,
+    I64ExtendI32S,
+    I64ExtendI32U,
+    I64TruncF32S,
+    I64TruncF32U,
+    I64TruncF64S,
+    I64TruncF64U,
+    F32ConvertI32S,
+    F32ConvertI32U,
+    F32ConvertI64S,
+    F32ConvertI64U,
+    F32DemoteF64,
+    F64ConvertI32S,
+    F64ConvertI32U,
+    F64ConvertI64S,
+    F64ConvertI64U,
+    F64PromoteF32,
+    I32ReinterpretF32,
+    I64ReinterpretF64,
+    F32ReinterpretI32,
+    F64ReinterpretI64,
+    I32TruncSatF32S,
+    I32TruncSatF32U,
+    I32TruncSatF64S,
+    I32TruncSatF64U,
+    I64TruncSatF32S,
+    I64TruncSatF32U,
+    I64TruncSatF64S,
+    I64TruncSatF64U,
 
     // vector instructions
     Vector, // TODO: fix
@@ -177,3 +216,35 @@ pub enum IRelOp {
     GeS,
     GeU,
 }
+
+#[derive(Clone, Debug)]
+pub enum FUnOp {
+    Abs,
+    Neg,
+    Ceil,
+    Floor,
+    Trunc,
+    Nearest,
+    Sqrt,
+}
+
+#[derive(Clone, Debug)]
+pub enum FBinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    Copysign,
+}
+
+#[derive(Clone, Debug)]
+pub enum FRelOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
@@ -73,7 +73,7 @@ impl TryFrom<u8> for ValueType {
 
 pub type ResultType = Vec<ValueType>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FuncType {
     pub params: ResultType,
     pub results: ResultType,
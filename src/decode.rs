@@ -1,38 +1,63 @@
-use self::prelude::*;
-use crate::core::Module;
-use anyhow::{ensure, Result};
-use std::io::BufRead;
+use crate::core::{Expression, Locals, Module, RawBody};
+use anyhow::Result;
+use std::io::{BufRead, Cursor};
 
+mod error;
 mod instruction;
+mod parser;
 mod prelude;
 mod section;
 mod types;
 mod util;
 mod value;
 
+pub use error::{DecodeError, DecodeErrorKind};
+pub use parser::{Event, Parser};
+
+/// Decodes a module from its binary format. If the binary is malformed, the
+/// returned error is a [`DecodeError`].
 pub fn decode(buf: &mut impl BufRead) -> Result<Module> {
-    let mut header = [0u8; 8];
-    buf.read_exact(&mut header)?;
+    decode_with(buf, Parser::new())
+}
 
-    ensure!(
-        header[0..4] == [0x00, 0x61, 0x73, 0x6d],
-        "invalid magic number"
-    );
-    ensure!(header[4..8] == [0x01, 0x00, 0x00, 0x00], "invalid version");
+/// Decodes a module like [`decode`], but leaves function bodies encoded in
+/// [`Func::raw`](crate::core::Func::raw). Malformed bodies are only reported
+/// when decoded with [`decode_body`].
+pub fn decode_lazy(buf: &mut impl BufRead) -> Result<Module> {
+    decode_with(buf, Parser::new().lazy(true))
+}
 
-    let mut module = Module::default();
+/// Decodes the locals and body of a function whose decoding was deferred, along
+/// with the byte offset in the module of each instruction of the body, in the
+/// order in which they appear in the binary, followed by the offset of its
+/// final `end`. `data_count` tells whether its module has a data count section.
+pub fn decode_body(raw: &RawBody, data_count: bool) -> Result<(Locals, Expression, Vec<u32>)> {
+    let mut reader = Cursor::new(raw.bytes.as_slice());
+    parser::read_body(&mut reader, raw.offset, data_count).map_err(|err| {
+        let offset = raw.offset + reader.position() as usize;
+        DecodeError::locate(err, offset, Some(10))
+    })
+}
 
-    while buf.has_data_left()? {
-        buf.read_section(&mut module)?;
+fn decode_with(buf: &mut impl BufRead, mut parser: Parser) -> Result<Module> {
+    loop {
+        let chunk = buf.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len();
+        parser.feed(chunk)?;
+        buf.consume(n);
     }
-    Ok(module)
+    parser.finish()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::{Instruction, NumType, ValueType};
     use std::fs::File;
-    use std::io::BufReader;
+    use std::io::{BufReader, Cursor};
 
     #[test]
     fn test() {
@@ -40,4 +65,238 @@ mod tests {
         let mut buf = BufReader::new(f);
         decode(&mut buf).unwrap();
     }
+
+    #[test]
+    fn test_custom_sections() {
+        let bytes = [
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            0, 3, 1, b'a', 1, // custom section "a"
+            1, 1, 0, // type section
+            0, 2, 1, b'b', // custom section "b"
+        ];
+        let module = decode(&mut Cursor::new(bytes)).unwrap();
+        let customs = module
+            .customs
+            .iter()
+            .map(|c| (c.name.as_str(), c.data.as_slice(), c.after))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            customs,
+            vec![("a", [1].as_slice(), None), ("b", &[], Some(1))]
+        );
+
+        let wat = r#"
+            (module $m
+              (func $f (param $x i32))
+              (func (local i32) (local $y i64)))
+        "#;
+        let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        let module = decode(&mut Cursor::new(wat.encode().unwrap())).unwrap();
+
+        let names = &module.names;
+        assert_eq!(names.module.as_ref().map(|n| n.as_str()), Some("m"));
+        assert_eq!(names.funcs.get(&0).map(|n| n.as_str()), Some("f"));
+        assert!(!names.funcs.contains_key(&1));
+        assert_eq!(names.locals[&0][&0].as_str(), "x");
+        assert_eq!(names.locals[&1][&1].as_str(), "y");
+        assert_eq!(module.customs[0].name.as_str(), "name");
+        assert_eq!(module.customs[0].after, Some(10));
+    }
+
+    fn decode_error(bytes: &[u8]) -> DecodeError {
+        let err = decode(&mut Cursor::new(bytes)).err().unwrap();
+        err.downcast_ref::<DecodeError>().unwrap().clone()
+    }
+
+    #[test]
+    fn test_decode_error() {
+        let err = decode_error(b"\0asn\x01\0\0\0");
+        assert_eq!(err.kind(), &DecodeErrorKind::InvalidMagic);
+        assert_eq!(err.section(), None);
+
+        let err = decode_error(b"\0asm\x01\0\0");
+        assert_eq!(err.kind(), &DecodeErrorKind::UnexpectedEof);
+
+        let err = decode_error(b"\0asm\x01\0\0\0\x0d\0");
+        assert_eq!(err.kind(), &DecodeErrorKind::InvalidSectionId(13));
+        assert_eq!((err.offset(), err.section()), (9, None));
+
+        // a type section declaring 3 bytes but containing an empty vector
+        let err = decode_error(b"\0asm\x01\0\0\0\x01\x03\0\0\0");
+        assert_eq!(err.kind(), &DecodeErrorKind::SectionSizeMismatch);
+        assert_eq!((err.offset(), err.section()), (11, Some(1)));
+
+        // a function whose body starts with the unknown opcode 0xff
+        let err = decode_error(&[
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            1, 4, 1, 0x60, 0, 0, // type section
+            3, 2, 1, 0, // function section
+            10, 5, 1, 3, 0, 0xff, 0x0b, // code section
+        ]);
+        assert_eq!(err.kind(), &DecodeErrorKind::UnknownOpcode(0xff, None));
+        assert_eq!((err.offset(), err.section()), (24, Some(10)));
+        assert_eq!(
+            err.to_string(),
+            "illegal opcode 0xff at offset 0x18 in section 10"
+        );
+    }
+
+    #[test]
+    fn test_decode_lazy() {
+        // a function whose body starts with the unknown opcode 0xff
+        let bytes = [
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            1, 4, 1, 0x60, 0, 0, // type section
+            3, 2, 1, 0, // function section
+            10, 5, 1, 3, 0, 0xff, 0x0b, // code section
+        ];
+        let module = decode_lazy(&mut Cursor::new(bytes)).unwrap();
+        let raw = module.funcs[0].raw.as_ref().unwrap();
+        assert_eq!(
+            (raw.offset, raw.bytes.as_slice()),
+            (22, [0, 0xff, 0x0b].as_slice())
+        );
+
+        let err = decode_body(raw, false).err().unwrap();
+        let err = err.downcast_ref::<DecodeError>().unwrap();
+        assert_eq!(err.kind(), &DecodeErrorKind::UnknownOpcode(0xff, None));
+        assert_eq!((err.offset(), err.section()), (24, Some(10)));
+    }
+
+    #[test]
+    fn test_instruction_offsets() {
+        let bytes = [
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            1, 4, 1, 0x60, 0, 0, // type section
+            3, 2, 1, 0, // function section
+            10, 9, 1, 7, 0, // code section
+            0x02, 0x40, 0x01, 0x0b, // block nop end
+            0x01, 0x0b, // nop end
+        ];
+        let module = decode_lazy(&mut Cursor::new(bytes)).unwrap();
+        let (_, _, offsets) = decode_body(module.funcs[0].raw.as_ref().unwrap(), false).unwrap();
+        assert_eq!(offsets, [23, 25, 27, 28]);
+    }
+
+    fn malformed(sections: &[u8]) -> DecodeErrorKind {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        bytes.extend_from_slice(sections);
+        decode_error(&bytes).kind().clone()
+    }
+
+    #[test]
+    fn test_malformed() {
+        let message = |kind| match kind {
+            DecodeErrorKind::MalformedSection(message) => message,
+            kind => panic!("unexpected error: {}", kind),
+        };
+
+        // duplicate and out-of-order sections
+        assert_eq!(
+            message(malformed(&[1, 1, 0, 1, 1, 0])),
+            "unexpected content after last section"
+        );
+        assert_eq!(
+            message(malformed(&[10, 1, 0, 12, 1, 0])),
+            "unexpected content after last section"
+        );
+
+        // a function without a code section
+        assert_eq!(
+            message(malformed(&[1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0])),
+            "function and code section have inconsistent lengths"
+        );
+
+        // a function body shorter than its declared size
+        assert_eq!(
+            malformed(&[1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0, 10, 5, 1, 3, 0, 0x0b, 0x01]),
+            DecodeErrorKind::SectionSizeMismatch
+        );
+
+        // a single group of 2^32 - 1 locals is fine, as locals are counted
+        let code = [10, 10, 1, 8, 1, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b];
+        let bytes = [
+            b"\0asm\x01\0\0\0".as_slice(),
+            &[1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0],
+            &code,
+        ]
+        .concat();
+        let module = decode(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(
+            module.funcs[0].locals,
+            [(u32::MAX, ValueType::Num(NumType::I32))]
+        );
+        crate::validate::validate(&module).unwrap();
+
+        // two groups of 2^32 - 1 locals
+        let code = [
+            10, 16, 1, 14, 2, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0x0f,
+            0x7e, 0x0b,
+        ];
+        assert_eq!(
+            message(malformed(
+                &[[1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0].as_slice(), &code].concat()
+            )),
+            "too many locals"
+        );
+
+        // data.drop without a data count section
+        assert_eq!(
+            message(malformed(&[
+                1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0, 10, 7, 1, 5, 0, 0xfc, 9, 0, 0x0b,
+            ])),
+            "data count section required"
+        );
+
+        // an i32.const whose last byte has padding bits that do not match
+        // the sign
+        assert_eq!(
+            malformed(&[
+                1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0, 10, 11, 1, 9, 0, 0x41, 0xff, 0xff, 0xff, 0xff,
+                0x4f, 0x1a, 0x0b,
+            ]),
+            DecodeErrorKind::IntegerTooLarge
+        );
+
+        // a block type that is neither a value type nor a type index
+        assert_eq!(
+            message(malformed(&[
+                1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0, 10, 7, 1, 5, 0, 0x02, 0x41, 0x0b, 0x0b,
+            ])),
+            "malformed value type"
+        );
+
+        // a custom section whose name exceeds the section
+        assert_eq!(malformed(&[0, 2, 3, b'a']), DecodeErrorKind::UnexpectedEof);
+        assert_eq!(malformed(&[0, 0]), DecodeErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_element_init() {
+        let wat = r#"
+            (module
+              (table 4 funcref)
+              (func $f)
+              (func $g)
+              (elem (i32.const 0) $f $g)
+              (elem funcref (ref.func $g) (ref.null func)))
+        "#;
+        let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        let module = decode(&mut Cursor::new(wat.encode().unwrap())).unwrap();
+
+        let funcs = |i: usize| -> Vec<Option<u32>> {
+            module.elements[i]
+                .init
+                .iter()
+                .map(|e| match e.instructions.as_slice() {
+                    [Instruction::RefFunc(f)] => Some(f.get()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(funcs(0), vec![Some(0), Some(1)]);
+        assert_eq!(funcs(1), vec![Some(1), None]);
+    }
 }
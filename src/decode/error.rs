@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// The reason a binary module is malformed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnexpectedEof,
+    InvalidMagic,
+    InvalidVersion,
+    /// A LEB128 integer is encoded with more bytes than its type allows.
+    InvalidLeb128,
+    /// A LEB128 integer has bits set beyond the width of its type.
+    IntegerTooLarge,
+    InvalidUtf8,
+    /// An opcode, or the sub-opcode following a prefix byte, is unknown.
+    UnknownOpcode(u8, Option<u32>),
+    InvalidSectionId(u8),
+    /// The content of a section does not end at its declared size.
+    SectionSizeMismatch,
+    /// A section contains an invalid encoding, described by the message.
+    MalformedSection(&'static str),
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeErrorKind::UnexpectedEof => write!(f, "unexpected end"),
+            DecodeErrorKind::InvalidMagic => write!(f, "magic header not detected"),
+            DecodeErrorKind::InvalidVersion => write!(f, "unknown binary version"),
+            DecodeErrorKind::InvalidLeb128 => write!(f, "integer representation too long"),
+            DecodeErrorKind::IntegerTooLarge => write!(f, "integer too large"),
+            DecodeErrorKind::InvalidUtf8 => write!(f, "malformed UTF-8 encoding"),
+            DecodeErrorKind::UnknownOpcode(opcode, None) => {
+                write!(f, "illegal opcode {:#04x}", opcode)
+            }
+            DecodeErrorKind::UnknownOpcode(prefix, Some(opcode)) => {
+                write!(f, "illegal opcode {:#04x} {}", prefix, opcode)
+            }
+            DecodeErrorKind::InvalidSectionId(id) => write!(f, "malformed section id {}", id),
+            DecodeErrorKind::SectionSizeMismatch => write!(f, "section size mismatch"),
+            DecodeErrorKind::MalformedSection(message) => f.write_str(message),
+        }
+    }
+}
+
+// Decoding functions raise the bare kind, which `decode` turns into a
+// `DecodeError` once the position is known.
+impl std::error::Error for DecodeErrorKind {}
+
+/// An error describing why and where a binary module is malformed. It is
+/// reported through [`anyhow::Error`] and can be recovered with
+/// `downcast_ref::<DecodeError>()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    kind: DecodeErrorKind,
+    offset: usize,
+    section: Option<u8>,
+}
+
+impl DecodeError {
+    /// Attaches a position to `err` if it describes a malformed module. Other
+    /// errors, e.g. I/O failures of the underlying reader, are returned as is.
+    pub(super) fn locate(err: anyhow::Error, offset: usize, section: Option<u8>) -> anyhow::Error {
+        let kind = match err.downcast_ref::<DecodeErrorKind>() {
+            Some(kind) => kind.clone(),
+            None => match err.downcast_ref::<std::io::Error>() {
+                Some(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    DecodeErrorKind::UnexpectedEof
+                }
+                _ => return err,
+            },
+        };
+        DecodeError {
+            kind,
+            offset,
+            section,
+        }
+        .into()
+    }
+
+    pub fn kind(&self) -> &DecodeErrorKind {
+        &self.kind
+    }
+
+    /// Returns the offset from the start of the module of the first byte that
+    /// was not consumed when decoding failed.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the id of the section being decoded, or `None` if decoding
+    /// failed in the module header or in a section id.
+    pub fn section(&self) -> Option<u8> {
+        self.section
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {:#x}", self.kind, self.offset)?;
+        if let Some(id) = self.section {
+            write!(f, " in section {}", id)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
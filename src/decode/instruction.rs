@@ -1,41 +1,55 @@
 use super::prelude::*;
-use crate::core::{BlockType, Expression, IBinOp, IRelOp, IUnOp, Instruction, MemArg};
-use anyhow::{bail, ensure, Context as _, Result};
+use super::DecodeErrorKind;
+use crate::core::{
+    BlockType, Expression, FBinOp, FRelOp, FUnOp, IBinOp, IRelOp, IUnOp, Instruction, MemArg,
+};
+use anyhow::{bail, ensure, Result};
 use std::io::BufRead;
 
 pub trait ReadInstructionExt: BufRead {
     fn read_expr(&mut self) -> Result<Expression> {
+        self.read_expr_with(&mut |_| {})
+    }
+
+    /// Reads an expression like `read_expr`, calling `mark` at the start of
+    /// each instruction in the order in which they appear.
+    fn read_expr_with(&mut self, mark: &mut dyn FnMut(&mut Self)) -> Result<Expression> {
         let mut vec = Vec::new();
         while !self.read_if_equal(0x0b)? {
-            vec.push(self.read_instr().context("failed to read instruction")?);
+            vec.push(self.read_instr_with(mark)?);
         }
 
         Ok(Expression { instructions: vec })
     }
 
     fn read_block_type(&mut self) -> Result<BlockType> {
-        // TODO: fix
-        let mut slice = &self.fill_buf()?[0..1];
-        if let Ok(value) = slice.read_value_type() {
+        // value types are encoded as single-byte negative s33 integers, so
+        // any other byte starts a type index
+        let b = self.peek_byte()?;
+        if b == 0x40 {
             self.consume(1);
-            Ok(BlockType::ValType(Some(value)))
-        } else if self.read_if_equal(0x40)? {
             Ok(BlockType::ValType(None))
+        } else if b & 0xc0 == 0x40 {
+            Ok(BlockType::ValType(Some(self.read_value_type()?)))
         } else {
             let x = self.read_signed_leb128(33)?;
-            ensure!(x >= 0, "invalid block type");
-            Ok(BlockType::Type(u32::try_from(x)?.into()))
+            ensure!(
+                x >= 0,
+                DecodeErrorKind::MalformedSection("malformed block type")
+            );
+            Ok(BlockType::Type((x as u32).into()))
         }
     }
 
     fn read_mem_arg(&mut self) -> Result<MemArg> {
-        let align = self.read_u32().context("failed to read align")?;
-        let offset = self.read_u32().context("failed to read offset")?;
+        let align = self.read_u32()?;
+        let offset = self.read_u32()?;
         Ok(MemArg { align, offset })
     }
 
-    fn read_instr(&mut self) -> Result<Instruction> {
-        let opcode = self.read_byte().context("failed to read opcode")?;
+    fn read_instr_with(&mut self, mark: &mut dyn FnMut(&mut Self)) -> Result<Instruction> {
+        mark(self);
+        let opcode = self.read_byte()?;
         let instr = match opcode {
             // control instructions
             0x00 => Instruction::Unreachable,
@@ -45,7 +59,7 @@ pub trait ReadInstructionExt: BufRead {
 
                 let mut instructions = Vec::new();
                 while !self.read_if_equal(0x0b)? {
-                    instructions.push(self.read_instr()?);
+                    instructions.push(self.read_instr_with(mark)?);
                 }
                 Instruction::Block {
                     block_type,
@@ -57,7 +71,7 @@ pub trait ReadInstructionExt: BufRead {
 
                 let mut instructions = Vec::new();
                 while !self.read_if_equal(0x0b)? {
-                    instructions.push(self.read_instr()?);
+                    instructions.push(self.read_instr_with(mark)?);
                 }
                 Instruction::Loop {
                     block_type,
@@ -73,11 +87,11 @@ pub trait ReadInstructionExt: BufRead {
                 while !self.read_if_equal(0x0b)? {
                     if self.read_if_equal(0x05)? {
                         while !self.read_if_equal(0x0b)? {
-                            else_instructions.push(self.read_instr()?);
+                            else_instructions.push(self.read_instr_with(mark)?);
                         }
                         break;
                     }
-                    instructions.push(self.read_instr()?);
+                    instructions.push(self.read_instr_with(mark)?);
                 }
                 Instruction::If {
                     block_type,
@@ -100,7 +114,7 @@ pub trait ReadInstructionExt: BufRead {
             },
 
             // reference instructions
-            0xd0 => Instruction::RefNull(self.read_byte()?.try_into()?),
+            0xd0 => Instruction::RefNull(self.read_ref_type()?),
             0xd1 => Instruction::RefIsNull,
             0xd2 => Instruction::RefFunc(self.read_u32()?.into()),
 
@@ -117,6 +131,8 @@ pub trait ReadInstructionExt: BufRead {
             0x24 => Instruction::GlobalSet(self.read_u32()?.into()),
 
             // table instructions
+            0x25 => Instruction::TableGet(self.read_u32()?.into()),
+            0x26 => Instruction::TableSet(self.read_u32()?.into()),
 
             // memory instructions
             0x28 => Instruction::I32Load(self.read_mem_arg()?),
@@ -143,17 +159,17 @@ pub trait ReadInstructionExt: BufRead {
             0x3d => Instruction::I64Store16(self.read_mem_arg()?),
             0x3e => Instruction::I64Store32(self.read_mem_arg()?),
             0x3f => {
-                self.read_and_ensure(0x00)?;
+                self.read_and_ensure(0x00, "zero byte expected")?;
                 Instruction::MemorySize
             }
             0x40 => {
-                self.read_and_ensure(0x00)?;
+                self.read_and_ensure(0x00, "zero byte expected")?;
                 Instruction::MemoryGrow
             }
 
             // numeric instructions
             0x41 => {
-                let v = self.read_signed_leb128(32)?.try_into()?;
+                let v = self.read_signed_leb128(32)? as i32;
                 Instruction::I32Const(v)
             }
             0x42 => {
@@ -161,18 +177,14 @@ pub trait ReadInstructionExt: BufRead {
                 Instruction::I64Const(v)
             }
             0x43 => {
-                // f32.const
-                for _ in 0..4 {
-                    self.read_byte()?;
-                }
-                Instruction::Numeric(opcode.into())
+                let mut bytes = [0u8; 4];
+                self.read_exact(&mut bytes)?;
+                Instruction::F32Const(f32::from_le_bytes(bytes))
             }
             0x44 => {
-                // f64.const
-                for _ in 0..8 {
-                    self.read_byte()?;
-                }
-                Instruction::Numeric(opcode.into())
+                let mut bytes = [0u8; 8];
+                self.read_exact(&mut bytes)?;
+                Instruction::F64Const(f64::from_le_bytes(bytes))
             }
 
             0xc0 => Instruction::I32Extend8S,
@@ -213,6 +225,32 @@ pub trait ReadInstructionExt: BufRead {
 
                 Instruction::I64RelOp(op)
             }
+            idx if (0x5b..=0x60).contains(&idx) => {
+                let op = match idx {
+                    0x5b => FRelOp::Eq,
+                    0x5c => FRelOp::Ne,
+                    0x5d => FRelOp::Lt,
+                    0x5e => FRelOp::Gt,
+                    0x5f => FRelOp::Le,
+                    0x60 => FRelOp::Ge,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F32RelOp(op)
+            }
+            idx if (0x61..=0x66).contains(&idx) => {
+                let op = match idx {
+                    0x61 => FRelOp::Eq,
+                    0x62 => FRelOp::Ne,
+                    0x63 => FRelOp::Lt,
+                    0x64 => FRelOp::Gt,
+                    0x65 => FRelOp::Le,
+                    0x66 => FRelOp::Ge,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F64RelOp(op)
+            }
             idx if (0x67..=0x69).contains(&idx) => {
                 let op = match idx {
                     0x67 => IUnOp::Clz,
@@ -278,43 +316,142 @@ pub trait ReadInstructionExt: BufRead {
 
                 Instruction::I64BinOp(op)
             }
+            idx if (0x8b..=0x91).contains(&idx) => {
+                let op = match idx {
+                    0x8b => FUnOp::Abs,
+                    0x8c => FUnOp::Neg,
+                    0x8d => FUnOp::Ceil,
+                    0x8e => FUnOp::Floor,
+                    0x8f => FUnOp::Trunc,
+                    0x90 => FUnOp::Nearest,
+                    0x91 => FUnOp::Sqrt,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F32UnOp(op)
+            }
+            idx if (0x92..=0x98).contains(&idx) => {
+                let op = match idx {
+                    0x92 => FBinOp::Add,
+                    0x93 => FBinOp::Sub,
+                    0x94 => FBinOp::Mul,
+                    0x95 => FBinOp::Div,
+                    0x96 => FBinOp::Min,
+                    0x97 => FBinOp::Max,
+                    0x98 => FBinOp::Copysign,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F32BinOp(op)
+            }
+            idx if (0x99..=0x9f).contains(&idx) => {
+                let op = match idx {
+                    0x99 => FUnOp::Abs,
+                    0x9a => FUnOp::Neg,
+                    0x9b => FUnOp::Ceil,
+                    0x9c => FUnOp::Floor,
+                    0x9d => FUnOp::Trunc,
+                    0x9e => FUnOp::Nearest,
+                    0x9f => FUnOp::Sqrt,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F64UnOp(op)
+            }
+            idx if (0xa0..=0xa6).contains(&idx) => {
+                let op = match idx {
+                    0xa0 => FBinOp::Add,
+                    0xa1 => FBinOp::Sub,
+                    0xa2 => FBinOp::Mul,
+                    0xa3 => FBinOp::Div,
+                    0xa4 => FBinOp::Min,
+                    0xa5 => FBinOp::Max,
+                    0xa6 => FBinOp::Copysign,
+                    _ => unreachable!("checked above"),
+                };
+
+                Instruction::F64BinOp(op)
+            }
+
+            0xa7 => Instruction::I32WrapI64,
+            0xa8 => Instruction::I32TruncF32S,
+            0xa9 => Instruction::I32TruncF32U,
+            0xaa => Instruction::I32TruncF64S,
+            0xab => Instruction::I32TruncF64U,
+            0xac => Instruction::I64ExtendI32S,
+            0xad => Instruction::I64ExtendI32U,
+            0xae => Instruction::I64TruncF32S,
+            0xaf => Instruction::I64TruncF32U,
+            0xb0 => Instruction::I64TruncF64S,
+            0xb1 => Instruction::I64TruncF64U,
+            0xb2 => Instruction::F32ConvertI32S,
+            0xb3 => Instruction::F32ConvertI32U,
+            0xb4 => Instruction::F32ConvertI64S,
+            0xb5 => Instruction::F32ConvertI64U,
+            0xb6 => Instruction::F32DemoteF64,
+            0xb7 => Instruction::F64ConvertI32S,
+            0xb8 => Instruction::F64ConvertI32U,
+            0xb9 => Instruction::F64ConvertI64S,
+            0xba => Instruction::F64ConvertI64U,
+            0xbb => Instruction::F64PromoteF32,
+            0xbc => Instruction::I32ReinterpretF32,
+            0xbd => Instruction::I64ReinterpretF64,
+            0xbe => Instruction::F32ReinterpretI32,
+            0xbf => Instruction::F64ReinterpretI64,
+
             0xc2 => Instruction::I64Extend8S,
             0xc3 => Instruction::I64Extend16S,
             0xc4 => Instruction::I64Extend32S,
 
-            idx if (0x45..=0xc4).contains(&idx) => Instruction::Numeric(opcode.into()),
-
             0xfc => {
                 let kind = self.read_u32()?;
                 match kind {
                     // numeric instructions
-                    kind if kind <= 0x07 => Instruction::Numeric(opcode.into()),
+                    0x00 => Instruction::I32TruncSatF32S,
+                    0x01 => Instruction::I32TruncSatF32U,
+                    0x02 => Instruction::I32TruncSatF64S,
+                    0x03 => Instruction::I32TruncSatF64U,
+                    0x04 => Instruction::I64TruncSatF32S,
+                    0x05 => Instruction::I64TruncSatF32U,
+                    0x06 => Instruction::I64TruncSatF64S,
+                    0x07 => Instruction::I64TruncSatF64U,
 
                     // memory instructions
                     0x08 => {
                         let idx = self.read_u32()?.into();
-                        self.read_and_ensure(0x00)?;
+                        self.read_and_ensure(0x00, "zero byte expected")?;
                         Instruction::MemoryInit(idx)
                     }
                     0x09 => {
                         let idx = self.read_u32()?.into();
                         Instruction::DataDrop(idx)
                     }
-                    0x10 => {
-                        self.read_and_ensure(0x00)
-                            .context("invalid memory instruction")?;
-                        self.read_and_ensure(0x00)
-                            .context("invalid memory instruction")?;
+                    0x0a => {
+                        self.read_and_ensure(0x00, "zero byte expected")?;
+                        self.read_and_ensure(0x00, "zero byte expected")?;
                         Instruction::MemoryCopy
                     }
-                    0x11 => {
-                        self.read_and_ensure(0x00)
-                            .context("invalid memory instruction")?;
+                    0x0b => {
+                        self.read_and_ensure(0x00, "zero byte expected")?;
                         Instruction::MemoryFill
                     }
 
                     // table instructions
-                    _ => bail!("invalid table instruction: {}", kind),
+                    0x0c => {
+                        let elem = self.read_u32()?.into();
+                        let table = self.read_u32()?.into();
+                        Instruction::TableInit { elem, table }
+                    }
+                    0x0d => Instruction::ElemDrop(self.read_u32()?.into()),
+                    0x0e => {
+                        let dst = self.read_u32()?.into();
+                        let src = self.read_u32()?.into();
+                        Instruction::TableCopy { dst, src }
+                    }
+                    0x0f => Instruction::TableGrow(self.read_u32()?.into()),
+                    0x10 => Instruction::TableSize(self.read_u32()?.into()),
+                    0x11 => Instruction::TableFill(self.read_u32()?.into()),
+                    _ => bail!(DecodeErrorKind::UnknownOpcode(0xfc, Some(kind))),
                 }
             }
 
@@ -349,7 +486,7 @@ pub trait ReadInstructionExt: BufRead {
                 Instruction::Vector
             }
 
-            _ => bail!("invalid opcode: {}", opcode),
+            _ => bail!(DecodeErrorKind::UnknownOpcode(opcode, None)),
         };
 
         Ok(instr)
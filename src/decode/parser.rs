@@ -0,0 +1,624 @@
+use super::prelude::*;
+use super::{DecodeError, DecodeErrorKind};
+use crate::core::{Expression, Instruction, Locals, Module, RawBody};
+use anyhow::{ensure, Result};
+use std::io::{BufRead, Cursor, Read};
+
+/// Something that has been decoded from the bytes fed to a [`Parser`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A section with the given id other than the code section has been
+    /// decoded into the module.
+    Section(u8),
+    /// The entry at the given index of the section with the given id has been
+    /// decoded into the module, e.g. `Entry(11, 0)` for the first data
+    /// segment. Sections other than custom, start, data count and code
+    /// sections are vectors of such entries.
+    Entry(u8, u32),
+    /// The code section starts, followed by the given number of bodies.
+    CodeStart(u32),
+    /// The body of the function at the given index in `Module::funcs` has been
+    /// decoded.
+    Func(u32),
+    /// The code section has ended.
+    CodeEnd,
+}
+
+enum State {
+    Header,
+    /// Expecting a section id and size.
+    Section,
+    /// Expecting the content of a custom, start or data count section ending
+    /// at `end`.
+    Content {
+        id: u8,
+        end: usize,
+    },
+    /// Expecting the number of entries in a section ending at `end`.
+    Count {
+        id: u8,
+        end: usize,
+    },
+    /// Expecting entry `index` of `count` in a section ending at `end`.
+    Entry {
+        id: u8,
+        end: usize,
+        index: u32,
+        count: u32,
+    },
+    /// Expecting entry `index` of `count` in a section ending at `end`, which
+    /// is decoded once the bytes up to `entry_end` are available.
+    EntryContent {
+        id: u8,
+        end: usize,
+        index: u32,
+        count: u32,
+        entry_end: usize,
+    },
+    /// Expecting the number of bodies in a code section ending at `end`.
+    CodeCount {
+        end: usize,
+    },
+    /// Expecting the size of the body of function `index`, or the end of the
+    /// code section.
+    Body {
+        end: usize,
+        index: u32,
+    },
+    /// Expecting the content of the body of function `index`.
+    BodyContent {
+        end: usize,
+        index: u32,
+        body_end: usize,
+    },
+}
+
+/// An incremental decoder fed with chunks of a binary module. Sections are
+/// decoded entry by entry and the code section body by body, and only the
+/// bytes of the entry or body being received are buffered, so the decoded
+/// parts can be processed while the rest of the module is still arriving.
+/// Custom, start and data count sections are buffered whole.
+pub struct Parser {
+    buf: Vec<u8>,
+    /// Offset in the module of the first buffered byte.
+    base: usize,
+    /// Offset in the module of the next byte to decode.
+    offset: usize,
+    state: State,
+    section: Option<u8>,
+    /// Position of the last non-custom section in the required order.
+    last: u8,
+    /// Id of the last non-custom section.
+    prev: Option<u8>,
+    code: bool,
+    lazy: bool,
+    module: Module,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            buf: Vec::new(),
+            base: 0,
+            offset: 0,
+            state: State::Header,
+            section: None,
+            last: 0,
+            prev: None,
+            code: false,
+            lazy: false,
+            module: Module::default(),
+        }
+    }
+
+    /// Defers decoding of function bodies, which are kept as [`RawBody`] to be
+    /// decoded with [`decode_body`](super::decode_body) when needed.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Returns the module decoded so far.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Decodes as much as possible of `data` and the bytes buffered before it,
+    /// returning what has been decoded in order. If the binary is malformed,
+    /// the returned error is a [`DecodeError`] and the parser must not be fed
+    /// again.
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<Event>> {
+        self.buf.extend_from_slice(data);
+        let mut events = Vec::new();
+        let result = self.parse(&mut events);
+        self.buf.drain(..self.offset - self.base);
+        self.base = self.offset;
+        result.map(|()| events)
+    }
+
+    /// Checks that the whole module has been fed and returns it.
+    pub fn finish(mut self) -> Result<Module> {
+        let end = self.base + self.buf.len();
+        if !matches!(self.state, State::Section) || self.offset < end {
+            self.offset = end;
+            return Err(self.error(DecodeErrorKind::UnexpectedEof));
+        }
+
+        self.section = None;
+        if !self.code && !self.module.funcs.is_empty() {
+            return Err(self.error(DecodeErrorKind::MalformedSection(
+                "function and code section have inconsistent lengths",
+            )));
+        }
+        if let Some(count) = self.module.data_count {
+            if count as usize != self.module.datas.len() {
+                return Err(self.error(DecodeErrorKind::MalformedSection(
+                    "data count and data section have inconsistent lengths",
+                )));
+            }
+        }
+        Ok(self.module)
+    }
+
+    fn parse(&mut self, events: &mut Vec<Event>) -> Result<()> {
+        let avail = self.base + self.buf.len();
+        loop {
+            match self.state {
+                State::Header => {
+                    let header = self.read(None, |r, _| {
+                        let mut magic = [0u8; 4];
+                        r.read_exact(&mut magic)?;
+                        ensure!(
+                            magic == [0x00, 0x61, 0x73, 0x6d],
+                            DecodeErrorKind::InvalidMagic
+                        );
+                        let mut version = [0u8; 4];
+                        r.read_exact(&mut version)?;
+                        ensure!(
+                            version == [0x01, 0x00, 0x00, 0x00],
+                            DecodeErrorKind::InvalidVersion
+                        );
+                        Ok(())
+                    })?;
+                    if header.is_none() {
+                        return Ok(());
+                    }
+                    self.state = State::Section;
+                }
+                State::Section => {
+                    if self.offset == avail {
+                        return Ok(());
+                    }
+                    self.section = None;
+                    let Some((id, size)) =
+                        self.read(None, |r, _| Ok((r.read_section_id()?, r.read_u32()?)))?
+                    else {
+                        return Ok(());
+                    };
+                    self.section = Some(id);
+
+                    if id != 0 {
+                        let order = section_order(id);
+                        if order <= self.last {
+                            return Err(self.error(DecodeErrorKind::MalformedSection(
+                                "unexpected content after last section",
+                            )));
+                        }
+                        self.last = order;
+                    }
+
+                    let end = self.offset + size as usize;
+                    self.state = match id {
+                        0 | 8 | 12 => State::Content { id, end },
+                        10 => State::CodeCount { end },
+                        _ => State::Count { id, end },
+                    };
+                }
+                State::Content { id, end } => {
+                    if avail < end {
+                        return Ok(());
+                    }
+                    let after = self.prev;
+                    if self
+                        .read(Some(end), |r, module| r.read_section(id, after, module))?
+                        .is_none()
+                    {
+                        return Ok(());
+                    }
+                    if id != 0 {
+                        self.prev = Some(id);
+                    }
+                    events.push(Event::Section(id));
+                    self.state = State::Section;
+                }
+                State::Count { id, end } => {
+                    let Some(count) = self.read(Some(end), |r, _| r.read_u32())? else {
+                        return Ok(());
+                    };
+                    self.state = State::Entry {
+                        id,
+                        end,
+                        index: 0,
+                        count,
+                    };
+                }
+                State::Entry {
+                    id,
+                    end,
+                    index,
+                    count,
+                } if index == count => {
+                    if self.offset != end {
+                        return Err(self.error(DecodeErrorKind::SectionSizeMismatch));
+                    }
+                    self.prev = Some(id);
+                    events.push(Event::Section(id));
+                    self.state = State::Section;
+                }
+                State::Entry {
+                    id,
+                    end,
+                    index,
+                    count,
+                } => {
+                    // a data segment is decoded once all its bytes have been
+                    // fed, rather than again from its start with every chunk
+                    let entry_end = match id {
+                        11 => {
+                            let Some(size) = self.peek(Some(end), |r, _| {
+                                let (_, len) = r.read_data_header()?;
+                                Ok(r.position() as usize + len as usize)
+                            })?
+                            else {
+                                return Ok(());
+                            };
+                            self.offset + size
+                        }
+                        _ => self.offset,
+                    };
+                    self.state = State::EntryContent {
+                        id,
+                        end,
+                        index,
+                        count,
+                        entry_end,
+                    };
+                }
+                State::EntryContent {
+                    id,
+                    end,
+                    index,
+                    count,
+                    entry_end,
+                } => {
+                    if avail < entry_end.min(end) {
+                        return Ok(());
+                    }
+                    if self
+                        .read(Some(end), |r, module| r.read_entry(id, module))?
+                        .is_none()
+                    {
+                        return Ok(());
+                    }
+                    events.push(Event::Entry(id, index));
+                    self.state = State::Entry {
+                        id,
+                        end,
+                        index: index + 1,
+                        count,
+                    };
+                }
+                State::CodeCount { end } => {
+                    let Some(count) = self.read(Some(end), |r, _| r.read_u32())? else {
+                        return Ok(());
+                    };
+                    if count as usize != self.module.funcs.len() {
+                        return Err(self.error(DecodeErrorKind::MalformedSection(
+                            "function and code section have inconsistent lengths",
+                        )));
+                    }
+                    events.push(Event::CodeStart(count));
+                    self.state = State::Body { end, index: 0 };
+                }
+                State::Body { end, index } if index as usize == self.module.funcs.len() => {
+                    if self.offset != end {
+                        return Err(self.error(DecodeErrorKind::SectionSizeMismatch));
+                    }
+                    self.prev = Some(10);
+                    self.code = true;
+                    events.push(Event::CodeEnd);
+                    self.state = State::Section;
+                }
+                State::Body { end, index } => {
+                    let Some(size) = self.read(Some(end), |r, _| r.read_u32())? else {
+                        return Ok(());
+                    };
+                    let body_end = self.offset + size as usize;
+                    self.state = State::BodyContent {
+                        end,
+                        index,
+                        body_end,
+                    };
+                }
+                State::BodyContent {
+                    end,
+                    index,
+                    body_end,
+                } => {
+                    // a body extending past its section is cut off at the end
+                    // of the section
+                    let stop = body_end.min(end);
+                    if avail < stop {
+                        return Ok(());
+                    }
+
+                    if self.lazy {
+                        if body_end > end {
+                            self.offset = end;
+                            return Err(self.error(DecodeErrorKind::UnexpectedEof));
+                        }
+                        let bytes =
+                            self.buf[self.offset - self.base..body_end - self.base].to_vec();
+                        let offset = std::mem::replace(&mut self.offset, body_end);
+                        self.module.funcs[index as usize].raw = Some(RawBody { offset, bytes });
+                    } else {
+                        let start = self.offset;
+                        let Some((locals, body, _)) = self.read(Some(stop), |r, module| {
+                            read_body(r, start, module.data_count.is_some())
+                        })?
+                        else {
+                            return Ok(());
+                        };
+                        if self.offset != body_end {
+                            return Err(self.error(DecodeErrorKind::SectionSizeMismatch));
+                        }
+
+                        let func = &mut self.module.funcs[index as usize];
+                        func.locals = locals;
+                        func.body = body;
+                    }
+                    events.push(Event::Func(index));
+                    self.state = State::Body {
+                        end,
+                        index: index + 1,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Decodes a value with `f` from the buffered bytes before `end`, returning
+    /// `None` if it may be completed by bytes that have not been fed yet.
+    fn read<T>(
+        &mut self,
+        end: Option<usize>,
+        f: impl FnOnce(&mut Cursor<&[u8]>, &mut Module) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let avail = self.base + self.buf.len();
+        let stop = end.map_or(avail, |end| end.min(avail));
+        let mut cursor = Cursor::new(&self.buf[self.offset - self.base..stop - self.base]);
+        let result = f(&mut cursor, &mut self.module);
+        let offset = self.offset + cursor.position() as usize;
+        match result {
+            Ok(value) => {
+                self.offset = offset;
+                Ok(Some(value))
+            }
+            Err(err) if is_eof(&err) && end.is_none_or(|end| avail < end) => Ok(None),
+            Err(err) => Err(DecodeError::locate(err, offset, self.section)),
+        }
+    }
+
+    /// Decodes a value with `f` like [`Parser::read`], but leaves its bytes to
+    /// be decoded again.
+    fn peek<T>(
+        &mut self,
+        end: Option<usize>,
+        f: impl FnOnce(&mut Cursor<&[u8]>, &mut Module) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let offset = self.offset;
+        let value = self.read(end, f)?;
+        self.offset = offset;
+        Ok(value)
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> anyhow::Error {
+        DecodeError::locate(kind.into(), self.offset, self.section)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+/// Reads the locals and body of a function up to the end of `reader`, which
+/// starts at `offset` in the module, along with the offsets of its
+/// instructions as described by [`decode_body`](super::decode_body).
+pub(super) fn read_body(
+    reader: &mut Cursor<&[u8]>,
+    offset: usize,
+    data_count: bool,
+) -> Result<(Locals, Expression, Vec<u32>)> {
+    let locals = reader.read_locals()?;
+    let mut offsets = Vec::new();
+    let at = |r: &Cursor<&[u8]>| (offset + r.position() as usize) as u32;
+    let body = reader.read_expr_with(&mut |r| offsets.push(at(r)))?;
+    offsets.push(at(reader) - 1);
+    ensure!(
+        !reader.has_data_left()?,
+        DecodeErrorKind::SectionSizeMismatch
+    );
+
+    // data indices may only be used if the number of data segments is known
+    // before the data section
+    ensure!(
+        data_count || !uses_data_segments(&body.instructions),
+        DecodeErrorKind::MalformedSection("data count section required")
+    );
+    Ok((locals, body, offsets))
+}
+
+fn is_eof(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<std::io::Error>() {
+        Some(err) => err.kind() == std::io::ErrorKind::UnexpectedEof,
+        None => err.downcast_ref() == Some(&DecodeErrorKind::UnexpectedEof),
+    }
+}
+
+/// Returns the position of a non-custom section in the order required by the
+/// binary format, in which the data count section precedes the code section.
+fn section_order(id: u8) -> u8 {
+    match id {
+        12 => 10,
+        10 | 11 => id + 1,
+        _ => id,
+    }
+}
+
+fn uses_data_segments(instrs: &[Instruction]) -> bool {
+    instrs.iter().any(|instr| match instr {
+        Instruction::MemoryInit(_) | Instruction::DataDrop(_) => true,
+        Instruction::Block { instructions, .. } | Instruction::Loop { instructions, .. } => {
+            uses_data_segments(instructions)
+        }
+        Instruction::If {
+            instructions,
+            else_instructions,
+            ..
+        } => uses_data_segments(instructions) || uses_data_segments(else_instructions),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wat2wasm(wat: &str) -> Vec<u8> {
+        let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        wat.encode().unwrap()
+    }
+
+    #[test]
+    fn test_feed() {
+        let bin = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func $f (result i32) (i32.const 1))
+              (func (export "g") (result i32) (i32.add (call $f) (call $f)))
+              (data (i32.const 0) "abc"))
+            "#,
+        );
+
+        for chunk in [1, 3, bin.len()] {
+            let mut parser = Parser::new();
+            let mut events = Vec::new();
+            let mut max = 0;
+            for data in bin.chunks(chunk) {
+                events.extend(parser.feed(data).unwrap());
+                max = max.max(parser.buf.len());
+            }
+            assert_eq!(
+                events,
+                vec![
+                    Event::Entry(1, 0),
+                    Event::Section(1),
+                    Event::Entry(3, 0),
+                    Event::Entry(3, 1),
+                    Event::Section(3),
+                    Event::Entry(5, 0),
+                    Event::Section(5),
+                    Event::Entry(7, 0),
+                    Event::Section(7),
+                    Event::CodeStart(2),
+                    Event::Func(0),
+                    Event::Func(1),
+                    Event::CodeEnd,
+                    Event::Entry(11, 0),
+                    Event::Section(11),
+                    Event::Section(0),
+                ]
+            );
+            // only the part of the current section or body is buffered
+            if chunk == 1 {
+                assert!(max < 16, "buffered {} bytes", max);
+            }
+
+            let module = parser.finish().unwrap();
+            assert_eq!(module.funcs.len(), 2);
+            assert_eq!(module.funcs[1].body.instructions.len(), 3);
+            assert_eq!(module.datas[0].init, b"abc");
+        }
+    }
+
+    #[test]
+    fn test_split_section() {
+        let bin = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 0) "first segment")
+              (data (i32.const 16) "second segment"))
+            "#,
+        );
+        let split = bin.windows(6).position(|w| w == b"second").unwrap();
+
+        // the first segment is decoded before the rest of its section arrives
+        let mut parser = Parser::new();
+        let events = parser.feed(&bin[..split]).unwrap();
+        assert_eq!(events.last(), Some(&Event::Entry(11, 0)));
+        assert!(!events.contains(&Event::Section(11)));
+        assert_eq!(parser.module().datas.len(), 1);
+        assert_eq!(parser.module().datas[0].init, b"first segment");
+        assert!(parser.buf.len() < 16, "buffered {} bytes", parser.buf.len());
+
+        let events = parser.feed(&bin[split..]).unwrap();
+        assert_eq!(events[..2], [Event::Entry(11, 1), Event::Section(11)]);
+        let module = parser.finish().unwrap();
+        assert_eq!(module.datas[1].init, b"second segment");
+    }
+
+    #[test]
+    fn test_large_segment() {
+        let bin = wat2wasm(r#"(module (memory 64) (data (i32.const 0) "") (data ""))"#);
+        let mut parser = Parser::new();
+        parser.feed(&bin).unwrap();
+        let mut module = parser.finish().unwrap();
+        let init: Vec<u8> = (0..4 << 20).map(|i| i as u8).collect();
+        module.datas[0].init = init.clone();
+        let bin = crate::encode::encode(&module).unwrap();
+
+        // the segment is decoded once it is complete rather than with every
+        // chunk, which would take quadratic time
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        for data in bin.chunks(4096) {
+            events.extend(parser.feed(data).unwrap());
+        }
+        assert_eq!(
+            events[events.len() - 3..],
+            [Event::Entry(11, 0), Event::Entry(11, 1), Event::Section(11),]
+        );
+        let module = parser.finish().unwrap();
+        assert_eq!(module.datas[0].init, init);
+        assert!(module.datas[1].init.is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bin = wat2wasm(r#"(module (func (export "f")))"#);
+        let mut parser = Parser::new();
+        let events = parser.feed(&bin[..bin.len() - 1]).unwrap();
+        assert_eq!(events.last(), Some(&Event::CodeStart(1)));
+
+        let err = parser.finish().err().unwrap();
+        let err = err.downcast_ref::<DecodeError>().unwrap();
+        assert_eq!(err.kind(), &DecodeErrorKind::UnexpectedEof);
+        assert_eq!(err.offset(), bin.len() - 1);
+        assert_eq!(err.section(), Some(10));
+    }
+}
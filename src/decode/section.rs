@@ -1,325 +1,334 @@
 use super::prelude::*;
+use super::DecodeErrorKind;
 use crate::core::{
-    Element, ElementMode, Export, ExportDesc, Expression, Func, FuncIdx, FuncType, Global, Idx,
-    Import, ImportDesc, Memory, Module, RefType, Table, TypeIdx,
+    Custom, Data, DataMode, Element, ElementMode, Export, ExportDesc, Expression, Func, FuncIdx,
+    Global, Idx, Import, ImportDesc, Instruction, Locals, Memory, Module, Name, Names, RefType,
+    Table,
 };
-use anyhow::{bail, ensure, Context as _, Result};
-use std::io::{BufRead, Cursor, Seek};
+use anyhow::{bail, ensure, Result};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Cursor, Read};
 
 pub trait ReadSectionExt: BufRead {
-    fn read_section(&mut self, module: &mut Module) -> Result<()> {
-        let idx = self
-            .read_unsigned_leb128(8)
-            .context("failed to read section index")?;
-        let size = self.read_u32().context("failed to read section size")?;
-
-        ensure!(idx <= 12, "invalid section id: {}", idx);
-
-        let mut cont = vec![0u8; size as usize];
-        self.read_exact(cont.as_mut_slice())
-            .context("failed to read section content")?;
-        let mut cursor = Cursor::new(cont);
+    fn read_section_id(&mut self) -> Result<u8> {
+        let id = self.read_byte()?;
+        ensure!(id <= 12, DecodeErrorKind::InvalidSectionId(id));
+        Ok(id)
+    }
 
-        match idx {
+    /// Reads the content of a custom, start or data count section `id` up to
+    /// the end of the reader, where `after` is the id of the last non-custom
+    /// section read before it. Other sections are read entry by entry with
+    /// `read_entry`, and bodies of the code section with `read_body`.
+    fn read_section(&mut self, id: u8, after: Option<u8>, module: &mut Module) -> Result<()> {
+        match id {
             0 => {
-                cursor.seek(std::io::SeekFrom::End(0))?; // skip custom section
-            }
-            1 => {
-                module.types = cursor.read_type_section()?;
-            }
-            2 => {
-                module.imports = cursor.read_import_section()?;
-            }
-            3 => {
-                module.funcs = cursor
-                    .read_function_section()?
-                    .into_iter()
-                    .map(|id| Func {
-                        type_id: id,
-                        locals: vec![],
-                        body: Expression::default(),
-                    })
-                    .collect();
-            }
-            4 => {
-                module.tables = cursor.read_table_section()?;
-            }
-            5 => {
-                module.memories = cursor.read_memory_section()?;
-            }
-            6 => {
-                module.globals = cursor.read_global_section()?;
-            }
-            7 => {
-                module.exports = cursor.read_export_section()?;
+                let name = self.read_name()?;
+                let mut data = Vec::new();
+                self.read_to_end(&mut data)?;
+
+                // a malformed name section does not invalidate the module
+                if name.as_str() == "name" {
+                    if let Ok(names) = Cursor::new(&data).read_name_section() {
+                        module.names = names;
+                    }
+                }
+                module.customs.push(Custom { name, data, after });
             }
             8 => {
-                module.start = Some(cursor.read_start_section()?);
+                module.start = Some(self.read_start_section()?);
             }
-            9 => {
-                module.elements = cursor.read_element_section()?;
+            12 => {
+                module.data_count = Some(self.read_data_count_section()?);
             }
-            10 => cursor.read_code_section(module)?,
-            11 => cursor.read_data_section()?,
-            12 => cursor.read_data_count_section()?,
-            _ => bail!("invalid section id: {}", idx),
+            _ => unreachable!("section {} is read entry by entry", id),
         };
 
-        ensure!(!cursor.has_data_left()?, "invalid section size");
+        ensure!(!self.has_data_left()?, DecodeErrorKind::SectionSizeMismatch);
         Ok(())
     }
 
-    fn read_type_section(&mut self) -> Result<Vec<FuncType>> {
-        Ok(read_vec!(self, self.read_func_type()?))
+    /// Reads an entry of section `id`, which must be a vector of entries, and
+    /// appends it to `module`.
+    fn read_entry(&mut self, id: u8, module: &mut Module) -> Result<()> {
+        match id {
+            1 => module.types.push(self.read_func_type()?),
+            2 => module.imports.push(self.read_import()?),
+            3 => module.funcs.push(Func {
+                type_id: self.read_u32()?.into(),
+                locals: vec![],
+                body: Expression::default(),
+                raw: None,
+            }),
+            4 => module.tables.push(Table(self.read_table_type()?)),
+            5 => module.memories.push(Memory(self.read_limits()?)),
+            6 => module.globals.push(self.read_global()?),
+            7 => module.exports.push(self.read_export()?),
+            9 => module.elements.push(self.read_element()?),
+            11 => module.datas.push(self.read_data()?),
+            _ => unreachable!("section {} is not a vector of entries", id),
+        }
+        Ok(())
     }
 
-    fn read_import_section(&mut self) -> Result<Vec<Import>> {
-        let vec = read_vec!(self, {
-            let module = self.read_name()?; // module
-            let name = self.read_name()?; // name
+    fn read_name_section(&mut self) -> Result<Names> {
+        let mut names = Names::default();
+        while self.has_data_left()? {
+            let id = self.read_byte()?;
+            let size = self.read_u32()?;
+            let mut cursor = Read::take(&mut *self, size as u64);
 
-            let desc_type = self.read_byte().context("failed to read import desc")?;
-            let desc = match desc_type {
-                0x00 => {
-                    let func = self.read_u32().context("failed to read type id")?;
-                    ImportDesc::Func(func.into())
-                }
-                0x01 => {
-                    let table = self.read_table_type()?;
-                    ImportDesc::Table(table)
-                }
-                0x02 => {
-                    let limits = self.read_limits()?;
-                    ImportDesc::Memory(limits)
+            match id {
+                0 => names.module = Some(cursor.read_name()?),
+                1 => names.funcs = cursor.read_name_map()?,
+                2 => {
+                    let locals = read_vec!(cursor, (cursor.read_u32()?, cursor.read_name_map()?));
+                    names.locals = locals.into_iter().collect();
                 }
-                0x03 => {
-                    let global_type = self.read_global_type()?;
-                    ImportDesc::Global(global_type)
+                _ => {
+                    std::io::copy(&mut cursor, &mut std::io::sink())?; // skip other names
                 }
-                _ => bail!("invalid import desc: {}", desc_type),
-            };
-
-            Import { module, name, desc }
-        });
+            }
 
-        Ok(vec)
+            ensure!(cursor.limit() == 0, DecodeErrorKind::SectionSizeMismatch);
+        }
+        Ok(names)
     }
 
-    fn read_function_section(&mut self) -> Result<Vec<Idx<TypeIdx>>> {
-        let vec = read_vec!(self, self.read_u32()?.into());
-        Ok(vec)
+    fn read_name_map(&mut self) -> Result<BTreeMap<u32, Name>> {
+        let vec = read_vec!(self, (self.read_u32()?, self.read_name()?));
+        Ok(vec.into_iter().collect())
     }
 
-    fn read_table_section(&mut self) -> Result<Vec<Table>> {
-        let table_type = read_vec!(self, Table(self.read_table_type()?));
-        Ok(table_type)
-    }
+    fn read_import(&mut self) -> Result<Import> {
+        let module = self.read_name()?; // module
+        let name = self.read_name()?; // name
+
+        let desc_type = self.read_byte()?;
+        let desc = match desc_type {
+            0x00 => {
+                let func = self.read_u32()?;
+                ImportDesc::Func(func.into())
+            }
+            0x01 => {
+                let table = self.read_table_type()?;
+                ImportDesc::Table(table)
+            }
+            0x02 => {
+                let limits = self.read_limits()?;
+                ImportDesc::Memory(limits)
+            }
+            0x03 => {
+                let global_type = self.read_global_type()?;
+                ImportDesc::Global(global_type)
+            }
+            _ => bail!(DecodeErrorKind::MalformedSection("malformed import kind")),
+        };
 
-    fn read_memory_section(&mut self) -> Result<Vec<Memory>> {
-        let memories = read_vec!(self, Memory(self.read_limits()?));
-        Ok(memories)
+        Ok(Import { module, name, desc })
     }
 
-    fn read_global_section(&mut self) -> Result<Vec<Global>> {
-        let vec = read_vec!(self, {
-            Global {
-                global_type: self.read_global_type()?,
-                init: self.read_expr()?,
-            }
-        });
-        Ok(vec)
+    fn read_global(&mut self) -> Result<Global> {
+        Ok(Global {
+            global_type: self.read_global_type()?,
+            init: self.read_expr()?,
+        })
     }
 
-    fn read_export_section(&mut self) -> Result<Vec<Export>> {
-        let vec = read_vec!(self, {
-            let name = self.read_name()?;
-            let ty = self.read_byte().context("failed to read export type")?;
-            let id = self.read_u32().context("failed to read export id")?;
-
-            let desc = match ty {
-                0x00 => ExportDesc::Func(id.into()),
-                0x01 => ExportDesc::Table(id.into()),
-                0x02 => ExportDesc::Memory(id.into()),
-                0x03 => ExportDesc::Global(id.into()),
-                _ => bail!("invalid export type: {}", ty),
-            };
-
-            Export { name, desc }
-        });
-        Ok(vec)
+    fn read_export(&mut self) -> Result<Export> {
+        let name = self.read_name()?;
+        let ty = self.read_byte()?;
+        let id = self.read_u32()?;
+
+        let desc = match ty {
+            0x00 => ExportDesc::Func(id.into()),
+            0x01 => ExportDesc::Table(id.into()),
+            0x02 => ExportDesc::Memory(id.into()),
+            0x03 => ExportDesc::Global(id.into()),
+            _ => bail!(DecodeErrorKind::MalformedSection("malformed export kind")),
+        };
+
+        Ok(Export { name, desc })
     }
 
     fn read_start_section(&mut self) -> Result<Idx<FuncIdx>> {
-        let func_id = self
-            .read_u32()
-            .context("failed to read start section func id")?;
+        let func_id = self.read_u32()?;
 
         Ok(func_id.into())
     }
 
-    fn read_element_section(&mut self) -> Result<Vec<Element>> {
-        let elements = read_vec!(self, {
-            let ty = self.read_u32()?;
-
-            match ty {
-                0 => {
-                    let offset = self.read_expr()?;
-                    let _y = read_vec!(self, self.read_u32()?);
-
-                    Element {
-                        ty: RefType::Funcref,
-                        init: Vec::new(),
-                        mode: ElementMode::Active {
-                            table: 0.into(),
-                            offset,
-                        },
-                    }
+    fn read_element(&mut self) -> Result<Element> {
+        let ty = self.read_u32()?;
+
+        let element = match ty {
+            0 => {
+                let offset = self.read_expr()?;
+                let init = self.read_elem_func_indices()?;
+
+                Element {
+                    ty: RefType::Funcref,
+                    init,
+                    mode: ElementMode::Active {
+                        table: 0.into(),
+                        offset,
+                    },
                 }
-                1 => {
-                    self.read_and_ensure(0x00)?;
-                    let _y = read_vec!(self, self.read_u32()?);
-
-                    Element {
-                        ty: RefType::Funcref,
-                        init: Vec::new(),
-                        mode: ElementMode::Passive,
-                    }
+            }
+            1 => {
+                self.read_and_ensure(0x00, "malformed element kind")?;
+                let init = self.read_elem_func_indices()?;
+
+                Element {
+                    ty: RefType::Funcref,
+                    init,
+                    mode: ElementMode::Passive,
                 }
-                2 => {
-                    let table = self.read_u32()?.into();
-                    let offset = self.read_expr()?;
-                    self.read_and_ensure(0x00)?;
-                    let _y = read_vec!(self, self.read_u32()?);
-
-                    Element {
-                        ty: RefType::Funcref,
-                        init: Vec::new(),
-                        mode: ElementMode::Active { table, offset },
-                    }
+            }
+            2 => {
+                let table = self.read_u32()?.into();
+                let offset = self.read_expr()?;
+                self.read_and_ensure(0x00, "malformed element kind")?;
+                let init = self.read_elem_func_indices()?;
+
+                Element {
+                    ty: RefType::Funcref,
+                    init,
+                    mode: ElementMode::Active { table, offset },
                 }
-                3 => {
-                    self.read_and_ensure(0x00)?;
-                    let _y = read_vec!(self, self.read_u32()?);
-
-                    Element {
-                        ty: RefType::Funcref,
-                        init: Vec::new(),
-                        mode: ElementMode::Declarative,
-                    }
+            }
+            3 => {
+                self.read_and_ensure(0x00, "malformed element kind")?;
+                let init = self.read_elem_func_indices()?;
+
+                Element {
+                    ty: RefType::Funcref,
+                    init,
+                    mode: ElementMode::Declarative,
                 }
-                4 => {
-                    let offset = self.read_expr()?;
-                    let _y = read_vec!(self, self.read_expr()?);
-
-                    Element {
-                        ty: RefType::Funcref,
-                        init: Vec::new(),
-                        mode: ElementMode::Active {
-                            table: 0.into(),
-                            offset,
-                        },
-                    }
+            }
+            4 => {
+                let offset = self.read_expr()?;
+                let init = read_vec!(self, self.read_expr()?);
+
+                Element {
+                    ty: RefType::Funcref,
+                    init,
+                    mode: ElementMode::Active {
+                        table: 0.into(),
+                        offset,
+                    },
                 }
-                5 => {
-                    let ty = self.read_byte()?.try_into()?;
-                    let _y = read_vec!(self, self.read_expr()?);
-
-                    Element {
-                        ty,
-                        init: Vec::new(),
-                        mode: ElementMode::Passive,
-                    }
+            }
+            5 => {
+                let ty = self.read_ref_type()?;
+                let init = read_vec!(self, self.read_expr()?);
+
+                Element {
+                    ty,
+                    init,
+                    mode: ElementMode::Passive,
                 }
-                6 => {
-                    let table = self.read_u32()?.into();
-                    let offset = self.read_expr()?;
-                    let ty = self.read_byte()?.try_into()?;
-                    let _y = read_vec!(self, self.read_expr()?);
-
-                    Element {
-                        ty,
-                        init: Vec::new(),
-                        mode: ElementMode::Active { table, offset },
-                    }
+            }
+            6 => {
+                let table = self.read_u32()?.into();
+                let offset = self.read_expr()?;
+                let ty = self.read_ref_type()?;
+                let init = read_vec!(self, self.read_expr()?);
+
+                Element {
+                    ty,
+                    init,
+                    mode: ElementMode::Active { table, offset },
                 }
-                7 => {
-                    let ty = self.read_byte()?.try_into()?;
-                    let _y = read_vec!(self, self.read_expr()?);
-
-                    Element {
-                        ty,
-                        init: Vec::new(),
-                        mode: ElementMode::Declarative,
-                    }
+            }
+            7 => {
+                let ty = self.read_ref_type()?;
+                let init = read_vec!(self, self.read_expr()?);
+
+                Element {
+                    ty,
+                    init,
+                    mode: ElementMode::Declarative,
                 }
-                _ => bail!("invalid element section type: {}", ty),
             }
-        });
+            _ => bail!(DecodeErrorKind::MalformedSection(
+                "malformed elements segment kind"
+            )),
+        };
 
-        Ok(elements)
+        Ok(element)
     }
 
-    fn read_code_section(&mut self, module: &mut Module) -> Result<()> {
-        let vec = read_vec!(self, {
-            // TODO: check size
-            self.read_u32()
-                .context("failed to read code section body size")?;
-
-            let types = read_vec!(self, {
-                let n = self.read_u32()?;
-                let ty = self.read_value_type()?;
-                (n, ty)
-            })
-            .into_iter()
-            .flat_map(|(n, ty)| std::iter::repeat(ty).take(n as usize))
-            .collect();
-
-            let expr = self.read_expr()?;
-            (types, expr)
+    fn read_elem_func_indices(&mut self) -> Result<Vec<Expression>> {
+        let init = read_vec!(self, {
+            let idx = self.read_u32()?;
+            Expression {
+                instructions: vec![Instruction::RefFunc(idx.into())],
+            }
         });
 
+        Ok(init)
+    }
+
+    /// Reads the local declarations of a function body. They are kept as runs
+    /// of locals, so declaring many locals costs no memory.
+    fn read_locals(&mut self) -> Result<Locals> {
+        let groups = read_vec!(self, (self.read_u32()?, self.read_value_type()?));
+
+        let total = groups
+            .iter()
+            .try_fold(0u64, |total, (n, _)| total.checked_add(*n as u64));
         ensure!(
-            vec.len() == module.funcs.len(),
-            "code section size mismatch"
+            total.is_some_and(|total| total <= u32::MAX as u64),
+            DecodeErrorKind::MalformedSection("too many locals")
         );
+        Ok(groups)
+    }
 
-        for (i, (locals, body)) in vec.into_iter().enumerate() {
-            module.funcs[i].locals = locals;
-            module.funcs[i].body = body;
-        }
+    fn read_data(&mut self) -> Result<Data> {
+        let (mode, len) = self.read_data_header()?;
+        // the length is untrusted, so the bytes are read in one go up to the
+        // end of the reader, which ends with the section, before checking it
+        let mut init = Vec::new();
+        Read::take(&mut *self, len as u64).read_to_end(&mut init)?;
+        ensure!(init.len() == len as usize, DecodeErrorKind::UnexpectedEof);
 
-        Ok(())
+        Ok(Data { init, mode })
     }
 
-    fn read_data_section(&mut self) -> Result<()> {
-        read_vec!(self, {
-            let ty = self.read_u32()?;
+    /// Reads a data segment up to its bytes, returning its mode and the number
+    /// of bytes that follow.
+    fn read_data_header(&mut self) -> Result<(DataMode, u32)> {
+        let ty = self.read_u32()?;
 
-            match ty {
-                0 => {
-                    self.read_expr()?;
-                    read_vec!(self, self.read_byte()?);
-                }
-                1 => {
-                    read_vec!(self, self.read_byte()?);
-                }
-                2 => {
-                    self.read_u32()?;
-                    self.read_expr()?;
-                    read_vec!(self, self.read_byte()?);
+        let mode = match ty {
+            0 => {
+                let offset = self.read_expr()?;
+
+                DataMode::Active {
+                    memory: 0.into(),
+                    offset,
                 }
-                _ => bail!("invalid data section type: {}", ty),
             }
-        });
+            1 => DataMode::Passive,
+            2 => {
+                let memory = self.read_u32()?.into();
+                let offset = self.read_expr()?;
 
-        Ok(())
+                DataMode::Active { memory, offset }
+            }
+            _ => bail!(DecodeErrorKind::MalformedSection(
+                "malformed data segment kind"
+            )),
+        };
+
+        Ok((mode, self.read_u32()?))
     }
 
-    fn read_data_count_section(&mut self) -> Result<()> {
-        self.read_u32()
-            .context("failed to read data count section size")?;
+    fn read_data_count_section(&mut self) -> Result<u32> {
+        let count = self.read_u32()?;
 
-        Ok(())
+        Ok(count)
     }
 }
 
@@ -1,13 +1,14 @@
 use super::prelude::*;
+use super::DecodeErrorKind;
 use crate::core::{
     FuncType, GlobalType, Limits, NumType, RefType, ResultType, TableType, ValueType, VecType,
 };
-use anyhow::{bail, ensure, Context as _, Result};
+use anyhow::{bail, ensure, Result};
 use std::io::BufRead;
 
 pub trait ReadTypeExt: BufRead {
     fn read_value_type(&mut self) -> Result<ValueType> {
-        let ty = self.read_byte().context("failed to read value type")?;
+        let ty = self.read_byte()?;
 
         let res = match ty {
             0x7f => ValueType::Num(NumType::I32),       // i32
@@ -17,25 +18,30 @@ pub trait ReadTypeExt: BufRead {
             0x7b => ValueType::Vec(VecType::V128),      // v128
             0x70 => ValueType::Ref(RefType::Funcref),   // funcref
             0x6f => ValueType::Ref(RefType::Externref), // externref
-            _ => bail!("invalid value type: {}", ty),
+            _ => bail!(DecodeErrorKind::MalformedSection("malformed value type")),
         };
 
         Ok(res)
     }
 
+    fn read_ref_type(&mut self) -> Result<RefType> {
+        match self.read_byte()? {
+            0x70 => Ok(RefType::Funcref),
+            0x6f => Ok(RefType::Externref),
+            _ => bail!(DecodeErrorKind::MalformedSection(
+                "malformed reference type"
+            )),
+        }
+    }
+
     fn read_result_type(&mut self) -> Result<ResultType> {
-        let vec = read_vec!(
-            self,
-            self.read_value_type()
-                .context("failed to read result type")?
-        );
+        let vec = read_vec!(self, self.read_value_type()?);
 
         Ok(vec)
     }
 
     fn read_func_type(&mut self) -> Result<FuncType> {
-        self.read_and_ensure(0x60)
-            .context("failed to read func type")?;
+        self.read_and_ensure(0x60, "malformed function type")?;
 
         Ok(FuncType {
             params: self.read_result_type()?,
@@ -44,31 +50,28 @@ pub trait ReadTypeExt: BufRead {
     }
 
     fn read_limits(&mut self) -> Result<Limits> {
-        let flag = self.read_byte().context("failed to read limits flag")?;
+        let flag = self.read_byte()?;
         let limits = match flag {
             0x00 => {
-                let min = self.read_u32().context("failed to read limits min")?;
+                let min = self.read_u32()?;
                 Limits { min, max: None }
             }
             0x01 => {
-                let min = self.read_u32().context("failed to read limits min")?;
-                let max = self.read_u32().context("failed to read limits max")?;
+                let min = self.read_u32()?;
+                let max = self.read_u32()?;
                 Limits {
                     min,
                     max: Some(max),
                 }
             }
-            _ => bail!("invalid limits flag: {}", flag),
+            _ => bail!(DecodeErrorKind::MalformedSection("malformed limits flags")),
         };
 
         Ok(limits)
     }
 
     fn read_table_type(&mut self) -> Result<TableType> {
-        let reftype = self
-            .read_byte()
-            .context("failed to read reftype")?
-            .try_into()?;
+        let reftype = self.read_ref_type()?;
 
         let limits = self.read_limits()?;
         Ok(TableType {
@@ -78,13 +81,13 @@ pub trait ReadTypeExt: BufRead {
     }
 
     fn read_global_type(&mut self) -> Result<GlobalType> {
-        let value_type = self
-            .read_byte()
-            .context("failed to read valtype")?
-            .try_into()?;
+        let value_type = self.read_value_type()?;
 
-        let mutability = self.read_byte().context("failed to read mutability")?;
-        ensure!(mutability <= 1, "invalid mutability: {}", mutability);
+        let mutability = self.read_byte()?;
+        ensure!(
+            mutability <= 1,
+            DecodeErrorKind::MalformedSection("malformed mutability")
+        );
 
         Ok(GlobalType {
             value_type,
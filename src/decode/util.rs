@@ -1,4 +1,5 @@
-use anyhow::{anyhow, Result};
+use super::DecodeErrorKind;
+use anyhow::{bail, Result};
 use std::io::BufRead;
 
 pub trait ReadUtilExt: BufRead {
@@ -8,14 +9,15 @@ pub trait ReadUtilExt: BufRead {
         Ok(a[0])
     }
 
-    fn read_if_equal(&mut self, b: u8) -> Result<bool> {
-        let top = self
-            .fill_buf()?
-            .first()
-            .copied()
-            .ok_or_else(|| anyhow!("unexpected EOF"))?;
+    fn peek_byte(&mut self) -> Result<u8> {
+        match self.fill_buf()?.first() {
+            Some(b) => Ok(*b),
+            None => bail!(DecodeErrorKind::UnexpectedEof),
+        }
+    }
 
-        if top == b {
+    fn read_if_equal(&mut self, b: u8) -> Result<bool> {
+        if self.peek_byte()? == b {
             self.consume(1);
             Ok(true)
         } else {
@@ -23,19 +25,12 @@ pub trait ReadUtilExt: BufRead {
         }
     }
 
-    fn read_and_ensure(&mut self, b: u8) -> Result<u8> {
-        let top = self
-            .fill_buf()?
-            .first()
-            .copied()
-            .ok_or_else(|| anyhow!("unexpected EOF"))?;
-
-        if top == b {
-            self.consume(1);
-            Ok(top)
-        } else {
-            Err(anyhow!("expected {}, got {}", b, top))
+    /// Reads a byte that must be `b`, failing with `message` otherwise.
+    fn read_and_ensure(&mut self, b: u8, message: &'static str) -> Result<()> {
+        if self.read_byte()? != b {
+            bail!(DecodeErrorKind::MalformedSection(message));
         }
+        Ok(())
     }
 }
 
@@ -44,8 +39,9 @@ impl<R: BufRead + ?Sized> ReadUtilExt for R {}
 #[macro_export]
 macro_rules! read_vec {
     ($r: expr, $x: expr) => {{
-        let size = $r.read_u32().context("failed to read vec size")?;
-        let mut vec = Vec::with_capacity(size as usize);
+        let size = $r.read_u32()?;
+        // the size is untrusted, so only a bounded capacity is reserved
+        let mut vec = Vec::with_capacity(size.min(1024) as usize);
         for _ in 0..size {
             vec.push($x);
         }
@@ -1,7 +1,8 @@
 use super::prelude::*;
+use super::DecodeErrorKind;
 use crate::core::Name;
-use anyhow::{bail, Context as _, Result};
-use std::io::BufRead;
+use anyhow::{bail, ensure, Result};
+use std::io::{BufRead, Read};
 
 pub trait ReadValueExt: BufRead {
     fn read_unsigned_leb128(&mut self, n: u64) -> Result<u64> {
@@ -11,8 +12,10 @@ pub trait ReadValueExt: BufRead {
         } else if a >= 128 && n > 7 {
             let b = self.read_unsigned_leb128(n - 7)?;
             Ok(128 * b + (a as u64 - 128))
+        } else if a >= 128 {
+            bail!(DecodeErrorKind::InvalidLeb128)
         } else {
-            bail!("invalid unsigned leb128")
+            bail!(DecodeErrorKind::IntegerTooLarge)
         }
     }
 
@@ -25,24 +28,25 @@ pub trait ReadValueExt: BufRead {
         } else if a >= 128 && n > 7 {
             let b = self.read_signed_leb128(n - 7)?;
             Ok(128 * b + (a as i64 - 128))
+        } else if a >= 128 {
+            bail!(DecodeErrorKind::InvalidLeb128)
         } else {
-            bail!("invalid signed leb128")
+            bail!(DecodeErrorKind::IntegerTooLarge)
         }
     }
 
     fn read_u32(&mut self) -> Result<u32> {
-        let val = self
-            .read_unsigned_leb128(32)
-            .context("failed to read u32")?;
+        let val = self.read_unsigned_leb128(32)?;
         Ok(val as u32)
     }
 
     fn read_name(&mut self) -> Result<Name> {
-        let size = self.read_u32().context("failed to read name size")?;
-        let mut cont = vec![0u8; size as usize];
-        self.read_exact(cont.as_mut_slice())
-            .context("failed to read name content")?;
-        Ok(Name::new(String::from_utf8(cont)?))
+        let size = self.read_u32()?;
+        let mut cont = Vec::new();
+        Read::take(&mut *self, size as u64).read_to_end(&mut cont)?;
+        ensure!(cont.len() == size as usize, DecodeErrorKind::UnexpectedEof);
+        let name = String::from_utf8(cont).map_err(|_| DecodeErrorKind::InvalidUtf8)?;
+        Ok(Name::new(name))
     }
 }
 
@@ -0,0 +1,514 @@
+use crate::core::{
+    BlockType, FBinOp, FRelOp, FUnOp, Func, IBinOp, IRelOp, IUnOp, ImportDesc, Instruction, MemArg,
+    Module, NumType, RefType, ValueType, VecType,
+};
+use crate::decode::decode_body;
+use anyhow::Result;
+use std::fmt::{self, Write};
+use std::slice;
+
+/// Width of the offsets printed before instructions, as in ` 00002d: `.
+const GUTTER: usize = 9;
+
+/// Disassembles the function bodies of a module in the format of
+/// `wasm-objdump -d`, without the encoded bytes. Functions and locals are
+/// named after [`Module::names`]. Deferred bodies are decoded, which fails if
+/// they are malformed, and their instructions are preceded by their byte offset
+/// in the module, as reported by traps.
+pub fn disassemble(module: &Module) -> Result<String> {
+    let mut out = String::new();
+    if let Some(name) = &module.names.module {
+        writeln!(out, "module <{}>:", name.as_str())?;
+    }
+
+    let imported = module
+        .imports
+        .iter()
+        .filter(|import| matches!(import.desc, ImportDesc::Func(_)))
+        .count();
+    for (i, func) in module.funcs.iter().enumerate() {
+        let decoded;
+        let (func, offsets) = match &func.raw {
+            Some(raw) => {
+                let (locals, body, offsets) = decode_body(raw, module.data_count.is_some())?;
+                let func = Func {
+                    type_id: func.type_id,
+                    locals,
+                    body,
+                    raw: None,
+                };
+                decoded = (func, offsets);
+                (&decoded.0, decoded.1.as_slice())
+            }
+            None => (func, &[][..]),
+        };
+
+        let mut printer = Printer {
+            module,
+            idx: (imported + i) as u32,
+            offsets: offsets.iter(),
+            out: &mut out,
+        };
+        printer.func(func)?;
+    }
+    Ok(out)
+}
+
+struct Printer<'a> {
+    module: &'a Module,
+    /// Index of the function in the function index space.
+    idx: u32,
+    /// Offsets of the instructions left to print, in the order in which they
+    /// appear in the binary.
+    offsets: slice::Iter<'a, u32>,
+    out: &'a mut String,
+}
+
+impl Printer<'_> {
+    fn func(&mut self, func: &Func) -> fmt::Result {
+        write!(self.out, "func[{}]", self.idx)?;
+        if let Some(name) = self.module.names.funcs.get(&self.idx) {
+            write!(self.out, " <{}>", name.as_str())?;
+        }
+        writeln!(self.out, ":")?;
+
+        let params = match self.module.types.get(func.type_id.get() as usize) {
+            Some(ty) => ty.params.len() as u64,
+            None => 0,
+        };
+        let mut first = params;
+        for (n, ty) in &func.locals {
+            let last = first + *n as u64;
+            match n {
+                0 => {}
+                1 => self.line(
+                    None,
+                    0,
+                    &format!("local[{}] type={}", first, value_type(*ty)),
+                )?,
+                _ => self.line(
+                    None,
+                    0,
+                    &format!("local[{}..{}] type={}", first, last - 1, value_type(*ty)),
+                )?,
+            }
+            first = last;
+        }
+
+        self.instrs(&func.body.instructions, 0)?;
+        let offset = self.offsets.next().copied();
+        self.line(offset, 0, "end")
+    }
+
+    fn instrs(&mut self, instrs: &[Instruction], depth: usize) -> fmt::Result {
+        for instr in instrs {
+            let offset = self.offsets.next().copied();
+            match instr {
+                Instruction::Block {
+                    block_type,
+                    instructions,
+                } => {
+                    self.line(offset, depth, &format!("block{}", block(block_type)))?;
+                    self.instrs(instructions, depth + 1)?;
+                    self.line(None, depth, "end")?;
+                }
+                Instruction::Loop {
+                    block_type,
+                    instructions,
+                } => {
+                    self.line(offset, depth, &format!("loop{}", block(block_type)))?;
+                    self.instrs(instructions, depth + 1)?;
+                    self.line(None, depth, "end")?;
+                }
+                Instruction::If {
+                    block_type,
+                    instructions,
+                    else_instructions,
+                } => {
+                    self.line(offset, depth, &format!("if{}", block(block_type)))?;
+                    self.instrs(instructions, depth + 1)?;
+                    if !else_instructions.is_empty() {
+                        self.line(None, depth, "else")?;
+                        self.instrs(else_instructions, depth + 1)?;
+                    }
+                    self.line(None, depth, "end")?;
+                }
+                _ => {
+                    let text = self.instr(instr);
+                    self.line(offset, depth, &text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a line for an instruction at `offset` nested in `depth` blocks.
+    /// Nested `else` and `end` have no offset of their own.
+    fn line(&mut self, offset: Option<u32>, depth: usize, text: &str) -> fmt::Result {
+        match offset {
+            Some(offset) => write!(self.out, " {:06x}: ", offset)?,
+            None => write!(self.out, "{:1$}", "", GUTTER)?,
+        }
+        writeln!(self.out, "{:2$}{}", "", text, depth * 2)
+    }
+
+    fn func_ref(&self, idx: u32) -> String {
+        match self.module.names.funcs.get(&idx) {
+            Some(name) => format!("{} <{}>", idx, name.as_str()),
+            None => idx.to_string(),
+        }
+    }
+
+    fn local_ref(&self, idx: u32) -> String {
+        let name = self.module.names.locals.get(&self.idx);
+        match name.and_then(|names| names.get(&idx)) {
+            Some(name) => format!("{} <{}>", idx, name.as_str()),
+            None => idx.to_string(),
+        }
+    }
+
+    /// Returns the text of an instruction other than a block.
+    fn instr(&self, instr: &Instruction) -> String {
+        match instr {
+            // control instructions
+            Instruction::Unreachable => "unreachable".to_string(),
+            Instruction::Nop => "nop".to_string(),
+            Instruction::Block { .. } | Instruction::Loop { .. } | Instruction::If { .. } => {
+                unreachable!("blocks are printed with their instructions")
+            }
+            Instruction::Br(idx) => format!("br {}", idx.get()),
+            Instruction::BrIf(idx) => format!("br_if {}", idx.get()),
+            Instruction::BrTable(labels, default) => {
+                let mut text = "br_table".to_string();
+                for label in labels.iter().chain([default]) {
+                    text += &format!(" {}", label.get());
+                }
+                text
+            }
+            Instruction::Return => "return".to_string(),
+            Instruction::Call(idx) => format!("call {}", self.func_ref(idx.get())),
+            Instruction::CallIndirect { ty, table } => {
+                format!("call_indirect {} (type {})", table.get(), ty.get())
+            }
+
+            // reference instructions
+            Instruction::RefNull(RefType::Funcref) => "ref.null func".to_string(),
+            Instruction::RefNull(RefType::Externref) => "ref.null extern".to_string(),
+            Instruction::RefIsNull => "ref.is_null".to_string(),
+            Instruction::RefFunc(idx) => format!("ref.func {}", self.func_ref(idx.get())),
+
+            // parametric instructions
+            Instruction::Drop => "drop".to_string(),
+            Instruction::Select(types) => match types.first() {
+                Some(ty) => format!("select (result {})", value_type(*ty)),
+                None => "select".to_string(),
+            },
+
+            // variable instructions
+            Instruction::LocalGet(idx) => format!("local.get {}", self.local_ref(idx.get())),
+            Instruction::LocalSet(idx) => format!("local.set {}", self.local_ref(idx.get())),
+            Instruction::LocalTee(idx) => format!("local.tee {}", self.local_ref(idx.get())),
+            Instruction::GlobalGet(idx) => format!("global.get {}", idx.get()),
+            Instruction::GlobalSet(idx) => format!("global.set {}", idx.get()),
+
+            // table instructions
+            Instruction::TableGet(idx) => format!("table.get {}", idx.get()),
+            Instruction::TableSet(idx) => format!("table.set {}", idx.get()),
+            Instruction::TableSize(idx) => format!("table.size {}", idx.get()),
+            Instruction::TableGrow(idx) => format!("table.grow {}", idx.get()),
+            Instruction::TableFill(idx) => format!("table.fill {}", idx.get()),
+            Instruction::TableCopy { dst, src } => {
+                format!("table.copy {} {}", dst.get(), src.get())
+            }
+            Instruction::TableInit { elem, table } => {
+                format!("table.init {} {}", table.get(), elem.get())
+            }
+            Instruction::ElemDrop(idx) => format!("elem.drop {}", idx.get()),
+
+            // memory instructions
+            Instruction::I32Load(arg) => mem("i32.load", 2, arg),
+            Instruction::I64Load(arg) => mem("i64.load", 3, arg),
+            Instruction::F32Load(arg) => mem("f32.load", 2, arg),
+            Instruction::F64Load(arg) => mem("f64.load", 3, arg),
+            Instruction::I32Load8S(arg) => mem("i32.load8_s", 0, arg),
+            Instruction::I32Load8U(arg) => mem("i32.load8_u", 0, arg),
+            Instruction::I32Load16S(arg) => mem("i32.load16_s", 1, arg),
+            Instruction::I32Load16U(arg) => mem("i32.load16_u", 1, arg),
+            Instruction::I64Load8S(arg) => mem("i64.load8_s", 0, arg),
+            Instruction::I64Load8U(arg) => mem("i64.load8_u", 0, arg),
+            Instruction::I64Load16S(arg) => mem("i64.load16_s", 1, arg),
+            Instruction::I64Load16U(arg) => mem("i64.load16_u", 1, arg),
+            Instruction::I64Load32S(arg) => mem("i64.load32_s", 2, arg),
+            Instruction::I64Load32U(arg) => mem("i64.load32_u", 2, arg),
+            Instruction::I32Store(arg) => mem("i32.store", 2, arg),
+            Instruction::I64Store(arg) => mem("i64.store", 3, arg),
+            Instruction::F32Store(arg) => mem("f32.store", 2, arg),
+            Instruction::F64Store(arg) => mem("f64.store", 3, arg),
+            Instruction::I32Store8(arg) => mem("i32.store8", 0, arg),
+            Instruction::I32Store16(arg) => mem("i32.store16", 1, arg),
+            Instruction::I64Store8(arg) => mem("i64.store8", 0, arg),
+            Instruction::I64Store16(arg) => mem("i64.store16", 1, arg),
+            Instruction::I64Store32(arg) => mem("i64.store32", 2, arg),
+            Instruction::MemorySize => "memory.size".to_string(),
+            Instruction::MemoryGrow => "memory.grow".to_string(),
+            Instruction::MemoryInit(idx) => format!("memory.init {}", idx.get()),
+            Instruction::DataDrop(idx) => format!("data.drop {}", idx.get()),
+            Instruction::MemoryCopy => "memory.copy".to_string(),
+            Instruction::MemoryFill => "memory.fill".to_string(),
+
+            // numeric instructions
+            Instruction::I32Const(v) => format!("i32.const {}", v),
+            Instruction::I32Extend8S => "i32.extend8_s".to_string(),
+            Instruction::I32Extend16S => "i32.extend16_s".to_string(),
+            Instruction::I32UnOp(op) => format!("i32.{}", iunop(op)),
+            Instruction::I32Eqz => "i32.eqz".to_string(),
+            Instruction::I32BinOp(op) => format!("i32.{}", ibinop(op)),
+            Instruction::I32RelOp(op) => format!("i32.{}", irelop(op)),
+
+            Instruction::I64Const(v) => format!("i64.const {}", v),
+            Instruction::I64UnOp(op) => format!("i64.{}", iunop(op)),
+            Instruction::I64BinOp(op) => format!("i64.{}", ibinop(op)),
+            Instruction::I64RelOp(op) => format!("i64.{}", irelop(op)),
+            Instruction::I64Extend8S => "i64.extend8_s".to_string(),
+            Instruction::I64Extend16S => "i64.extend16_s".to_string(),
+            Instruction::I64Extend32S => "i64.extend32_s".to_string(),
+            Instruction::I64Eqz => "i64.eqz".to_string(),
+
+            Instruction::F32Const(v) => format!("f32.const {}", float(*v as f64, v.is_nan())),
+            Instruction::F32UnOp(op) => format!("f32.{}", funop(op)),
+            Instruction::F32BinOp(op) => format!("f32.{}", fbinop(op)),
+            Instruction::F32RelOp(op) => format!("f32.{}", frelop(op)),
+
+            Instruction::F64Const(v) => format!("f64.const {}", float(*v, v.is_nan())),
+            Instruction::F64UnOp(op) => format!("f64.{}", funop(op)),
+            Instruction::F64BinOp(op) => format!("f64.{}", fbinop(op)),
+            Instruction::F64RelOp(op) => format!("f64.{}", frelop(op)),
+
+            Instruction::I32WrapI64 => "i32.wrap_i64".to_string(),
+            Instruction::I32TruncF32S => "i32.trunc_f32_s".to_string(),
+            Instruction::I32TruncF32U => "i32.trunc_f32_u".to_string(),
+            Instruction::I32TruncF64S => "i32.trunc_f64_s".to_string(),
+            Instruction::I32TruncF64U => "i32.trunc_f64_u".to_string(),
+            Instruction::I64ExtendI32S => "i64.extend_i32_s".to_string(),
+            Instruction::I64ExtendI32U => "i64.extend_i32_u".to_string(),
+            Instruction::I64TruncF32S => "i64.trunc_f32_s".to_string(),
+            Instruction::I64TruncF32U => "i64.trunc_f32_u".to_string(),
+            Instruction::I64TruncF64S => "i64.trunc_f64_s".to_string(),
+            Instruction::I64TruncF64U => "i64.trunc_f64_u".to_string(),
+            Instruction::F32ConvertI32S => "f32.convert_i32_s".to_string(),
+            Instruction::F32ConvertI32U => "f32.convert_i32_u".to_string(),
+            Instruction::F32ConvertI64S => "f32.convert_i64_s".to_string(),
+            Instruction::F32ConvertI64U => "f32.convert_i64_u".to_string(),
+            Instruction::F32DemoteF64 => "f32.demote_f64".to_string(),
+            Instruction::F64ConvertI32S => "f64.convert_i32_s".to_string(),
+            Instruction::F64ConvertI32U => "f64.convert_i32_u".to_string(),
+            Instruction::F64ConvertI64S => "f64.convert_i64_s".to_string(),
+            Instruction::F64ConvertI64U => "f64.convert_i64_u".to_string(),
+            Instruction::F64PromoteF32 => "f64.promote_f32".to_string(),
+            Instruction::I32ReinterpretF32 => "i32.reinterpret_f32".to_string(),
+            Instruction::I64ReinterpretF64 => "i64.reinterpret_f64".to_string(),
+            Instruction::F32ReinterpretI32 => "f32.reinterpret_i32".to_string(),
+            Instruction::F64ReinterpretI64 => "f64.reinterpret_i64".to_string(),
+            Instruction::I32TruncSatF32S => "i32.trunc_sat_f32_s".to_string(),
+            Instruction::I32TruncSatF32U => "i32.trunc_sat_f32_u".to_string(),
+            Instruction::I32TruncSatF64S => "i32.trunc_sat_f64_s".to_string(),
+            Instruction::I32TruncSatF64U => "i32.trunc_sat_f64_u".to_string(),
+            Instruction::I64TruncSatF32S => "i64.trunc_sat_f32_s".to_string(),
+            Instruction::I64TruncSatF32U => "i64.trunc_sat_f32_u".to_string(),
+            Instruction::I64TruncSatF64S => "i64.trunc_sat_f64_s".to_string(),
+            Instruction::I64TruncSatF64U => "i64.trunc_sat_f64_u".to_string(),
+
+            // vector instructions are not retained by the decoder
+            Instruction::Vector => "<vector instruction>".to_string(),
+        }
+    }
+}
+
+fn value_type(ty: ValueType) -> &'static str {
+    match ty {
+        ValueType::Num(NumType::I32) => "i32",
+        ValueType::Num(NumType::I64) => "i64",
+        ValueType::Num(NumType::F32) => "f32",
+        ValueType::Num(NumType::F64) => "f64",
+        ValueType::Vec(VecType::V128) => "v128",
+        ValueType::Ref(RefType::Funcref) => "funcref",
+        ValueType::Ref(RefType::Externref) => "externref",
+    }
+}
+
+fn block(block_type: &BlockType) -> String {
+    match block_type {
+        BlockType::ValType(None) => String::new(),
+        BlockType::ValType(Some(ty)) => format!(" (result {})", value_type(*ty)),
+        BlockType::Type(idx) => format!(" (type {})", idx.get()),
+    }
+}
+
+/// Returns the text of a memory instruction, whose natural alignment is
+/// `2^natural` bytes.
+fn mem(name: &str, natural: u32, arg: &MemArg) -> String {
+    let mut text = name.to_string();
+    if arg.offset != 0 {
+        text += &format!(" offset={}", arg.offset);
+    }
+    if arg.align != natural {
+        text += &format!(" align={}", 1u64 << arg.align.min(63));
+    }
+    text
+}
+
+/// Formats a float constant in the text format, which spells NaN and infinity
+/// differently from Rust.
+fn float(v: f64, nan: bool) -> String {
+    let sign = if v.is_sign_negative() { "-" } else { "" };
+    if nan {
+        format!("{}nan", sign)
+    } else if v.is_infinite() {
+        format!("{}inf", sign)
+    } else {
+        v.to_string()
+    }
+}
+
+fn iunop(op: &IUnOp) -> &'static str {
+    match op {
+        IUnOp::Clz => "clz",
+        IUnOp::Ctz => "ctz",
+        IUnOp::Popcnt => "popcnt",
+    }
+}
+
+fn ibinop(op: &IBinOp) -> &'static str {
+    match op {
+        IBinOp::Add => "add",
+        IBinOp::Sub => "sub",
+        IBinOp::Mul => "mul",
+        IBinOp::DivS => "div_s",
+        IBinOp::DivU => "div_u",
+        IBinOp::RemS => "rem_s",
+        IBinOp::RemU => "rem_u",
+        IBinOp::And => "and",
+        IBinOp::Or => "or",
+        IBinOp::Xor => "xor",
+        IBinOp::Shl => "shl",
+        IBinOp::ShrS => "shr_s",
+        IBinOp::ShrU => "shr_u",
+        IBinOp::Rotl => "rotl",
+        IBinOp::Rotr => "rotr",
+    }
+}
+
+fn irelop(op: &IRelOp) -> &'static str {
+    match op {
+        IRelOp::Eq => "eq",
+        IRelOp::Ne => "ne",
+        IRelOp::LtS => "lt_s",
+        IRelOp::LtU => "lt_u",
+        IRelOp::GtS => "gt_s",
+        IRelOp::GtU => "gt_u",
+        IRelOp::LeS => "le_s",
+        IRelOp::LeU => "le_u",
+        IRelOp::GeS => "ge_s",
+        IRelOp::GeU => "ge_u",
+    }
+}
+
+fn funop(op: &FUnOp) -> &'static str {
+    match op {
+        FUnOp::Abs => "abs",
+        FUnOp::Neg => "neg",
+        FUnOp::Ceil => "ceil",
+        FUnOp::Floor => "floor",
+        FUnOp::Trunc => "trunc",
+        FUnOp::Nearest => "nearest",
+        FUnOp::Sqrt => "sqrt",
+    }
+}
+
+fn fbinop(op: &FBinOp) -> &'static str {
+    match op {
+        FBinOp::Add => "add",
+        FBinOp::Sub => "sub",
+        FBinOp::Mul => "mul",
+        FBinOp::Div => "div",
+        FBinOp::Min => "min",
+        FBinOp::Max => "max",
+        FBinOp::Copysign => "copysign",
+    }
+}
+
+fn frelop(op: &FRelOp) -> &'static str {
+    match op {
+        FRelOp::Eq => "eq",
+        FRelOp::Ne => "ne",
+        FRelOp::Lt => "lt",
+        FRelOp::Gt => "gt",
+        FRelOp::Le => "le",
+        FRelOp::Ge => "ge",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode, decode_lazy};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_disassemble() {
+        let buf = wast::parser::ParseBuffer::new(
+            r#"
+            (module $m
+              (import "env" "log" (func $log (param i32)))
+              (func $abs (export "abs") (param $x i32) (result i32) (local $neg i32) (local f64 f64)
+                (local.set $neg (i32.lt_s (local.get $x) (i32.const 0)))
+                (if (result i32) (local.get $neg)
+                  (then (i32.sub (i32.const 0) (local.get $x)))
+                  (else (local.get $x))))
+              (func (param i32)
+                (call $log (i32.load offset=4 align=1 (local.get 0)))))
+            "#,
+        )
+        .unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        let bin = wat.encode().unwrap();
+
+        let expected = "\
+module <m>:
+func[1] <abs>:
+         local[1] type=i32
+         local[2..3] type=f64
+ 000038: local.get 0 <x>
+ 00003a: i32.const 0
+ 00003c: i32.lt_s
+ 00003d: local.set 1 <neg>
+ 00003f: local.get 1 <neg>
+ 000041: if (result i32)
+ 000043:   i32.const 0
+ 000045:   local.get 0 <x>
+ 000047:   i32.sub
+         else
+ 000049:   local.get 0 <x>
+         end
+ 00004c: end
+func[2]:
+ 00004f: local.get 0
+ 000051: i32.load offset=4 align=1
+ 000054: call 0 <log>
+ 000056: end
+";
+        let module = decode_lazy(&mut Cursor::new(&bin)).unwrap();
+        assert_eq!(disassemble(&module).unwrap(), expected);
+
+        // offsets are not known for eagerly decoded bodies
+        let module = decode(&mut Cursor::new(&bin)).unwrap();
+        let expected: String = expected
+            .lines()
+            .map(|line| match line.strip_prefix(' ') {
+                Some(line) if line.starts_with('0') => format!("{:2$}{}\n", "", &line[8..], GUTTER),
+                _ => format!("{}\n", line),
+            })
+            .collect();
+        assert_eq!(disassemble(&module).unwrap(), expected);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::core::Module;
+use anyhow::Result;
+use prelude::*;
+
+mod instruction;
+mod prelude;
+mod section;
+mod types;
+mod value;
+
+/// The order of non-custom sections, where the data count section precedes
+/// the code section.
+const SECTION_ORDER: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11];
+
+/// Encodes a module into its binary format, which decodes back to the same
+/// module. Empty sections are omitted, LEB128 integers are written in their
+/// shortest form and custom sections are written where they were found, so
+/// decoding a canonical binary and encoding it again yields the same bytes.
+///
+/// [`Module::names`] is not encoded, the `name` section is written from
+/// [`Module::customs`] like any other custom section. Vector instructions are
+/// not retained by the decoder and cannot be encoded.
+pub fn encode(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    write_customs(&mut buf, module, None)?;
+    for id in SECTION_ORDER {
+        write_section(&mut buf, module, id)?;
+        write_customs(&mut buf, module, Some(id))?;
+    }
+    Ok(buf)
+}
+
+fn write_section(buf: &mut Vec<u8>, module: &Module, id: u8) -> Result<()> {
+    match id {
+        1 if !module.types.is_empty() => buf.write_section(id, |buf| {
+            buf.write_vec(&module.types, |buf, ty| buf.write_func_type(ty));
+            Ok(())
+        }),
+        2 if !module.imports.is_empty() => buf.write_section(id, |buf| {
+            buf.write_import_section(&module.imports);
+            Ok(())
+        }),
+        3 if !module.funcs.is_empty() => buf.write_section(id, |buf| {
+            buf.write_function_section(&module.funcs);
+            Ok(())
+        }),
+        4 if !module.tables.is_empty() => buf.write_section(id, |buf| {
+            buf.write_table_section(&module.tables);
+            Ok(())
+        }),
+        5 if !module.memories.is_empty() => buf.write_section(id, |buf| {
+            buf.write_memory_section(&module.memories);
+            Ok(())
+        }),
+        6 if !module.globals.is_empty() => {
+            buf.write_section(id, |buf| buf.write_global_section(&module.globals))
+        }
+        7 if !module.exports.is_empty() => buf.write_section(id, |buf| {
+            buf.write_export_section(&module.exports);
+            Ok(())
+        }),
+        8 => match module.start {
+            Some(idx) => buf.write_section(id, |buf| {
+                buf.write_u32(idx.get());
+                Ok(())
+            }),
+            None => Ok(()),
+        },
+        9 if !module.elements.is_empty() => {
+            buf.write_section(id, |buf| buf.write_element_section(&module.elements))
+        }
+        10 if !module.funcs.is_empty() => {
+            buf.write_section(id, |buf| buf.write_code_section(&module.funcs))
+        }
+        11 if !module.datas.is_empty() => {
+            buf.write_section(id, |buf| buf.write_data_section(&module.datas))
+        }
+        12 => match module.data_count {
+            Some(count) => buf.write_section(id, |buf| {
+                buf.write_u32(count);
+                Ok(())
+            }),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Writes the custom sections that followed the section `after`.
+fn write_customs(buf: &mut Vec<u8>, module: &Module, after: Option<u8>) -> Result<()> {
+    for custom in module.customs.iter().filter(|c| c.after == after) {
+        buf.write_section(0, |buf| {
+            buf.write_name(&custom.name);
+            buf.extend_from_slice(&custom.data);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        Expression, Func, FuncType, Global, GlobalType, Instruction, NumType, ValueType, VecType,
+    };
+    use crate::decode::{decode, decode_lazy};
+    use std::io::Cursor;
+
+    fn wat2wasm(wat: &str) -> Vec<u8> {
+        let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        wat.encode().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bin = wat2wasm(
+            r#"
+            (module
+              (type $t (func (param i32) (result i32)))
+              (import "env" "f" (func $f (type $t)))
+              (import "env" "t" (table 1 funcref))
+              (import "env" "g" (global $g (mut i64)))
+              (memory 1 2)
+              (table $t2 2 externref)
+              (global $h f64 (f64.const -1.5))
+              (export "run" (func $run))
+              (export "mem" (memory 0))
+              (start $init)
+              (elem (i32.const 0) $run)
+              (elem $e1 func $f $run)
+              (elem $e2 externref (ref.null extern))
+              (elem (table $t2) (i32.const 1) externref (ref.null extern))
+              (elem declare funcref (ref.func $init) (ref.null func))
+              (data (i32.const 8) "hello")
+              (data $d "world")
+              (func $init)
+              (func $run (type $t) (local i64 i64 f32) (local v128)
+                (block $b (result i32)
+                  (loop $l
+                    (br_table $b $l $b (local.get 0)))
+                  (i32.const -129))
+                (if (result i32) (i32.eqz (local.get 0))
+                  (then (i32.const 624485))
+                  (else (select (result i32) (i32.const 1) (i32.const 2) (local.get 0))))
+                i32.add
+                (drop (i64.load32_u offset=4 align=2 (i32.const 0)))
+                (memory.init $d (i32.const 0) (i32.const 0) (i32.const 5))
+                (data.drop $d)
+                (memory.copy (i32.const 0) (i32.const 1) (i32.const 2))
+                (memory.fill (i32.const 0) (i32.const 1) (i32.const 2))
+                (table.init $t2 $e2 (i32.const 0) (i32.const 0) (i32.const 1))
+                (elem.drop $e1)
+                (drop (table.grow $t2 (ref.null extern) (i32.const 1)))
+                (global.set $g (i64.extend8_s (i64.const -9223372036854775808)))
+                (drop (i32.trunc_sat_f64_u (global.get $h)))
+                (drop (f32.copysign (f32.const nan:0x200000) (f32.const -0)))
+                (call_indirect (type $t) (i32.const 0) (i32.const 0))
+                (drop (call $f (i32.const 1)))
+                (drop (memory.grow (memory.size))))
+              (func (result i32 i64)
+                (block (result i32 i64) (i32.const 0) (i64.const 1))))
+            "#,
+        );
+
+        let module = decode(&mut Cursor::new(&bin)).unwrap();
+        assert_eq!(encode(&module).unwrap(), bin);
+        let module = decode_lazy(&mut Cursor::new(&bin)).unwrap();
+        assert_eq!(encode(&module).unwrap(), bin);
+    }
+
+    #[test]
+    fn test_encode_custom_sections() {
+        let bytes = vec![
+            0, 0x61, 0x73, 0x6d, 1, 0, 0, 0, // header
+            0, 3, 1, b'a', 1, // custom section "a"
+            1, 4, 1, 0x60, 0, 0, // type section
+            0, 2, 1, b'b', // custom section "b"
+            12, 1, 0, // data count section
+            0, 2, 1, b'c', // custom section "c"
+        ];
+        let module = decode(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(encode(&module).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_encode_locals() {
+        let i32 = ValueType::Num(NumType::I32);
+        let f64 = ValueType::Num(NumType::F64);
+        let module = Module {
+            types: vec![FuncType {
+                params: vec![],
+                results: vec![],
+            }],
+            funcs: vec![Func {
+                type_id: 0.into(),
+                locals: vec![(2, i32), (1, f64), (1, i32)],
+                body: Expression {
+                    instructions: vec![Instruction::Nop],
+                },
+                raw: None,
+            }],
+            ..Default::default()
+        };
+
+        let bin = encode(&module).unwrap();
+        assert_eq!(
+            bin[bin.len() - 13..],
+            [
+                10, 11, 1, 9, // code section with a body of 9 bytes
+                3, 2, 0x7f, 1, 0x7c, 1, 0x7f, // locals
+                0x01, 0x0b, // nop end
+            ]
+        );
+        let decoded = decode(&mut Cursor::new(&bin)).unwrap();
+        assert_eq!(decoded.funcs[0].locals, module.funcs[0].locals);
+    }
+
+    #[test]
+    fn test_encode_vector() {
+        let module = Module {
+            globals: vec![Global {
+                global_type: GlobalType {
+                    value_type: ValueType::Vec(VecType::V128),
+                    mutability: false,
+                },
+                init: Expression {
+                    instructions: vec![Instruction::Vector],
+                },
+            }],
+            ..Default::default()
+        };
+        assert!(encode(&module).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use super::prelude::*;
+use crate::core::{BlockType, Expression, Instruction, MemArg};
+use anyhow::{bail, Result};
+
+pub trait WriteInstructionExt {
+    fn write_expr(&mut self, expr: &Expression) -> Result<()>;
+    fn write_block_type(&mut self, block_type: &BlockType);
+    fn write_mem_arg(&mut self, arg: &MemArg);
+    fn write_instr(&mut self, instr: &Instruction) -> Result<()>;
+}
+
+impl WriteInstructionExt for Vec<u8> {
+    fn write_expr(&mut self, expr: &Expression) -> Result<()> {
+        for instr in &expr.instructions {
+            self.write_instr(instr)?;
+        }
+        self.push(0x0b);
+        Ok(())
+    }
+
+    fn write_block_type(&mut self, block_type: &BlockType) {
+        match block_type {
+            BlockType::ValType(None) => self.push(0x40),
+            BlockType::ValType(Some(ty)) => self.write_value_type(*ty),
+            BlockType::Type(idx) => self.write_signed_leb128(idx.get() as i64),
+        }
+    }
+
+    fn write_mem_arg(&mut self, arg: &MemArg) {
+        self.write_u32(arg.align);
+        self.write_u32(arg.offset);
+    }
+
+    fn write_instr(&mut self, instr: &Instruction) -> Result<()> {
+        // the operators are declared in opcode order, so their discriminant is
+        // the offset from the first opcode of their group
+        match instr {
+            // control instructions
+            Instruction::Unreachable => self.push(0x00),
+            Instruction::Nop => self.push(0x01),
+            Instruction::Block {
+                block_type,
+                instructions,
+            }
+            | Instruction::Loop {
+                block_type,
+                instructions,
+            } => {
+                self.push(match instr {
+                    Instruction::Block { .. } => 0x02,
+                    _ => 0x03,
+                });
+                self.write_block_type(block_type);
+                for instr in instructions {
+                    self.write_instr(instr)?;
+                }
+                self.push(0x0b);
+            }
+            Instruction::If {
+                block_type,
+                instructions,
+                else_instructions,
+            } => {
+                self.push(0x04);
+                self.write_block_type(block_type);
+                for instr in instructions {
+                    self.write_instr(instr)?;
+                }
+                if !else_instructions.is_empty() {
+                    self.push(0x05);
+                    for instr in else_instructions {
+                        self.write_instr(instr)?;
+                    }
+                }
+                self.push(0x0b);
+            }
+            Instruction::Br(idx) => {
+                self.push(0x0c);
+                self.write_u32(idx.get());
+            }
+            Instruction::BrIf(idx) => {
+                self.push(0x0d);
+                self.write_u32(idx.get());
+            }
+            Instruction::BrTable(labels, default) => {
+                self.push(0x0e);
+                self.write_vec(labels, |buf, idx| buf.write_u32(idx.get()));
+                self.write_u32(default.get());
+            }
+            Instruction::Return => self.push(0x0f),
+            Instruction::Call(idx) => {
+                self.push(0x10);
+                self.write_u32(idx.get());
+            }
+            Instruction::CallIndirect { ty, table } => {
+                self.push(0x11);
+                self.write_u32(ty.get());
+                self.write_u32(table.get());
+            }
+
+            // reference instructions
+            Instruction::RefNull(ty) => {
+                self.push(0xd0);
+                self.write_ref_type(*ty);
+            }
+            Instruction::RefIsNull => self.push(0xd1),
+            Instruction::RefFunc(idx) => {
+                self.push(0xd2);
+                self.write_u32(idx.get());
+            }
+
+            // parametric instructions
+            Instruction::Drop => self.push(0x1a),
+            Instruction::Select(types) if types.is_empty() => self.push(0x1b),
+            Instruction::Select(types) => {
+                self.push(0x1c);
+                self.write_result_type(types);
+            }
+
+            // variable instructions
+            Instruction::LocalGet(idx) => {
+                self.push(0x20);
+                self.write_u32(idx.get());
+            }
+            Instruction::LocalSet(idx) => {
+                self.push(0x21);
+                self.write_u32(idx.get());
+            }
+            Instruction::LocalTee(idx) => {
+                self.push(0x22);
+                self.write_u32(idx.get());
+            }
+            Instruction::GlobalGet(idx) => {
+                self.push(0x23);
+                self.write_u32(idx.get());
+            }
+            Instruction::GlobalSet(idx) => {
+                self.push(0x24);
+                self.write_u32(idx.get());
+            }
+
+            // table instructions
+            Instruction::TableGet(idx) => {
+                self.push(0x25);
+                self.write_u32(idx.get());
+            }
+            Instruction::TableSet(idx) => {
+                self.push(0x26);
+                self.write_u32(idx.get());
+            }
+            Instruction::TableInit { elem, table } => {
+                self.extend_from_slice(&[0xfc, 0x0c]);
+                self.write_u32(elem.get());
+                self.write_u32(table.get());
+            }
+            Instruction::ElemDrop(idx) => {
+                self.extend_from_slice(&[0xfc, 0x0d]);
+                self.write_u32(idx.get());
+            }
+            Instruction::TableCopy { dst, src } => {
+                self.extend_from_slice(&[0xfc, 0x0e]);
+                self.write_u32(dst.get());
+                self.write_u32(src.get());
+            }
+            Instruction::TableGrow(idx) => {
+                self.extend_from_slice(&[0xfc, 0x0f]);
+                self.write_u32(idx.get());
+            }
+            Instruction::TableSize(idx) => {
+                self.extend_from_slice(&[0xfc, 0x10]);
+                self.write_u32(idx.get());
+            }
+            Instruction::TableFill(idx) => {
+                self.extend_from_slice(&[0xfc, 0x11]);
+                self.write_u32(idx.get());
+            }
+
+            // memory instructions
+            Instruction::I32Load(arg)
+            | Instruction::I64Load(arg)
+            | Instruction::F32Load(arg)
+            | Instruction::F64Load(arg)
+            | Instruction::I32Load8S(arg)
+            | Instruction::I32Load8U(arg)
+            | Instruction::I32Load16S(arg)
+            | Instruction::I32Load16U(arg)
+            | Instruction::I64Load8S(arg)
+            | Instruction::I64Load8U(arg)
+            | Instruction::I64Load16S(arg)
+            | Instruction::I64Load16U(arg)
+            | Instruction::I64Load32S(arg)
+            | Instruction::I64Load32U(arg)
+            | Instruction::I32Store(arg)
+            | Instruction::I64Store(arg)
+            | Instruction::F32Store(arg)
+            | Instruction::F64Store(arg)
+            | Instruction::I32Store8(arg)
+            | Instruction::I32Store16(arg)
+            | Instruction::I64Store8(arg)
+            | Instruction::I64Store16(arg)
+            | Instruction::I64Store32(arg) => {
+                self.push(match instr {
+                    Instruction::I32Load(_) => 0x28,
+                    Instruction::I64Load(_) => 0x29,
+                    Instruction::F32Load(_) => 0x2a,
+                    Instruction::F64Load(_) => 0x2b,
+                    Instruction::I32Load8S(_) => 0x2c,
+                    Instruction::I32Load8U(_) => 0x2d,
+                    Instruction::I32Load16S(_) => 0x2e,
+                    Instruction::I32Load16U(_) => 0x2f,
+                    Instruction::I64Load8S(_) => 0x30,
+                    Instruction::I64Load8U(_) => 0x31,
+                    Instruction::I64Load16S(_) => 0x32,
+                    Instruction::I64Load16U(_) => 0x33,
+                    Instruction::I64Load32S(_) => 0x34,
+                    Instruction::I64Load32U(_) => 0x35,
+                    Instruction::I32Store(_) => 0x36,
+                    Instruction::I64Store(_) => 0x37,
+                    Instruction::F32Store(_) => 0x38,
+                    Instruction::F64Store(_) => 0x39,
+                    Instruction::I32Store8(_) => 0x3a,
+                    Instruction::I32Store16(_) => 0x3b,
+                    Instruction::I64Store8(_) => 0x3c,
+                    Instruction::I64Store16(_) => 0x3d,
+                    _ => 0x3e,
+                });
+                self.write_mem_arg(arg);
+            }
+            Instruction::MemorySize => self.extend_from_slice(&[0x3f, 0x00]),
+            Instruction::MemoryGrow => self.extend_from_slice(&[0x40, 0x00]),
+            Instruction::MemoryInit(idx) => {
+                self.extend_from_slice(&[0xfc, 0x08]);
+                self.write_u32(idx.get());
+                self.push(0x00);
+            }
+            Instruction::DataDrop(idx) => {
+                self.extend_from_slice(&[0xfc, 0x09]);
+                self.write_u32(idx.get());
+            }
+            Instruction::MemoryCopy => self.extend_from_slice(&[0xfc, 0x0a, 0x00, 0x00]),
+            Instruction::MemoryFill => self.extend_from_slice(&[0xfc, 0x0b, 0x00]),
+
+            // numeric instructions
+            Instruction::I32Const(v) => {
+                self.push(0x41);
+                self.write_signed_leb128(*v as i64);
+            }
+            Instruction::I64Const(v) => {
+                self.push(0x42);
+                self.write_signed_leb128(*v);
+            }
+            Instruction::F32Const(v) => {
+                self.push(0x43);
+                self.extend_from_slice(&v.to_le_bytes());
+            }
+            Instruction::F64Const(v) => {
+                self.push(0x44);
+                self.extend_from_slice(&v.to_le_bytes());
+            }
+
+            Instruction::I32Eqz => self.push(0x45),
+            Instruction::I32RelOp(op) => self.push(0x46 + op.clone() as u8),
+            Instruction::I64Eqz => self.push(0x50),
+            Instruction::I64RelOp(op) => self.push(0x51 + op.clone() as u8),
+            Instruction::F32RelOp(op) => self.push(0x5b + op.clone() as u8),
+            Instruction::F64RelOp(op) => self.push(0x61 + op.clone() as u8),
+            Instruction::I32UnOp(op) => self.push(0x67 + op.clone() as u8),
+            Instruction::I32BinOp(op) => self.push(0x6a + op.clone() as u8),
+            Instruction::I64UnOp(op) => self.push(0x79 + op.clone() as u8),
+            Instruction::I64BinOp(op) => self.push(0x7c + op.clone() as u8),
+            Instruction::F32UnOp(op) => self.push(0x8b + op.clone() as u8),
+            Instruction::F32BinOp(op) => self.push(0x92 + op.clone() as u8),
+            Instruction::F64UnOp(op) => self.push(0x99 + op.clone() as u8),
+            Instruction::F64BinOp(op) => self.push(0xa0 + op.clone() as u8),
+
+            Instruction::I32WrapI64 => self.push(0xa7),
+            Instruction::I32TruncF32S => self.push(0xa8),
+            Instruction::I32TruncF32U => self.push(0xa9),
+            Instruction::I32TruncF64S => self.push(0xaa),
+            Instruction::I32TruncF64U => self.push(0xab),
+            Instruction::I64ExtendI32S => self.push(0xac),
+            Instruction::I64ExtendI32U => self.push(0xad),
+            Instruction::I64TruncF32S => self.push(0xae),
+            Instruction::I64TruncF32U => self.push(0xaf),
+            Instruction::I64TruncF64S => self.push(0xb0),
+            Instruction::I64TruncF64U => self.push(0xb1),
+            Instruction::F32ConvertI32S => self.push(0xb2),
+            Instruction::F32ConvertI32U => self.push(0xb3),
+            Instruction::F32ConvertI64S => self.push(0xb4),
+            Instruction::F32ConvertI64U => self.push(0xb5),
+            Instruction::F32DemoteF64 => self.push(0xb6),
+            Instruction::F64ConvertI32S => self.push(0xb7),
+            Instruction::F64ConvertI32U => self.push(0xb8),
+            Instruction::F64ConvertI64S => self.push(0xb9),
+            Instruction::F64ConvertI64U => self.push(0xba),
+            Instruction::F64PromoteF32 => self.push(0xbb),
+            Instruction::I32ReinterpretF32 => self.push(0xbc),
+            Instruction::I64ReinterpretF64 => self.push(0xbd),
+            Instruction::F32ReinterpretI32 => self.push(0xbe),
+            Instruction::F64ReinterpretI64 => self.push(0xbf),
+
+            Instruction::I32Extend8S => self.push(0xc0),
+            Instruction::I32Extend16S => self.push(0xc1),
+            Instruction::I64Extend8S => self.push(0xc2),
+            Instruction::I64Extend16S => self.push(0xc3),
+            Instruction::I64Extend32S => self.push(0xc4),
+
+            Instruction::I32TruncSatF32S => self.extend_from_slice(&[0xfc, 0x00]),
+            Instruction::I32TruncSatF32U => self.extend_from_slice(&[0xfc, 0x01]),
+            Instruction::I32TruncSatF64S => self.extend_from_slice(&[0xfc, 0x02]),
+            Instruction::I32TruncSatF64U => self.extend_from_slice(&[0xfc, 0x03]),
+            Instruction::I64TruncSatF32S => self.extend_from_slice(&[0xfc, 0x04]),
+            Instruction::I64TruncSatF32U => self.extend_from_slice(&[0xfc, 0x05]),
+            Instruction::I64TruncSatF64S => self.extend_from_slice(&[0xfc, 0x06]),
+            Instruction::I64TruncSatF64U => self.extend_from_slice(&[0xfc, 0x07]),
+
+            // vector instructions
+            Instruction::Vector => bail!("vector instructions cannot be encoded"),
+        }
+
+        Ok(())
+    }
+}
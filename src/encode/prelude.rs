@@ -0,0 +1,4 @@
+pub use super::instruction::WriteInstructionExt;
+pub use super::section::WriteSectionExt;
+pub use super::types::WriteTypeExt;
+pub use super::value::WriteValueExt;
@@ -0,0 +1,196 @@
+use super::prelude::*;
+use crate::core::{
+    Data, DataMode, Element, ElementMode, Export, ExportDesc, Func, Global, Import, ImportDesc,
+    Instruction, Memory, RefType, Table,
+};
+use anyhow::Result;
+
+pub trait WriteSectionExt {
+    /// Writes section `id` with the content written by `f`, prefixed by its
+    /// size.
+    fn write_section(&mut self, id: u8, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()>;
+    fn write_import_section(&mut self, imports: &[Import]);
+    fn write_function_section(&mut self, funcs: &[Func]);
+    fn write_table_section(&mut self, tables: &[Table]);
+    fn write_memory_section(&mut self, memories: &[Memory]);
+    fn write_global_section(&mut self, globals: &[Global]) -> Result<()>;
+    fn write_export_section(&mut self, exports: &[Export]);
+    fn write_element_section(&mut self, elements: &[Element]) -> Result<()>;
+    fn write_code_section(&mut self, funcs: &[Func]) -> Result<()>;
+    fn write_data_section(&mut self, datas: &[Data]) -> Result<()>;
+}
+
+impl WriteSectionExt for Vec<u8> {
+    fn write_section(&mut self, id: u8, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let mut content = Vec::new();
+        f(&mut content)?;
+        self.push(id);
+        self.write_u32(content.len() as u32);
+        self.extend_from_slice(&content);
+        Ok(())
+    }
+
+    fn write_import_section(&mut self, imports: &[Import]) {
+        self.write_vec(imports, |buf, import| {
+            buf.write_name(&import.module);
+            buf.write_name(&import.name);
+            match &import.desc {
+                ImportDesc::Func(idx) => {
+                    buf.push(0x00);
+                    buf.write_u32(idx.get());
+                }
+                ImportDesc::Table(ty) => {
+                    buf.push(0x01);
+                    buf.write_table_type(ty);
+                }
+                ImportDesc::Memory(limits) => {
+                    buf.push(0x02);
+                    buf.write_limits(limits);
+                }
+                ImportDesc::Global(ty) => {
+                    buf.push(0x03);
+                    buf.write_global_type(ty);
+                }
+            }
+        });
+    }
+
+    fn write_function_section(&mut self, funcs: &[Func]) {
+        self.write_vec(funcs, |buf, func| buf.write_u32(func.type_id.get()));
+    }
+
+    fn write_table_section(&mut self, tables: &[Table]) {
+        self.write_vec(tables, |buf, table| buf.write_table_type(&table.0));
+    }
+
+    fn write_memory_section(&mut self, memories: &[Memory]) {
+        self.write_vec(memories, |buf, memory| buf.write_limits(&memory.0));
+    }
+
+    fn write_global_section(&mut self, globals: &[Global]) -> Result<()> {
+        self.write_u32(globals.len() as u32);
+        for global in globals {
+            self.write_global_type(&global.global_type);
+            self.write_expr(&global.init)?;
+        }
+        Ok(())
+    }
+
+    fn write_export_section(&mut self, exports: &[Export]) {
+        self.write_vec(exports, |buf, export| {
+            buf.write_name(&export.name);
+            let (kind, idx) = match &export.desc {
+                ExportDesc::Func(idx) => (0x00, idx.get()),
+                ExportDesc::Table(idx) => (0x01, idx.get()),
+                ExportDesc::Memory(idx) => (0x02, idx.get()),
+                ExportDesc::Global(idx) => (0x03, idx.get()),
+            };
+            buf.push(kind);
+            buf.write_u32(idx);
+        });
+    }
+
+    fn write_element_section(&mut self, elements: &[Element]) -> Result<()> {
+        self.write_u32(elements.len() as u32);
+        for element in elements {
+            // segments of plain function references use the compact encoding
+            // with function indices, which is what most producers emit
+            let funcs = (element.ty == RefType::Funcref)
+                .then(|| {
+                    element
+                        .init
+                        .iter()
+                        .map(|expr| match expr.instructions.as_slice() {
+                            [Instruction::RefFunc(idx)] => Some(idx.get()),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+                .flatten();
+
+            // kinds 0 and 4 imply table 0 and a funcref table
+            let mut flags = match &element.mode {
+                ElementMode::Active { table, .. }
+                    if table.get() == 0 && element.ty == RefType::Funcref =>
+                {
+                    0
+                }
+                ElementMode::Active { .. } => 2,
+                ElementMode::Passive => 1,
+                ElementMode::Declarative => 3,
+            };
+            if funcs.is_none() {
+                flags |= 4;
+            }
+            self.write_u32(flags);
+
+            if let ElementMode::Active { table, offset } = &element.mode {
+                if flags & 2 != 0 {
+                    self.write_u32(table.get());
+                }
+                self.write_expr(offset)?;
+            }
+            match funcs {
+                Some(funcs) => {
+                    if flags & 3 != 0 {
+                        self.push(0x00); // elemkind
+                    }
+                    self.write_vec(&funcs, |buf, idx| buf.write_u32(*idx));
+                }
+                None => {
+                    if flags & 3 != 0 {
+                        self.write_ref_type(element.ty);
+                    }
+                    self.write_u32(element.init.len() as u32);
+                    for expr in &element.init {
+                        self.write_expr(expr)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_code_section(&mut self, funcs: &[Func]) -> Result<()> {
+        self.write_u32(funcs.len() as u32);
+        for func in funcs {
+            if let Some(raw) = &func.raw {
+                self.write_u32(raw.bytes.len() as u32);
+                self.extend_from_slice(&raw.bytes);
+                continue;
+            }
+
+            let mut code = Vec::new();
+            code.write_vec(&func.locals, |buf, (n, ty)| {
+                buf.write_u32(*n);
+                buf.write_value_type(*ty);
+            });
+            code.write_expr(&func.body)?;
+
+            self.write_u32(code.len() as u32);
+            self.extend_from_slice(&code);
+        }
+        Ok(())
+    }
+
+    fn write_data_section(&mut self, datas: &[Data]) -> Result<()> {
+        self.write_u32(datas.len() as u32);
+        for data in datas {
+            match &data.mode {
+                DataMode::Active { memory, offset } if memory.get() == 0 => {
+                    self.write_u32(0);
+                    self.write_expr(offset)?;
+                }
+                DataMode::Active { memory, offset } => {
+                    self.write_u32(2);
+                    self.write_u32(memory.get());
+                    self.write_expr(offset)?;
+                }
+                DataMode::Passive => self.write_u32(1),
+            }
+            self.write_u32(data.init.len() as u32);
+            self.extend_from_slice(&data.init);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::prelude::*;
+use crate::core::{FuncType, GlobalType, Limits, NumType, RefType, TableType, ValueType, VecType};
+
+pub trait WriteTypeExt {
+    fn write_value_type(&mut self, ty: ValueType);
+    fn write_ref_type(&mut self, ty: RefType);
+    fn write_result_type(&mut self, types: &[ValueType]);
+    fn write_func_type(&mut self, ty: &FuncType);
+    fn write_limits(&mut self, limits: &Limits);
+    fn write_table_type(&mut self, ty: &TableType);
+    fn write_global_type(&mut self, ty: &GlobalType);
+}
+
+impl WriteTypeExt for Vec<u8> {
+    fn write_value_type(&mut self, ty: ValueType) {
+        let b = match ty {
+            ValueType::Num(NumType::I32) => 0x7f,
+            ValueType::Num(NumType::I64) => 0x7e,
+            ValueType::Num(NumType::F32) => 0x7d,
+            ValueType::Num(NumType::F64) => 0x7c,
+            ValueType::Vec(VecType::V128) => 0x7b,
+            ValueType::Ref(ty) => return self.write_ref_type(ty),
+        };
+        self.push(b);
+    }
+
+    fn write_ref_type(&mut self, ty: RefType) {
+        self.push(match ty {
+            RefType::Funcref => 0x70,
+            RefType::Externref => 0x6f,
+        });
+    }
+
+    fn write_result_type(&mut self, types: &[ValueType]) {
+        self.write_vec(types, |buf, ty| buf.write_value_type(*ty));
+    }
+
+    fn write_func_type(&mut self, ty: &FuncType) {
+        self.push(0x60);
+        self.write_result_type(&ty.params);
+        self.write_result_type(&ty.results);
+    }
+
+    fn write_limits(&mut self, limits: &Limits) {
+        match limits.max {
+            None => {
+                self.push(0x00);
+                self.write_u32(limits.min);
+            }
+            Some(max) => {
+                self.push(0x01);
+                self.write_u32(limits.min);
+                self.write_u32(max);
+            }
+        }
+    }
+
+    fn write_table_type(&mut self, ty: &TableType) {
+        self.write_ref_type(ty.elem_type);
+        self.write_limits(&ty.limits);
+    }
+
+    fn write_global_type(&mut self, ty: &GlobalType) {
+        self.write_value_type(ty.value_type);
+        self.push(ty.mutability as u8);
+    }
+}
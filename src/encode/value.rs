@@ -0,0 +1,82 @@
+use crate::core::Name;
+
+pub trait WriteValueExt {
+    fn write_unsigned_leb128(&mut self, v: u64);
+    fn write_signed_leb128(&mut self, v: i64);
+    fn write_u32(&mut self, v: u32);
+    fn write_name(&mut self, name: &Name);
+    /// Writes a vector of `items`, each written with `f`.
+    fn write_vec<T>(&mut self, items: &[T], f: impl FnMut(&mut Self, &T));
+}
+
+impl WriteValueExt for Vec<u8> {
+    fn write_unsigned_leb128(&mut self, mut v: u64) {
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.push(b);
+                return;
+            }
+            self.push(b | 0x80);
+        }
+    }
+
+    fn write_signed_leb128(&mut self, mut v: i64) {
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            // the sign bit of the last byte must match the remaining bits
+            if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+                self.push(b);
+                return;
+            }
+            self.push(b | 0x80);
+        }
+    }
+
+    fn write_u32(&mut self, v: u32) {
+        self.write_unsigned_leb128(v as u64);
+    }
+
+    fn write_name(&mut self, name: &Name) {
+        let bytes = name.as_str().as_bytes();
+        self.write_u32(bytes.len() as u32);
+        self.extend_from_slice(bytes);
+    }
+
+    fn write_vec<T>(&mut self, items: &[T], mut f: impl FnMut(&mut Self, &T)) {
+        self.write_u32(items.len() as u32);
+        for item in items {
+            f(self, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_leb128() {
+        let unsigned = |v| {
+            let mut buf = vec![];
+            buf.write_unsigned_leb128(v);
+            buf
+        };
+        let signed = |v| {
+            let mut buf = vec![];
+            buf.write_signed_leb128(v);
+            buf
+        };
+
+        assert_eq!(unsigned(0x10), [0x10]);
+        assert_eq!(unsigned(0x100), [0x80, 0x02]);
+        assert_eq!(unsigned(u32::MAX as u64), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert_eq!(signed(63), [0x3f]);
+        assert_eq!(signed(64), [0xc0, 0x00]);
+        assert_eq!(signed(-64), [0x40]);
+        assert_eq!(signed(-65), [0xbf, 0x7f]);
+        assert_eq!(signed(i32::MIN as i64), [0x80, 0x80, 0x80, 0x80, 0x78]);
+    }
+}
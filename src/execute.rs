@@ -1,13 +1,31 @@
 use crate::core::{
-    BlockType, Export, ExportDesc, Func, FuncIdx, FuncType, IBinOp, IRelOp, IUnOp, Idx,
-    Instruction, Module, NumType, TypeIdx, ValueType,
+    Data, DataMode, ElementMode, ExportDesc, Expression, Func, FuncIdx, FuncType, GlobalType, Idx,
+    Import, ImportDesc, Instruction, Limits, MemoryType, Module, Name, NumType, RawBody, RefType,
+    Table, TableType, TypeIdx, ValueType,
 };
-use anyhow::{bail, ensure, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
+use crate::decode::decode_body;
+use crate::validate::{validate, FuncValidator};
+use anyhow::{bail, ensure, Context as _, Result};
+use std::any::Any;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
+mod compile;
+mod config;
+mod linker;
+mod memory;
+mod numeric;
+mod register;
 mod stack;
-use stack::{Frame, Label, Stack};
+mod table;
+mod trap;
+use compile::{Code, DropKeep, LoadKind, Operation, StoreKind};
+pub use config::{Config, Engine};
+pub use linker::{Caller, Linker};
+pub use memory::{MemoryInstance, PAGE_SIZE};
+use stack::{Frame, Stack};
+pub use table::TableInstance;
+pub use trap::{FrameInfo, Trap, TrapKind};
 
 pub struct Address<T> {
     pub address: u32,
@@ -23,22 +41,164 @@ impl<T> Address<T> {
     }
 }
 
+impl<T> Clone for Address<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Address<T> {}
+
+impl<T> std::fmt::Debug for Address<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Address({})", self.address)
+    }
+}
+
+impl<T> PartialEq for Address<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<T> Eq for Address<T> {}
+
 pub struct FuncAddr;
+pub struct TableAddr;
+pub struct MemAddr;
+pub struct GlobalAddr;
+pub struct ElemAddr;
+pub struct DataAddr;
+pub struct ExternAddr;
+
+/// An entity that can be exported by a module instance or provided to a
+/// module as an import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extern {
+    Func(Address<FuncAddr>),
+    Table(Address<TableAddr>),
+    Memory(Address<MemAddr>),
+    Global(Address<GlobalAddr>),
+}
 
+#[derive(Clone, Debug)]
+pub struct ExportInstance {
+    pub name: String,
+    pub value: Extern,
+}
+
+#[derive(Default)]
 pub struct ModuleInstance {
     types: Vec<Rc<FuncType>>,
     func_addrs: Vec<Address<FuncAddr>>,
+    table_addrs: Vec<Address<TableAddr>>,
+    mem_addrs: Vec<Address<MemAddr>>,
+    global_addrs: Vec<Address<GlobalAddr>>,
+    elem_addrs: Vec<Address<ElemAddr>>,
+    data_addrs: Vec<Address<DataAddr>>,
+    exports: Vec<ExportInstance>,
+    func_names: BTreeMap<u32, Name>,
 }
 
 impl ModuleInstance {
     pub fn get_type(&self, idx: Idx<TypeIdx>) -> Rc<FuncType> {
         self.types[idx.get() as usize].clone()
     }
+
+    /// Returns the name of the function at `idx` from the `name` section of
+    /// the module, if any.
+    pub fn func_name(&self, idx: u32) -> Option<&str> {
+        self.func_names.get(&idx).map(Name::as_str)
+    }
+
+    pub fn exports(&self) -> &[ExportInstance] {
+        &self.exports
+    }
+
+    pub fn export(&self, name: &str) -> Result<Extern> {
+        match self.exports.iter().find(|e| e.name == name) {
+            Some(e) => Ok(e.value),
+            None => bail!("export not found: {}", name),
+        }
+    }
+}
+
+pub type HostFunc = Rc<dyn Fn(&mut Caller, &[Value]) -> Result<Vec<Value>>>;
+
+pub enum FuncInstance {
+    Wasm {
+        ty: Rc<FuncType>,
+        module: Rc<ModuleInstance>,
+        body: Body,
+    },
+    Host {
+        ty: Rc<FuncType>,
+        func: HostFunc,
+    },
 }
 
-pub struct FuncInstance {
-    ty: Rc<FuncType>,
-    code: Func,
+/// A wasm function body, lowered for the engine of the store that owns it.
+pub enum Body {
+    Stack(Rc<Code>),
+    Register(Rc<register::Code>),
+    /// A body whose decoding was deferred, which is decoded, validated and
+    /// lowered when the function is first called.
+    Lazy(Box<LazyBody>),
+}
+
+pub struct LazyBody {
+    type_id: Idx<TypeIdx>,
+    raw: RawBody,
+    /// Index of the function in the function index space of its module.
+    idx: u32,
+    module: Rc<LazyModule>,
+}
+
+/// What the functions of a module instance with deferred bodies need to be
+/// lowered.
+struct LazyModule {
+    validator: FuncValidator,
+    /// Types of the function index space of the module.
+    funcs: Vec<Rc<FuncType>>,
+    data_count: bool,
+}
+
+impl FuncInstance {
+    pub fn ty(&self) -> &FuncType {
+        match self {
+            FuncInstance::Wasm { ty, .. } | FuncInstance::Host { ty, .. } => ty,
+        }
+    }
+}
+
+pub struct GlobalInstance {
+    ty: GlobalType,
+    value: Value,
+}
+
+impl GlobalInstance {
+    pub fn ty(&self) -> &GlobalType {
+        &self.ty
+    }
+
+    pub fn get(&self) -> Value {
+        self.value
+    }
+
+    pub fn set(&mut self, value: Value) -> Result<()> {
+        ensure!(self.ty.mutability, "global is immutable");
+        ensure!(value.get_type() == self.ty.value_type, "type mismatch");
+        self.value = value;
+        Ok(())
+    }
+}
+
+pub struct ElemInstance {
+    elem: Vec<Value>,
+}
+
+pub struct DataInstance {
+    data: Vec<u8>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -47,6 +207,8 @@ pub enum Value {
     I64(i64),
     F32(f32),
     F64(f64),
+    FuncRef(Option<Address<FuncAddr>>),
+    ExternRef(Option<Address<ExternAddr>>),
 }
 
 impl Value {
@@ -56,451 +218,1074 @@ impl Value {
             Value::I64(_) => ValueType::Num(NumType::I64),
             Value::F32(_) => ValueType::Num(NumType::F32),
             Value::F64(_) => ValueType::Num(NumType::F64),
+            Value::FuncRef(_) => ValueType::Ref(RefType::Funcref),
+            Value::ExternRef(_) => ValueType::Ref(RefType::Externref),
         }
     }
-}
 
-enum ExecuteLabelRes {
-    Continue,
-    Branch(u32),
+    /// Returns the default value of `ty`, or `None` for vector types, whose
+    /// values cannot be represented.
+    pub fn default_for(ty: ValueType) -> Option<Self> {
+        let value = match ty {
+            ValueType::Num(NumType::I32) => Value::I32(0),
+            ValueType::Num(NumType::I64) => Value::I64(0),
+            ValueType::Num(NumType::F32) => Value::F32(0.0),
+            ValueType::Num(NumType::F64) => Value::F64(0.0),
+            ValueType::Ref(ty) => Value::null(ty),
+            ValueType::Vec(_) => return None,
+        };
+        Some(value)
+    }
+
+    pub fn null(ty: RefType) -> Self {
+        match ty {
+            RefType::Funcref => Value::FuncRef(None),
+            RefType::Externref => Value::ExternRef(None),
+        }
+    }
+
+    /// Returns the untagged representation used on the operand stack. Null
+    /// references are zero, so zeroed slots hold the default of every type.
+    fn to_raw(self) -> u64 {
+        match self {
+            Value::I32(v) => v as u32 as u64,
+            Value::I64(v) => v as u64,
+            Value::F32(v) => v.to_bits() as u64,
+            Value::F64(v) => v.to_bits(),
+            Value::FuncRef(r) => r.map_or(0, |addr| addr.address as u64 + 1),
+            Value::ExternRef(r) => r.map_or(0, |addr| addr.address as u64 + 1),
+        }
+    }
+
+    fn from_raw(raw: u64, ty: ValueType) -> Self {
+        fn addr<T>(raw: u64) -> Option<Address<T>> {
+            (raw != 0).then(|| Address::new((raw - 1) as u32))
+        }
+        match ty {
+            ValueType::Num(NumType::I32) => Value::I32(raw as i32),
+            ValueType::Num(NumType::I64) => Value::I64(raw as i64),
+            ValueType::Num(NumType::F32) => Value::F32(f32::from_bits(raw as u32)),
+            ValueType::Num(NumType::F64) => Value::F64(f64::from_bits(raw)),
+            ValueType::Ref(RefType::Funcref) => Value::FuncRef(addr(raw)),
+            ValueType::Ref(RefType::Externref) => Value::ExternRef(addr(raw)),
+            ValueType::Vec(_) => unreachable!("vector types are rejected by validation"),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct Store {
     funcs: Vec<FuncInstance>,
-    exports: Vec<Export>,
+    tables: Vec<TableInstance>,
+    mems: Vec<MemoryInstance>,
+    globals: Vec<GlobalInstance>,
+    elems: Vec<ElemInstance>,
+    datas: Vec<DataInstance>,
+    externs: Vec<Box<dyn Any>>,
+    config: Config,
+    /// Frames, value slots and number of the invocations waiting for a host
+    /// function to return, which count against the limits of the invocations
+    /// made by the host function.
+    depth: usize,
+    slots: usize,
+    host_depth: usize,
 }
 
 impl Store {
-    fn alloc_func(&mut self, func: Func, module: &ModuleInstance) -> Address<FuncAddr> {
-        let ty = module.get_type(func.type_id);
-        let i = FuncInstance { ty, code: func };
+    pub fn new(config: Config) -> Self {
+        Store {
+            config,
+            ..Default::default()
+        }
+    }
+
+    fn alloc_func(
+        &mut self,
+        ty: Rc<FuncType>,
+        module: &Rc<ModuleInstance>,
+        body: Body,
+    ) -> Address<FuncAddr> {
+        let i = FuncInstance::Wasm {
+            ty,
+            module: module.clone(),
+            body,
+        };
         self.funcs.push(i);
         Address::new(self.funcs.len() as u32 - 1)
     }
 
-    fn alloc_module(&mut self, module: Module) -> ModuleInstance {
-        let mut instance = ModuleInstance {
-            types: module.types.into_iter().map(Rc::new).collect(),
-            func_addrs: Vec::with_capacity(module.funcs.len()),
+    /// Returns the number of frames left to the current invocation.
+    fn max_depth(&self) -> usize {
+        self.config.max_call_depth.saturating_sub(self.depth)
+    }
+
+    /// Returns the number of value slots left to the current invocation.
+    fn max_slots(&self) -> usize {
+        self.config.max_stack_size.saturating_sub(self.slots)
+    }
+
+    /// Prepares `code` for the engine of the store.
+    fn lower(&self, code: Code) -> Body {
+        match self.config.engine {
+            Engine::Stack => Body::Stack(Rc::new(code)),
+            Engine::Register => Body::Register(Rc::new(register::translate(&code))),
+        }
+    }
+
+    /// Decodes, validates and lowers the body of the function at `addr` if it
+    /// is deferred, so that it can be called.
+    fn prepare(&mut self, addr: Address<FuncAddr>) -> Result<()> {
+        let body = match &self.funcs[addr.address as usize] {
+            FuncInstance::Wasm {
+                body: Body::Lazy(lazy),
+                module,
+                ..
+            } => {
+                let (locals, body, offsets) = decode_body(&lazy.raw, lazy.module.data_count)?;
+                let func = Func {
+                    type_id: lazy.type_id,
+                    locals,
+                    body,
+                    raw: None,
+                };
+                lazy.module
+                    .validator
+                    .validate(&func)
+                    .with_context(|| format!("invalid function {}", lazy.idx))?;
+                let code = compile::compile(&func, &offsets, lazy.idx, module, &lazy.module.funcs)?;
+                self.lower(code)
+            }
+            _ => return Ok(()),
         };
+        if let FuncInstance::Wasm { body: b, .. } = &mut self.funcs[addr.address as usize] {
+            *b = body;
+        }
+        Ok(())
+    }
 
-        for func in module.funcs {
-            let addr = self.alloc_func(func, &instance);
-            instance.func_addrs.push(addr);
+    fn func_type(&self, addr: Address<FuncAddr>) -> Rc<FuncType> {
+        match &self.funcs[addr.address as usize] {
+            FuncInstance::Wasm { ty, .. } | FuncInstance::Host { ty, .. } => ty.clone(),
         }
+    }
+
+    /// Creates a host function that can be passed as an import.
+    pub fn new_func(
+        &mut self,
+        ty: FuncType,
+        func: impl Fn(&mut Caller, &[Value]) -> Result<Vec<Value>> + 'static,
+    ) -> Address<FuncAddr> {
+        self.funcs.push(FuncInstance::Host {
+            ty: Rc::new(ty),
+            func: Rc::new(func),
+        });
+        Address::new(self.funcs.len() as u32 - 1)
+    }
+
+    /// Creates a table outside of any module, with all elements set to `init`.
+    pub fn new_table(&mut self, ty: TableType, init: Value) -> Result<Address<TableAddr>> {
+        ensure!(
+            init.get_type() == ValueType::Ref(ty.elem_type),
+            "type mismatch"
+        );
+        self.tables.push(TableInstance::new(&ty, init));
+        Ok(Address::new(self.tables.len() as u32 - 1))
+    }
+
+    /// Creates a memory outside of any module.
+    pub fn new_memory(&mut self, ty: MemoryType) -> Result<Address<MemAddr>> {
+        Ok(self.alloc_mem(MemoryInstance::new(&ty)?))
+    }
+
+    /// Wraps a host object in a non-null `externref`. Guest code can pass the
+    /// reference around and store it in tables but cannot inspect it.
+    pub fn new_externref<T: Any>(&mut self, object: T) -> Value {
+        self.externs.push(Box::new(object));
+        Value::ExternRef(Some(Address::new(self.externs.len() as u32 - 1)))
+    }
+
+    /// Returns the host object behind `value`, or `None` if it is not a
+    /// non-null `externref` to an object of type `T`.
+    pub fn externref<T: Any>(&self, value: Value) -> Option<&T> {
+        let Value::ExternRef(Some(addr)) = value else {
+            return None;
+        };
+        self.externs[addr.address as usize].downcast_ref()
+    }
+
+    pub fn externref_mut<T: Any>(&mut self, value: Value) -> Option<&mut T> {
+        let Value::ExternRef(Some(addr)) = value else {
+            return None;
+        };
+        self.externs[addr.address as usize].downcast_mut()
+    }
+
+    fn alloc_table(&mut self, table: &Table) -> Address<TableAddr> {
+        let init = Value::null(table.0.elem_type);
+        self.tables.push(TableInstance::new(&table.0, init));
+        Address::new(self.tables.len() as u32 - 1)
+    }
 
-        for e in module.exports {
-            self.exports.push(e);
+    fn alloc_mem(&mut self, mem: MemoryInstance) -> Address<MemAddr> {
+        self.mems.push(mem);
+        Address::new(self.mems.len() as u32 - 1)
+    }
+
+    fn alloc_global(&mut self, ty: GlobalType, value: Value) -> Address<GlobalAddr> {
+        self.globals.push(GlobalInstance { ty, value });
+        Address::new(self.globals.len() as u32 - 1)
+    }
+
+    /// Creates a global outside of any module, e.g. to be passed as an import.
+    pub fn new_global(&mut self, ty: GlobalType, value: Value) -> Result<Address<GlobalAddr>> {
+        ensure!(value.get_type() == ty.value_type, "type mismatch");
+        Ok(self.alloc_global(ty, value))
+    }
+
+    fn alloc_elem(&mut self, elem: Vec<Value>) -> Address<ElemAddr> {
+        self.elems.push(ElemInstance { elem });
+        Address::new(self.elems.len() as u32 - 1)
+    }
+
+    fn alloc_data(&mut self, data: Data) -> Address<DataAddr> {
+        self.datas.push(DataInstance { data: data.init });
+        Address::new(self.datas.len() as u32 - 1)
+    }
+
+    /// Checks `externs` against the module's import descriptions and records
+    /// them in the index spaces of `instance`.
+    fn resolve_imports(
+        &self,
+        instance: &mut ModuleInstance,
+        imports: &[Import],
+        externs: &[Extern],
+    ) -> Result<()> {
+        ensure!(
+            imports.len() == externs.len(),
+            "expected {} imports, got {}",
+            imports.len(),
+            externs.len()
+        );
+        for (import, ext) in imports.iter().zip(externs) {
+            match (&import.desc, *ext) {
+                (ImportDesc::Func(ty), Extern::Func(addr)) => {
+                    ensure!(
+                        *self.funcs[addr.address as usize].ty() == *instance.get_type(*ty),
+                        "incompatible import type"
+                    );
+                    instance.func_addrs.push(addr);
+                }
+                (ImportDesc::Table(ty), Extern::Table(addr)) => {
+                    let actual = self.tables[addr.address as usize].ty();
+                    ensure!(
+                        actual.elem_type == ty.elem_type
+                            && match_limits(&actual.limits, &ty.limits),
+                        "incompatible import type"
+                    );
+                    instance.table_addrs.push(addr);
+                }
+                (ImportDesc::Memory(ty), Extern::Memory(addr)) => {
+                    let mem = &self.mems[addr.address as usize];
+                    let actual = Limits {
+                        min: mem.size(),
+                        max: mem.max(),
+                    };
+                    ensure!(match_limits(&actual, ty), "incompatible import type");
+                    instance.mem_addrs.push(addr);
+                }
+                (ImportDesc::Global(ty), Extern::Global(addr)) => {
+                    let actual = self.globals[addr.address as usize].ty();
+                    ensure!(
+                        actual.mutability == ty.mutability && actual.value_type == ty.value_type,
+                        "incompatible import type"
+                    );
+                    instance.global_addrs.push(addr);
+                }
+                _ => bail!("incompatible import type"),
+            }
         }
 
-        instance
+        Ok(())
     }
 
-    pub fn instantiate(&mut self, module: Module) {
-        self.alloc_module(module);
+    pub fn instantiate(&mut self, module: Module) -> Result<Rc<ModuleInstance>> {
+        self.instantiate_with_imports(module, &[])
     }
 
-    pub fn invoke(&self, name: &str, args: Vec<Value>) -> Result<Vec<Value>> {
-        for e in &self.exports {
-            if e.name.as_str() == name {
-                if let ExportDesc::Func(idx) = e.desc {
-                    return self.execute(idx, args);
+    /// Instantiates `module`, resolving its imports to `imports` in the order
+    /// in which they are declared.
+    pub fn instantiate_with_imports(
+        &mut self,
+        mut module: Module,
+        imports: &[Extern],
+    ) -> Result<Rc<ModuleInstance>> {
+        validate(&module)?;
+
+        let mut instance = ModuleInstance {
+            types: module.types.iter().cloned().map(Rc::new).collect(),
+            func_names: module.names.funcs.clone(),
+            ..Default::default()
+        };
+        self.resolve_imports(&mut instance, &module.imports, imports)?;
+        let func_types = instance
+            .func_addrs
+            .iter()
+            .map(|addr| self.func_type(*addr))
+            .chain(module.funcs.iter().map(|f| instance.get_type(f.type_id)))
+            .collect::<Vec<_>>();
+
+        // Function instances refer to their module instance, so the addresses
+        // of everything the module defines are assigned before allocation.
+        fn next<T>(len: usize, n: usize) -> impl Iterator<Item = Address<T>> {
+            (len..len + n).map(|i| Address::new(i as u32))
+        }
+        let func_addrs = next(self.funcs.len(), module.funcs.len());
+        instance.func_addrs.extend(func_addrs);
+
+        // Constant expressions are evaluated against an auxiliary instance that
+        // only knows the imported globals and the functions.
+        let aux = Rc::new(ModuleInstance {
+            types: instance.types.clone(),
+            func_addrs: instance.func_addrs.clone(),
+            global_addrs: instance.global_addrs.clone(),
+            ..Default::default()
+        });
+        let globals = module
+            .globals
+            .iter()
+            .map(|global| self.eval_const_expr(&aux, &global.init))
+            .collect::<Result<Vec<_>>>()?;
+        let elems = module
+            .elements
+            .iter()
+            .map(|elem| {
+                elem.init
+                    .iter()
+                    .map(|expr| self.eval_const_expr(&aux, expr))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        instance
+            .table_addrs
+            .extend(next(self.tables.len(), module.tables.len()));
+        instance
+            .mem_addrs
+            .extend(next(self.mems.len(), module.memories.len()));
+        instance
+            .global_addrs
+            .extend(next(self.globals.len(), module.globals.len()));
+        instance.elem_addrs = next(self.elems.len(), module.elements.len()).collect();
+        instance.data_addrs = next(self.datas.len(), module.datas.len()).collect();
+        instance.exports = module
+            .exports
+            .iter()
+            .map(|e| {
+                let value = match e.desc {
+                    ExportDesc::Func(idx) => Extern::Func(instance.func_addrs[idx.get() as usize]),
+                    ExportDesc::Table(idx) => {
+                        Extern::Table(instance.table_addrs[idx.get() as usize])
+                    }
+                    ExportDesc::Memory(idx) => {
+                        Extern::Memory(instance.mem_addrs[idx.get() as usize])
+                    }
+                    ExportDesc::Global(idx) => {
+                        Extern::Global(instance.global_addrs[idx.get() as usize])
+                    }
+                };
+                ExportInstance {
+                    name: e.name.as_str().to_string(),
+                    value,
+                }
+            })
+            .collect();
+        let instance = Rc::new(instance);
+
+        // Function bodies are lowered against the predicted addresses before
+        // anything is allocated. Deferred bodies are lowered on first call.
+        let imported = func_types.len() - module.funcs.len();
+        let lazy = module.funcs.iter().any(|f| f.raw.is_some()).then(|| {
+            Rc::new(LazyModule {
+                validator: FuncValidator::new(&module),
+                funcs: func_types.clone(),
+                data_count: module.data_count.is_some(),
+            })
+        });
+        let bodies = module
+            .funcs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, func)| {
+                let idx = (imported + i) as u32;
+                match (func.raw.take(), &lazy) {
+                    (Some(raw), Some(module)) => Ok(Body::Lazy(Box::new(LazyBody {
+                        type_id: func.type_id,
+                        raw,
+                        idx,
+                        module: module.clone(),
+                    }))),
+                    _ => Ok(self.lower(compile::compile(func, &[], idx, &instance, &func_types)?)),
                 }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let Module {
+            funcs,
+            tables,
+            memories,
+            globals: global_types,
+            elements,
+            datas,
+            start,
+            ..
+        } = module;
+        // creating memories is the only allocation that can fail, so it is
+        // done before anything is added to the store
+        let mems = memories
+            .iter()
+            .map(|mem| MemoryInstance::new(&mem.0))
+            .collect::<Result<Vec<_>>>()?;
+        for (func, body) in funcs.iter().zip(bodies) {
+            self.alloc_func(instance.get_type(func.type_id), &instance, body);
+        }
+        for table in &tables {
+            self.alloc_table(table);
+        }
+        for mem in mems {
+            self.alloc_mem(mem);
+        }
+        for (global, value) in global_types.into_iter().zip(globals) {
+            self.alloc_global(global.global_type, value);
+        }
+        for elem in elems {
+            self.alloc_elem(elem);
+        }
+        let data_modes = datas
+            .iter()
+            .map(|data| data.mode.clone())
+            .collect::<Vec<_>>();
+        for data in datas {
+            self.alloc_data(data);
+        }
+
+        for (elem, addr) in elements.into_iter().zip(instance.elem_addrs.clone()) {
+            match elem.mode {
+                ElementMode::Active { table, offset } => {
+                    let Value::I32(offset) = self.eval_const_expr(&instance, &offset)? else {
+                        bail!("expected i32 offset");
+                    };
+                    let table = instance.table_addrs[table.get() as usize];
+                    let n = elem.init.len() as u32;
+                    self.init_table(table, addr, offset as u32, 0, n)?;
+                    self.drop_elem(addr);
+                }
+                ElementMode::Declarative => self.drop_elem(addr),
+                ElementMode::Passive => {}
+            }
+        }
+
+        for (mode, addr) in data_modes.into_iter().zip(instance.data_addrs.clone()) {
+            if let DataMode::Active { memory, offset } = mode {
+                let Value::I32(offset) = self.eval_const_expr(&instance, &offset)? else {
+                    bail!("expected i32 offset");
+                };
+                let memory = instance.mem_addrs[memory.get() as usize];
+                let n = self.datas[addr.address as usize].data.len() as u32;
+                self.init_memory(memory, addr, offset as u32, 0, n)?;
+                self.drop_data(addr);
             }
         }
 
-        bail!("function not found: {}", name)
+        if let Some(start) = start {
+            self.execute(&instance, start, vec![])?;
+        }
+
+        Ok(instance)
     }
 
-    pub fn execute(&self, idx: Idx<FuncIdx>, args: Vec<Value>) -> Result<Vec<Value>> {
-        let mut stack = Stack::default();
-        let res = self.execute_func(&mut stack, idx, args);
-        ensure!(stack.is_empty(), "stack is not empty");
-        res
+    fn eval_const_expr(&self, module: &ModuleInstance, expr: &Expression) -> Result<Value> {
+        let mut stack = vec![];
+        for instr in &expr.instructions {
+            let value = match instr {
+                Instruction::I32Const(v) => Value::I32(*v),
+                Instruction::I64Const(v) => Value::I64(*v),
+                Instruction::F32Const(v) => Value::F32(*v),
+                Instruction::F64Const(v) => Value::F64(*v),
+                Instruction::RefNull(ty) => Value::null(*ty),
+                Instruction::RefFunc(idx) => {
+                    Value::FuncRef(Some(module.func_addrs[idx.get() as usize]))
+                }
+                Instruction::GlobalGet(idx) => {
+                    let addr = module.global_addrs[idx.get() as usize];
+                    self.globals[addr.address as usize].get()
+                }
+                _ => bail!("constant expression required"),
+            };
+            stack.push(value);
+        }
+        match stack.pop() {
+            Some(value) => Ok(value),
+            None => bail!("type mismatch"),
+        }
     }
 
-    fn execute_func<'a>(
-        &'a self,
-        stack: &mut Stack<'a>,
+    fn init_memory(
+        &mut self,
+        memory: Address<MemAddr>,
+        data: Address<DataAddr>,
+        dst: u32,
+        src: u32,
+        n: u32,
+    ) -> Result<()> {
+        let mem = &mut self.mems[memory.address as usize];
+        let data = &self.datas[data.address as usize];
+
+        let src = src as usize;
+        ensure!(
+            src + n as usize <= data.data.len(),
+            Trap::new(TrapKind::OutOfBoundsMemory)
+        );
+        mem.store(dst as u64, &data.data[src..src + n as usize])
+    }
+
+    fn init_table(
+        &mut self,
+        table: Address<TableAddr>,
+        elem: Address<ElemAddr>,
+        dst: u32,
+        src: u32,
+        n: u32,
+    ) -> Result<()> {
+        let table = &mut self.tables[table.address as usize];
+        let elem = &self.elems[elem.address as usize];
+
+        let src = src as usize;
+        ensure!(
+            src + n as usize <= elem.elem.len(),
+            Trap::new(TrapKind::OutOfBoundsTable)
+        );
+        table
+            .elements_mut(dst, n)?
+            .copy_from_slice(&elem.elem[src..src + n as usize]);
+        Ok(())
+    }
+
+    fn drop_elem(&mut self, elem: Address<ElemAddr>) {
+        self.elems[elem.address as usize].elem = Vec::new();
+    }
+
+    fn drop_data(&mut self, data: Address<DataAddr>) {
+        self.datas[data.address as usize].data = Vec::new();
+    }
+
+    pub fn memory(&self, instance: &ModuleInstance, name: &str) -> Result<&MemoryInstance> {
+        let Extern::Memory(addr) = instance.export(name)? else {
+            bail!("memory not found: {}", name);
+        };
+        Ok(&self.mems[addr.address as usize])
+    }
+
+    pub fn memory_mut(
+        &mut self,
+        instance: &ModuleInstance,
+        name: &str,
+    ) -> Result<&mut MemoryInstance> {
+        let Extern::Memory(addr) = instance.export(name)? else {
+            bail!("memory not found: {}", name);
+        };
+        Ok(&mut self.mems[addr.address as usize])
+    }
+
+    pub fn table(&self, instance: &ModuleInstance, name: &str) -> Result<&TableInstance> {
+        let Extern::Table(addr) = instance.export(name)? else {
+            bail!("table not found: {}", name);
+        };
+        Ok(&self.tables[addr.address as usize])
+    }
+
+    pub fn table_mut(
+        &mut self,
+        instance: &ModuleInstance,
+        name: &str,
+    ) -> Result<&mut TableInstance> {
+        let Extern::Table(addr) = instance.export(name)? else {
+            bail!("table not found: {}", name);
+        };
+        Ok(&mut self.tables[addr.address as usize])
+    }
+
+    pub fn global(&self, instance: &ModuleInstance, name: &str) -> Result<&GlobalInstance> {
+        let Extern::Global(addr) = instance.export(name)? else {
+            bail!("global not found: {}", name);
+        };
+        Ok(&self.globals[addr.address as usize])
+    }
+
+    pub fn global_mut(
+        &mut self,
+        instance: &ModuleInstance,
+        name: &str,
+    ) -> Result<&mut GlobalInstance> {
+        let Extern::Global(addr) = instance.export(name)? else {
+            bail!("global not found: {}", name);
+        };
+        Ok(&mut self.globals[addr.address as usize])
+    }
+
+    pub fn invoke(
+        &mut self,
+        instance: &ModuleInstance,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        let Ok(Extern::Func(addr)) = instance.export(name) else {
+            bail!("function not found: {}", name);
+        };
+        self.call_func(addr, args)
+    }
+
+    pub fn execute(
+        &mut self,
+        instance: &ModuleInstance,
         idx: Idx<FuncIdx>,
-        locals: Vec<Value>,
+        args: Vec<Value>,
     ) -> Result<Vec<Value>> {
-        let func = &self.funcs[idx.get() as usize];
+        self.call_func(instance.func_addrs[idx.get() as usize], args)
+    }
 
-        let mut frame = Frame::new(locals);
-        self.execute_label(stack, &mut frame, &func.code.body.instructions)?;
+    pub fn call_func(&mut self, addr: Address<FuncAddr>, args: Vec<Value>) -> Result<Vec<Value>> {
+        let ty = self.func_type(addr);
+        ensure!(
+            args.iter()
+                .map(Value::get_type)
+                .eq(ty.params.iter().copied()),
+            "type mismatch"
+        );
+
+        self.prepare(addr)?;
+        let (body, module) = match &self.funcs[addr.address as usize] {
+            FuncInstance::Wasm { body, module, .. } => (body, module.clone()),
+            FuncInstance::Host { func, .. } => {
+                let func = func.clone();
+                return self.call_host(&func, &ty, Rc::default(), &args);
+            }
+        };
 
-        let values = stack.pop_and_check_values(&func.ty.results)?;
+        ensure!(
+            self.max_depth() > 0 && self.host_depth <= self.config.max_host_depth,
+            Trap::new(TrapKind::StackOverflow)
+        );
+        match body {
+            Body::Stack(code) => {
+                let code = code.clone();
+                let mut stack = Stack::new(self.max_slots());
+                stack.push_values(&args);
+                let frame = Frame::enter(&mut stack, code, module)?;
+                self.run(&mut stack, frame)?;
+                Ok(stack.pop_values(&ty.results))
+            }
+            Body::Register(code) => {
+                let code = code.clone();
+                let mut regs = args.iter().copied().map(Value::to_raw).collect();
+                self.run_register(&mut regs, code, module)?;
+                let results = regs.iter().zip(&ty.results);
+                Ok(results
+                    .map(|(raw, ty)| Value::from_raw(*raw, *ty))
+                    .collect())
+            }
+            Body::Lazy(_) => unreachable!("function body not prepared"),
+        }
+    }
+
+    fn call_host(
+        &mut self,
+        func: &HostFunc,
+        ty: &FuncType,
+        caller: Rc<ModuleInstance>,
+        args: &[Value],
+    ) -> Result<Vec<Value>> {
+        // a trap in wasm code called by the host function keeps its kind, and
+        // its backtrace is extended by the callers of the host function
+        let values = func(&mut Caller::new(self, caller), args).map_err(|err| {
+            if err.is::<Trap>() {
+                err
+            } else {
+                Trap::host(err).into()
+            }
+        })?;
+        ensure!(
+            values
+                .iter()
+                .map(Value::get_type)
+                .eq(ty.results.iter().copied()),
+            "type mismatch"
+        );
         Ok(values)
     }
 
-    fn get_block_return_type(&self, block_type: &BlockType) -> Vec<ValueType> {
-        match block_type {
-            BlockType::ValType(op) => op.iter().copied().collect(),
-            _ => todo!(),
-        }
+    /// Calls a host function from an invocation with `depth` frames using
+    /// `slots` value slots.
+    fn call_host_nested(
+        &mut self,
+        (depth, slots): (usize, usize),
+        func: &HostFunc,
+        ty: &FuncType,
+        caller: Rc<ModuleInstance>,
+        args: &[Value],
+    ) -> Result<Vec<Value>> {
+        self.depth += depth;
+        self.slots += slots;
+        self.host_depth += 1;
+        let result = self.call_host(func, ty, caller, args);
+        self.depth -= depth;
+        self.slots -= slots;
+        self.host_depth -= 1;
+        result
     }
 
-    fn execute_label<'a>(
-        &'a self,
-        stack: &mut Stack<'a>,
+    /// Calls the function at `addr` from `frame`, with the arguments on top of
+    /// `stack`. Host functions run to completion, while wasm functions become
+    /// the current frame.
+    fn call(
+        &mut self,
+        stack: &mut Stack,
+        frames: &mut Vec<Frame>,
         frame: &mut Frame,
-        instructions: &'a Vec<Instruction>,
-    ) -> Result<ExecuteLabelRes> {
-        for instr in instructions {
-            match instr {
-                // control instructions
-                Instruction::Nop => {}
-                Instruction::Unreachable => bail!("unreachable"),
-                Instruction::Block {
-                    block_type,
-                    instructions,
-                } => {
-                    let iter = self.get_block_return_type(block_type);
-                    let label = Label::new(iter.len(), instructions);
-                    stack.push_label(label);
-
-                    let l = self.execute_label(stack, frame, instructions)?;
-
-                    let values = stack.pop_and_check_values(&iter)?;
-
-                    stack.pop_label()?;
-                    for v in values {
-                        stack.push_value(v);
-                    }
-
-                    match l {
-                        ExecuteLabelRes::Continue => {}
-                        ExecuteLabelRes::Branch(l) => {
-                            if l != 0 {
-                                return Ok(ExecuteLabelRes::Branch(l - 1));
-                            }
-                        }
-                    }
-                }
-                Instruction::Loop {
-                    block_type,
-                    instructions,
-                } => {
-                    let ty = self.get_block_return_type(block_type);
-
-                    loop {
-                        let label = Label::new(ty.len(), instructions);
-                        stack.push_label(label);
-
-                        let l = self.execute_label(stack, frame, instructions)?;
-
-                        let values = stack.pop_and_check_values(&ty)?;
-
-                        stack.pop_label()?;
-                        for v in values {
-                            stack.push_value(v);
-                        }
-
-                        match l {
-                            ExecuteLabelRes::Continue => {
-                                break;
-                            }
-                            ExecuteLabelRes::Branch(l) => {
-                                if l != 0 {
-                                    return Ok(ExecuteLabelRes::Branch(l - 1));
-                                }
-                            }
-                        }
-                    }
-                }
-                Instruction::If {
-                    block_type,
-                    instructions,
-                    else_instructions,
-                } => {
-                    let b = stack.pop_i32()?;
-                    let instr = if b != 0 {
-                        instructions
-                    } else {
-                        else_instructions
-                    };
-
-                    let iter = self.get_block_return_type(block_type);
-                    let label = Label::new(iter.len(), instr);
-                    stack.push_label(label);
+        addr: Address<FuncAddr>,
+    ) -> Result<()> {
+        self.prepare(addr)?;
+        match &self.funcs[addr.address as usize] {
+            FuncInstance::Wasm {
+                body: Body::Stack(code),
+                module,
+                ..
+            } => {
+                ensure!(
+                    frames.len() + 1 < self.max_depth(),
+                    Trap::new(TrapKind::StackOverflow)
+                );
+                let callee = Frame::enter(stack, code.clone(), module.clone())?;
+                frames.push(std::mem::replace(frame, callee));
+            }
+            FuncInstance::Wasm { .. } => unreachable!("function lowered for another engine"),
+            FuncInstance::Host { ty, func } => {
+                let (ty, func) = (ty.clone(), func.clone());
+                let args = stack.pop_values(&ty.params);
+                let usage = (frames.len() + 1, stack.len());
+                let values =
+                    self.call_host_nested(usage, &func, &ty, frame.module.clone(), &args)?;
+                stack.push_values(&values);
+            }
+        }
+        Ok(())
+    }
 
-                    let l = self.execute_label(stack, frame, instr)?;
+    /// Loads a value of the given kind from the memory at `addr + offset`,
+    /// returning its untagged representation.
+    fn load(&self, kind: LoadKind, mem: Address<MemAddr>, addr: u32, offset: u32) -> Result<u64> {
+        let mem = &self.mems[mem.address as usize];
+        let addr = addr as u64 + offset as u64;
+        let v = match kind {
+            LoadKind::I32Load => u32::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load => u64::from_le_bytes(mem.load(addr)?),
+            LoadKind::F32Load => u32::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::F64Load => u64::from_le_bytes(mem.load(addr)?),
+            LoadKind::I32Load8S => i8::from_le_bytes(mem.load(addr)?) as u32 as u64,
+            LoadKind::I32Load8U => u8::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I32Load16S => i16::from_le_bytes(mem.load(addr)?) as u32 as u64,
+            LoadKind::I32Load16U => u16::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load8S => i8::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load8U => u8::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load16S => i16::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load16U => u16::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load32S => i32::from_le_bytes(mem.load(addr)?) as u64,
+            LoadKind::I64Load32U => u32::from_le_bytes(mem.load(addr)?) as u64,
+        };
+        Ok(v)
+    }
 
-                    let values = stack.pop_and_check_values(&iter)?;
+    /// Stores the untagged value `v` as the given kind to the memory at
+    /// `addr + offset`.
+    fn store(
+        &mut self,
+        kind: StoreKind,
+        mem: Address<MemAddr>,
+        addr: u32,
+        offset: u32,
+        v: u64,
+    ) -> Result<()> {
+        let mem = &mut self.mems[mem.address as usize];
+        let addr = addr as u64 + offset as u64;
+        match kind {
+            StoreKind::I32Store | StoreKind::F32Store => mem.store(addr, &(v as u32).to_le_bytes()),
+            StoreKind::I64Store | StoreKind::F64Store => mem.store(addr, &v.to_le_bytes()),
+            StoreKind::I32Store8 | StoreKind::I64Store8 => {
+                mem.store(addr, &(v as u8).to_le_bytes())
+            }
+            StoreKind::I32Store16 | StoreKind::I64Store16 => {
+                mem.store(addr, &(v as u16).to_le_bytes())
+            }
+            StoreKind::I64Store32 => mem.store(addr, &(v as u32).to_le_bytes()),
+        }
+    }
 
-                    stack.pop_label()?;
-                    for v in values {
-                        stack.push_value(v);
-                    }
+    /// Executes `frame` until it returns, leaving its results on top of
+    /// `stack`. Calls between wasm functions do not recurse on the host stack.
+    fn run(&mut self, stack: &mut Stack, mut frame: Frame) -> Result<()> {
+        let mut frames = Vec::new();
+        self.run_frames(stack, &mut frames, &mut frame)
+            .map_err(|err| {
+                let frames = std::iter::once(&frame).chain(frames.iter().rev());
+                Trap::trace(err, frames.map(Frame::info))
+            })
+    }
 
-                    match l {
-                        ExecuteLabelRes::Continue => {}
-                        ExecuteLabelRes::Branch(l) => {
-                            if l != 0 {
-                                return Ok(ExecuteLabelRes::Branch(l - 1));
-                            }
-                        }
+    /// Runs the dispatch loop, leaving `frame` at the trapping operation if
+    /// it fails.
+    fn run_frames(
+        &mut self,
+        stack: &mut Stack,
+        frames: &mut Vec<Frame>,
+        frame: &mut Frame,
+    ) -> Result<()> {
+        loop {
+            let op = &frame.code.ops[frame.pc];
+            frame.pc += 1;
+            match op {
+                // control operations
+                Operation::Unreachable => bail!(Trap::new(TrapKind::Unreachable)),
+                Operation::Br(target) => {
+                    stack.drop_keep(target.drop_keep);
+                    frame.pc = target.pc as usize;
+                }
+                Operation::BrIf(target) => {
+                    if stack.pop_i32() != 0 {
+                        stack.drop_keep(target.drop_keep);
+                        frame.pc = target.pc as usize;
                     }
                 }
-                Instruction::Br(idx) => return Ok(ExecuteLabelRes::Branch((*idx).into())),
-                Instruction::BrIf(idx) => {
-                    let b = stack.pop_i32()?;
-                    if b != 0 {
-                        return Ok(ExecuteLabelRes::Branch((*idx).into()));
+                Operation::BrIfEqz(pc) => {
+                    if stack.pop_i32() == 0 {
+                        frame.pc = *pc as usize;
                     }
                 }
-                Instruction::Call(idx) => {
-                    let ty = &self.funcs[idx.get() as usize].ty;
-
-                    let args = ty
-                        .params
-                        .iter()
-                        .map(|t| {
-                            let v = stack.pop_value()?;
-                            ensure!(v.get_type() == *t, "type mismatch");
-                            Ok(v)
-                        })
-                        .collect::<Result<Vec<Value>>>()?;
-
-                    let res = self.execute_func(stack, *idx, args)?;
-                    for v in res {
-                        stack.push_value(v);
+                Operation::BrTable(targets) => {
+                    let i = stack.pop_i32() as u32 as usize;
+                    let target = targets[i.min(targets.len() - 1)];
+                    stack.drop_keep(target.drop_keep);
+                    frame.pc = target.pc as usize;
+                }
+                Operation::Return => {
+                    let keep = frame.code.results;
+                    let drop = (stack.len() - frame.fp) as u32 - keep;
+                    stack.drop_keep(DropKeep { drop, keep });
+                    match frames.pop() {
+                        Some(caller) => *frame = caller,
+                        None => return Ok(()),
                     }
                 }
+                Operation::Call(addr) => {
+                    let addr = *addr;
+                    self.call(stack, frames, frame, addr)?;
+                }
+                Operation::CallIndirect(ty, table) => {
+                    let i = stack.pop_i32() as u32;
+                    let table = &self.tables[table.address as usize];
+                    ensure!(i < table.size(), Trap::new(TrapKind::UndefinedElement));
 
-                Instruction::LocalGet(idx) => {
-                    let v = frame.get_local(*idx);
-                    stack.push_value(v);
+                    let Value::FuncRef(Some(addr)) = table.get(i)? else {
+                        bail!(Trap::new(TrapKind::UninitializedElement));
+                    };
+                    ensure!(
+                        *self.funcs[addr.address as usize].ty() == **ty,
+                        Trap::new(TrapKind::IndirectCallTypeMismatch)
+                    );
+                    self.call(stack, frames, frame, addr)?;
                 }
-                Instruction::LocalSet(idx) => {
-                    let v = stack.pop_value()?;
-                    frame.set_local(*idx, v);
+
+                // parametric operations
+                Operation::Drop => {
+                    stack.pop();
                 }
-                Instruction::LocalTee(idx) => {
-                    let v = stack.pop_value()?;
-                    frame.set_local(*idx, v);
-                    stack.push_value(v);
+                Operation::Select => {
+                    let c = stack.pop_i32();
+                    let v2 = stack.pop();
+                    let v1 = stack.pop();
+                    stack.push(if c != 0 { v1 } else { v2 });
                 }
 
-                Instruction::I32Const(i) => {
-                    stack.push_i32(*i);
+                // variable operations
+                Operation::LocalGet(i) => {
+                    let v = stack.get(frame.fp + *i as usize);
+                    stack.push(v);
                 }
-                Instruction::I32Extend8S => {
-                    let v = stack.pop_i32()?;
-                    stack.push_i32(v.to_le_bytes().as_ref().read_i8()? as i32);
+                Operation::LocalSet(i) => {
+                    let v = stack.pop();
+                    stack.set(frame.fp + *i as usize, v);
                 }
-                Instruction::I32Extend16S => {
-                    let v = stack.pop_i32()?;
-                    stack.push_i32(v.to_le_bytes().as_ref().read_i16::<LittleEndian>()? as i32);
+                Operation::LocalTee(i) => {
+                    let v = stack.pop();
+                    stack.set(frame.fp + *i as usize, v);
+                    stack.push(v);
+                }
+                Operation::GlobalGet(addr) => {
+                    let v = self.globals[addr.address as usize].get();
+                    stack.push(v.to_raw());
+                }
+                Operation::GlobalSet(addr) => {
+                    let global = &mut self.globals[addr.address as usize];
+                    global.value = Value::from_raw(stack.pop(), global.ty.value_type);
                 }
-                Instruction::I32UnOp(op) => {
-                    let v = stack.pop_i32()?;
-                    let res = match op {
-                        IUnOp::Clz => v.leading_zeros(),
-                        IUnOp::Ctz => v.trailing_zeros(),
-                        IUnOp::Popcnt => v.count_ones(),
-                    };
-                    stack.push_i32(res as i32);
-                }
-                Instruction::I32Eqz => {
-                    let v = stack.pop_i32()?;
-                    stack.push_i32((v == 0).into());
-                }
-                Instruction::I32BinOp(op) => {
-                    let v2 = stack.pop_i32()?;
-                    let v1 = stack.pop_i32()?;
-
-                    let res = match op {
-                        IBinOp::Add => v1.wrapping_add(v2),
-                        IBinOp::Sub => v1.wrapping_sub(v2),
-                        IBinOp::Mul => v1.wrapping_mul(v2),
-                        IBinOp::DivS => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            if v1 == i32::MIN && v2 == -1 {
-                                bail!("overflow")
-                            }
-                            v1.wrapping_div(v2)
-                        }
-                        IBinOp::DivU => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            (v1 as u32 / v2 as u32) as i32
-                        }
-                        IBinOp::RemS => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            v1.wrapping_rem(v2)
-                        }
-                        IBinOp::RemU => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            (v1 as u32 % v2 as u32) as i32
-                        }
-                        IBinOp::And => v1 & v2,
-                        IBinOp::Or => v1 | v2,
-                        IBinOp::Xor => v1 ^ v2,
-                        IBinOp::Shl => v1.wrapping_shl(v2 as u32),
-                        IBinOp::ShrS => v1.wrapping_shr(v2 as u32),
-                        IBinOp::ShrU => (v1 as u32).wrapping_shr(v2 as u32) as i32,
-                        IBinOp::Rotl => v1.rotate_left(v2 as u32),
-                        IBinOp::Rotr => v1.rotate_right(v2 as u32),
-                    };
-
-                    stack.push_i32(res);
-                }
-                Instruction::I32RelOp(op) => {
-                    let v2 = stack.pop_i32()?;
-                    let v1 = stack.pop_i32()?;
-
-                    let res = match op {
-                        IRelOp::Eq => v1 == v2,
-                        IRelOp::Ne => v1 != v2,
-                        IRelOp::LtS => v1 < v2,
-                        IRelOp::LtU => (v1 as u32) < v2 as u32,
-                        IRelOp::GtS => v1 > v2,
-                        IRelOp::GtU => v1 as u32 > v2 as u32,
-                        IRelOp::LeS => v1 <= v2,
-                        IRelOp::LeU => v1 as u32 <= v2 as u32,
-                        IRelOp::GeS => v1 >= v2,
-                        IRelOp::GeU => v1 as u32 >= v2 as u32,
-                    };
-
-                    stack.push_i32(res as i32);
-                }
-
-                Instruction::I64Const(i) => {
-                    stack.push_i64(*i);
-                }
-                Instruction::I64BinOp(op) => {
-                    let v2 = stack.pop_i64()?;
-                    let v1 = stack.pop_i64()?;
-
-                    let res = match op {
-                        IBinOp::Add => v1.wrapping_add(v2),
-                        IBinOp::Sub => v1.wrapping_sub(v2),
-                        IBinOp::Mul => v1.wrapping_mul(v2),
-                        IBinOp::DivS => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            if v1 == i64::MIN && v2 == -1 {
-                                bail!("overflow")
-                            }
-                            v1.wrapping_div(v2)
-                        }
-                        IBinOp::DivU => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            (v1 as u64 / v2 as u64) as i64
-                        }
-                        IBinOp::RemS => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            v1.wrapping_rem(v2)
-                        }
-                        IBinOp::RemU => {
-                            if v2 == 0 {
-                                bail!("division by zero")
-                            }
-                            (v1 as u64 % v2 as u64) as i64
-                        }
-                        IBinOp::And => v1 & v2,
-                        IBinOp::Or => v1 | v2,
-                        IBinOp::Xor => v1 ^ v2,
-                        IBinOp::Shl => v1.wrapping_shl(v2 as u32),
-                        IBinOp::ShrS => v1.wrapping_shr(v2 as u32),
-                        IBinOp::ShrU => (v1 as u64).wrapping_shr(v2 as u32) as i64,
-                        IBinOp::Rotl => v1.rotate_left(v2 as u32),
-                        IBinOp::Rotr => v1.rotate_right(v2 as u32),
-                    };
 
-                    stack.push_i64(res);
+                // table operations
+                Operation::TableGet(addr) => {
+                    let i = stack.pop_i32();
+                    let v = self.tables[addr.address as usize].get(i as u32)?;
+                    stack.push(v.to_raw());
                 }
-                Instruction::I64UnOp(op) => {
-                    let v = stack.pop_i64()?;
-                    let res = match op {
-                        IUnOp::Clz => v.leading_zeros(),
-                        IUnOp::Ctz => v.trailing_zeros(),
-                        IUnOp::Popcnt => v.count_ones(),
-                    };
-                    stack.push_i64(res as i64);
-                }
-                Instruction::I64Eqz => {
-                    let v = stack.pop_i64()?;
-                    stack.push_i32((v == 0) as i32);
-                }
-                Instruction::I64RelOp(op) => {
-                    let v2 = stack.pop_i64()?;
-                    let v1 = stack.pop_i64()?;
-
-                    let res = match op {
-                        IRelOp::Eq => v1 == v2,
-                        IRelOp::Ne => v1 != v2,
-                        IRelOp::LtS => v1 < v2,
-                        IRelOp::LtU => (v1 as u64) < v2 as u64,
-                        IRelOp::GtS => v1 > v2,
-                        IRelOp::GtU => v1 as u64 > v2 as u64,
-                        IRelOp::LeS => v1 <= v2,
-                        IRelOp::LeU => v1 as u64 <= v2 as u64,
-                        IRelOp::GeS => v1 >= v2,
-                        IRelOp::GeU => v1 as u64 >= v2 as u64,
-                    };
+                Operation::TableSet(addr) => {
+                    let table = &mut self.tables[addr.address as usize];
+                    let v = Value::from_raw(stack.pop(), ValueType::Ref(table.ty().elem_type));
+                    let i = stack.pop_i32();
+                    table.set(i as u32, v)?;
+                }
+                Operation::TableSize(addr) => {
+                    let size = self.tables[addr.address as usize].size();
+                    stack.push_i32(size as i32);
+                }
+                Operation::TableGrow(addr) => {
+                    let table = &mut self.tables[addr.address as usize];
+                    let n = stack.pop_i32();
+                    let v = Value::from_raw(stack.pop(), ValueType::Ref(table.ty().elem_type));
+                    let res = table.grow(n as u32, v);
+                    stack.push_i32(res.map_or(-1, |size| size as i32));
+                }
+                Operation::TableFill(addr) => {
+                    let table = &mut self.tables[addr.address as usize];
+                    let n = stack.pop_i32();
+                    let v = Value::from_raw(stack.pop(), ValueType::Ref(table.ty().elem_type));
+                    let i = stack.pop_i32();
+                    table.fill(i as u32, v, n as u32)?;
+                }
+                Operation::TableCopy { dst, src } => {
+                    let n = stack.pop_i32() as u32;
+                    let s = stack.pop_i32() as u32;
+                    let d = stack.pop_i32() as u32;
+
+                    let src = &self.tables[src.address as usize];
+                    let values = src.elements()[src.range(s, n)?].to_vec();
+                    self.tables[dst.address as usize]
+                        .elements_mut(d, n)?
+                        .copy_from_slice(&values);
+                }
+                Operation::TableInit { elem, table } => {
+                    let n = stack.pop_i32() as u32;
+                    let s = stack.pop_i32() as u32;
+                    let d = stack.pop_i32() as u32;
+                    self.init_table(*table, *elem, d, s, n)?;
+                }
+                Operation::ElemDrop(addr) => self.drop_elem(*addr),
 
-                    stack.push_i32(res as i32);
+                // memory operations
+                Operation::Load(kind, mem, offset) => {
+                    let addr = stack.pop_i32() as u32;
+                    let v = self.load(*kind, *mem, addr, *offset)?;
+                    stack.push(v);
+                }
+                Operation::Store(kind, mem, offset) => {
+                    let v = stack.pop();
+                    let addr = stack.pop_i32() as u32;
+                    self.store(*kind, *mem, addr, *offset, v)?;
                 }
-                Instruction::I64Extend8S => {
-                    let v = stack.pop_i64()?;
-                    stack.push_i64(v.to_le_bytes().as_ref().read_i8()? as i64);
+                Operation::MemorySize(mem) => {
+                    let size = self.mems[mem.address as usize].size();
+                    stack.push_i32(size as i32);
                 }
-                Instruction::I64Extend16S => {
-                    let v = stack.pop_i64()?;
-                    stack.push_i64(v.to_le_bytes().as_ref().read_i16::<LittleEndian>()? as i64);
+                Operation::MemoryGrow(mem) => {
+                    let n = stack.pop_i32();
+                    let res = self.mems[mem.address as usize].grow(n as u32);
+                    stack.push_i32(res.map_or(-1, |size| size as i32));
                 }
-                Instruction::I64Extend32S => {
-                    let v = stack.pop_i64()?;
-                    stack.push_i64(v.to_le_bytes().as_ref().read_i32::<LittleEndian>()? as i64);
+                Operation::MemoryInit(mem, data) => {
+                    let n = stack.pop_i32() as u32;
+                    let s = stack.pop_i32() as u32;
+                    let d = stack.pop_i32() as u32;
+                    self.init_memory(*mem, *data, d, s, n)?;
+                }
+                Operation::DataDrop(addr) => self.drop_data(*addr),
+                Operation::MemoryCopy(mem) => {
+                    let n = stack.pop_i32() as u32;
+                    let s = stack.pop_i32() as u32;
+                    let d = stack.pop_i32() as u32;
+                    self.mems[mem.address as usize].copy(d as u64, s as u64, n as usize)?;
+                }
+                Operation::MemoryFill(mem) => {
+                    let n = stack.pop_i32() as u32;
+                    let v = stack.pop_i32();
+                    let d = stack.pop_i32() as u32;
+                    self.mems[mem.address as usize].fill(d as u64, v as u8, n as usize)?;
                 }
 
-                _ => unimplemented!("{:?}", instr),
+                // numeric operations
+                Operation::Const(v) => stack.push(*v),
+                Operation::Unary(op) => {
+                    let v = stack.pop();
+                    stack.push(op.eval(v)?);
+                }
+                Operation::Binary(op) => {
+                    let v2 = stack.pop();
+                    let v1 = stack.pop();
+                    stack.push(op.eval(v1, v2)?);
+                }
             }
         }
-        Ok(ExecuteLabelRes::Continue)
     }
 }
 
+/// Checks whether `actual` limits are a subtype of the `expected` ones.
+fn match_limits(actual: &Limits, expected: &Limits) -> bool {
+    actual.min >= expected.min
+        && match (actual.max, expected.max) {
+            (_, None) => true,
+            (Some(actual), Some(expected)) => actual <= expected,
+            (None, Some(_)) => false,
+        }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::{BlockType, Expression, Func, FuncType, Instruction, Module};
-    use crate::decode::decode;
+    use crate::core::{BlockType, Expression, Func, FuncType, IBinOp, IRelOp, Instruction, Module};
+    use crate::decode::{decode, decode_lazy};
 
     fn execute_instructions(
         types: Vec<FuncType>,
         instructions: Vec<Instruction>,
         args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        execute_instructions_with_locals(types, vec![], instructions, args)
+    }
+
+    fn execute_instructions_with_locals(
+        types: Vec<FuncType>,
+        locals: Vec<ValueType>,
+        instructions: Vec<Instruction>,
+        args: Vec<Value>,
     ) -> Result<Vec<Value>> {
         let mut store = Store::default();
         let mut module = Module::default();
         let func = Func {
             type_id: Idx::new(0),
-            locals: vec![],
+            locals: locals.into_iter().map(|ty| (1, ty)).collect(),
             body: Expression { instructions },
+            raw: None,
         };
 
         module.types = types;
         module.funcs.push(func);
 
-        store.instantiate(module);
-        store.execute(Idx::new(0), args)
+        let instance = store.instantiate(module).unwrap();
+        store.execute(&instance, Idx::new(0), args)
     }
 
     #[test]
@@ -581,7 +1366,10 @@ mod tests {
             },
             Instruction::LocalGet(Idx::from(2)),
         ];
-        let value = execute_instructions(types, loop_instr, vec![Value::I32(10)]).unwrap();
+        let locals = vec![ValueType::Num(NumType::I32), ValueType::Num(NumType::I32)];
+        let value =
+            execute_instructions_with_locals(types, locals, loop_instr, vec![Value::I32(10)])
+                .unwrap();
         assert_eq!(value, vec![Value::I32(55)]);
     }
 
@@ -669,6 +1457,1081 @@ mod tests {
         assert_eq!(value, vec![Value::I32(55)]);
     }
 
+    fn wat2wasm(wat: &str) -> Vec<u8> {
+        let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+        let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+        wat.encode().unwrap()
+    }
+
+    fn decode_wat(wat: &str) -> Module {
+        decode(&mut std::io::Cursor::new(wat2wasm(wat))).unwrap()
+    }
+
+    fn instantiate_wat(wat: &str) -> (Store, Rc<ModuleInstance>) {
+        let mut store = Store::default();
+        let instance = store.instantiate(decode_wat(wat)).unwrap();
+        (store, instance)
+    }
+
+    fn trap_kind(err: &anyhow::Error) -> Option<TrapKind> {
+        err.downcast_ref::<Trap>().map(Trap::kind)
+    }
+
+    #[test]
+    fn test_memory_load_store() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (memory (export "mem") 1 2)
+              (func (export "store") (param i32 i64)
+                (i64.store offset=4 (local.get 0) (local.get 1)))
+              (func (export "load8_s") (param i32) (result i32)
+                (i32.load8_s (local.get 0)))
+              (func (export "load32_u") (param i32) (result i64)
+                (i64.load32_u offset=4 (local.get 0))))
+            "#,
+        );
+
+        store
+            .invoke(&instance, "store", vec![Value::I32(0), Value::I64(-2)])
+            .unwrap();
+        assert_eq!(
+            store
+                .invoke(&instance, "load8_s", vec![Value::I32(4)])
+                .unwrap(),
+            vec![Value::I32(-2)]
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "load32_u", vec![Value::I32(0)])
+                .unwrap(),
+            vec![Value::I64(0xffff_fffe)]
+        );
+        assert_eq!(
+            store.memory(&instance, "mem").unwrap().data()[4..8],
+            [0xfe, 0xff, 0xff, 0xff]
+        );
+
+        assert!(store
+            .invoke(&instance, "load8_s", vec![Value::I32(PAGE_SIZE as i32)])
+            .is_err());
+        assert!(store
+            .invoke(&instance, "store", vec![Value::I32(-1), Value::I64(0)])
+            .is_err());
+    }
+
+    #[test]
+    fn test_memory_grow() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (memory 1 3)
+              (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0)))
+              (func (export "size") (result i32)
+                (memory.size)))
+            "#,
+        );
+
+        assert_eq!(
+            store
+                .invoke(&instance, "grow", vec![Value::I32(1)])
+                .unwrap(),
+            vec![Value::I32(1)]
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "grow", vec![Value::I32(2)])
+                .unwrap(),
+            vec![Value::I32(-1)]
+        );
+        assert_eq!(
+            store.invoke(&instance, "size", vec![]).unwrap(),
+            vec![Value::I32(2)]
+        );
+    }
+
+    #[test]
+    fn test_data_segments() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (memory (export "mem") 1)
+              (data (i32.const 2) "abc")
+              (data "passive")
+              (data (i32.const 4) "de")
+              (func (export "load") (param i32) (result i32)
+                (i32.load8_u (local.get 0))))
+            "#,
+        );
+
+        assert_eq!(
+            &store.memory(&instance, "mem").unwrap().data()[..7],
+            b"\0\0abde\0"
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "load", vec![Value::I32(3)])
+                .unwrap(),
+            vec![Value::I32(b'b' as i32)]
+        );
+    }
+
+    #[test]
+    fn test_data_segment_out_of_bounds() {
+        let module = decode_wat(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 0) "a")
+              (data (i32.const 65535) "bc"))
+            "#,
+        );
+
+        let mut store = Store::default();
+        assert!(store.instantiate(module).is_err());
+        // segments preceding the failing one stay applied
+        assert_eq!(store.mems[0].data()[0], b'a');
+    }
+
+    #[test]
+    fn test_bulk_memory() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (memory (export "mem") 1)
+              (data $d "hello")
+              (func (export "init") (param i32 i32 i32)
+                (memory.init $d (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "drop") (data.drop $d))
+              (func (export "copy") (param i32 i32 i32)
+                (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "fill") (param i32 i32 i32)
+                (memory.fill (local.get 0) (local.get 1) (local.get 2))))
+            "#,
+        );
+        let args = |a, b, c| vec![Value::I32(a), Value::I32(b), Value::I32(c)];
+
+        store.invoke(&instance, "init", args(1, 1, 4)).unwrap();
+        assert_eq!(
+            &store.memory(&instance, "mem").unwrap().data()[..6],
+            b"\0ello\0"
+        );
+        assert!(store.invoke(&instance, "init", args(0, 3, 3)).is_err());
+
+        store.invoke(&instance, "copy", args(2, 1, 4)).unwrap();
+        assert_eq!(
+            &store.memory(&instance, "mem").unwrap().data()[..7],
+            b"\0eello\0"
+        );
+
+        store
+            .invoke(&instance, "fill", args(0, b'x' as i32, 2))
+            .unwrap();
+        assert_eq!(
+            &store.memory(&instance, "mem").unwrap().data()[..7],
+            b"xxello\0"
+        );
+        assert!(store.invoke(&instance, "fill", args(65535, 0, 2)).is_err());
+
+        store.invoke(&instance, "drop", vec![]).unwrap();
+        store.invoke(&instance, "init", args(0, 0, 0)).unwrap();
+        assert!(store.invoke(&instance, "init", args(0, 0, 1)).is_err());
+    }
+
+    #[test]
+    fn test_globals() {
+        let module = decode_wat(
+            r#"
+            (module
+              (global $base (import "env" "base") i32)
+              (global $c (export "c") i32 (global.get $base))
+              (global $counter (export "counter") (mut i64) (i64.const 10))
+              (func (export "bump") (param i64) (result i64)
+                (global.set $counter (i64.add (global.get $counter) (local.get 0)))
+                (global.get $counter)))
+            "#,
+        );
+
+        let mut store = Store::default();
+        let ty = GlobalType {
+            value_type: ValueType::Num(NumType::I32),
+            mutability: false,
+        };
+        let base = store.new_global(ty, Value::I32(42)).unwrap();
+        let instance = store
+            .instantiate_with_imports(module, &[Extern::Global(base)])
+            .unwrap();
+
+        assert_eq!(store.global(&instance, "c").unwrap().get(), Value::I32(42));
+        assert!(store
+            .global_mut(&instance, "c")
+            .unwrap()
+            .set(Value::I32(0))
+            .is_err());
+        assert_eq!(
+            store
+                .invoke(&instance, "bump", vec![Value::I64(5)])
+                .unwrap(),
+            vec![Value::I64(15)]
+        );
+
+        let counter = store.global_mut(&instance, "counter").unwrap();
+        assert!(counter.set(Value::I32(0)).is_err());
+        counter.set(Value::I64(100)).unwrap();
+        assert_eq!(
+            store
+                .invoke(&instance, "bump", vec![Value::I64(1)])
+                .unwrap(),
+            vec![Value::I64(101)]
+        );
+    }
+
+    #[test]
+    fn test_global_import_mismatch() {
+        let module = decode_wat(r#"(module (global (import "env" "g") (mut i32)))"#);
+
+        let mut store = Store::default();
+        let ty = GlobalType {
+            value_type: ValueType::Num(NumType::I32),
+            mutability: false,
+        };
+        let g = store.new_global(ty, Value::I32(0)).unwrap();
+        assert!(store
+            .instantiate_with_imports(module.clone(), &[Extern::Global(g)])
+            .is_err());
+        assert!(store.instantiate(module).is_err());
+    }
+
+    #[test]
+    fn test_host_func_import() {
+        let module = decode_wat(
+            r#"
+            (module
+              (import "env" "add" (func $add (param i32 i32) (result i32)))
+              (import "env" "mem" (memory 1))
+              (import "env" "log" (func $log (param i32)))
+              (export "mem" (memory 0))
+              (func (export "run") (param i32) (result i32)
+                (call $log (local.get 0))
+                (call $add (local.get 0) (i32.const 2))))
+            "#,
+        );
+
+        let i32 = ValueType::Num(NumType::I32);
+        let mut store = Store::default();
+        let mut linker = Linker::new();
+        linker
+            .func(
+                &mut store,
+                "env",
+                "add",
+                FuncType {
+                    params: vec![i32, i32],
+                    results: vec![i32],
+                },
+                |_, args| match args {
+                    [Value::I32(a), Value::I32(b)] => Ok(vec![Value::I32(a + b)]),
+                    _ => unreachable!(),
+                },
+            )
+            .unwrap();
+        let mem = store.new_memory(Limits { min: 1, max: None }).unwrap();
+        linker.define("env", "mem", Extern::Memory(mem)).unwrap();
+        linker
+            .func(
+                &mut store,
+                "env",
+                "log",
+                FuncType {
+                    params: vec![i32],
+                    results: vec![],
+                },
+                move |caller, args| {
+                    let Value::I32(v) = args[0] else {
+                        unreachable!()
+                    };
+                    let mem = caller.memory_mut("mem")?;
+                    mem.store(0, &v.to_le_bytes())?;
+                    Ok(vec![])
+                },
+            )
+            .unwrap();
+        let instance = linker.instantiate(&mut store, module).unwrap();
+
+        assert_eq!(
+            store
+                .invoke(&instance, "run", vec![Value::I32(40)])
+                .unwrap(),
+            vec![Value::I32(42)]
+        );
+        assert_eq!(store.memory(&instance, "mem").unwrap().data()[0], 40);
+    }
+
+    #[test]
+    fn test_unresolved_imports() {
+        let module = decode_wat(r#"(module (import "env" "f" (func (param i32))))"#);
+
+        let mut store = Store::default();
+        let mut linker = Linker::new();
+        assert!(linker.instantiate(&mut store, module.clone()).is_err());
+
+        let ty = FuncType {
+            params: vec![],
+            results: vec![],
+        };
+        linker
+            .func(&mut store, "env", "f", ty, |_, _| Ok(vec![]))
+            .unwrap();
+        assert!(linker.instantiate(&mut store, module).is_err());
+    }
+
+    #[test]
+    fn test_multiple_instances() {
+        let producer = decode_wat(
+            r#"
+            (module
+              (memory (export "mem") 1)
+              (global $n (export "n") (mut i32) (i32.const 0))
+              (func (export "inc") (result i32)
+                (global.set $n (i32.add (global.get $n) (i32.const 1)))
+                (i32.store8 (i32.const 0) (global.get $n))
+                (global.get $n)))
+            "#,
+        );
+        let consumer = decode_wat(
+            r#"
+            (module
+              (import "producer" "inc" (func $inc (result i32)))
+              (import "producer" "mem" (memory 1))
+              (func (export "run") (result i32)
+                (i32.add (call $inc) (call $inc))
+                (i32.add (i32.load8_u (i32.const 0)))))
+            "#,
+        );
+
+        let mut store = Store::default();
+        let mut linker = Linker::new();
+        let first = linker.instantiate(&mut store, producer.clone()).unwrap();
+        let second = linker.instantiate(&mut store, producer).unwrap();
+        linker.register("producer", &first).unwrap();
+        let consumer = linker.instantiate(&mut store, consumer).unwrap();
+
+        assert_eq!(
+            store.invoke(&consumer, "run", vec![]).unwrap(),
+            vec![Value::I32(5)]
+        );
+        assert_eq!(store.global(&first, "n").unwrap().get(), Value::I32(2));
+        assert_eq!(store.global(&second, "n").unwrap().get(), Value::I32(0));
+        assert_eq!(store.memory(&first, "mem").unwrap().data()[0], 2);
+        assert_eq!(store.memory(&second, "mem").unwrap().data()[0], 0);
+        assert!(store.invoke(&consumer, "inc", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_start_function() {
+        let (store, instance) = instantiate_wat(
+            r#"
+            (module
+              (memory (export "mem") 1)
+              (data (i32.const 0) "\01")
+              (func $start
+                (i32.store8 (i32.const 1) (i32.add (i32.load8_u (i32.const 0)) (i32.const 1))))
+              (start $start))
+            "#,
+        );
+        assert_eq!(
+            &store.memory(&instance, "mem").unwrap().data()[..2],
+            &[1, 2]
+        );
+
+        let module = decode_wat(
+            r#"
+            (module
+              (import "env" "mem" (memory 1))
+              (data (i32.const 0) "a")
+              (func $start (i32.store8 (i32.const 1) (i32.const 98)) unreachable)
+              (start $start))
+            "#,
+        );
+        let mut store = Store::default();
+        let mem = store.new_memory(Limits { min: 1, max: None }).unwrap();
+        assert!(store
+            .instantiate_with_imports(module, &[Extern::Memory(mem)])
+            .is_err());
+        // effects preceding the trap remain visible
+        assert_eq!(&store.mems[mem.address as usize].data()[..2], b"ab");
+    }
+
+    #[test]
+    fn test_multi_value() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (func $swap (param i32 i32) (result i32 i32)
+                (local.get 1) (local.get 0))
+              (func (export "swap") (param i32 i32) (result i32 i32)
+                (call $swap (local.get 0) (local.get 1)))
+              (func (export "block") (param i32) (result i32 i64)
+                (local.get 0)
+                (block (param i32) (result i32 i64)
+                  (i32.const 1) (i32.add) (i64.const 7)))
+              (func (export "sum") (param i32) (result i32)
+                (i32.const 0) (local.get 0)
+                (loop (param i32 i32) (result i32)
+                  (local.tee 0)
+                  (i32.add)
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0 (local.get 0))
+                  (i32.add)))
+              (func (export "br_table") (param i32) (result i32 i32)
+                (block (result i32 i32)
+                  (block (result i32 i32)
+                    (i32.const 99) (i32.const 1) (i32.const 2) (local.get 0)
+                    (br_table 0 1))
+                  (i32.add) (i32.const 0))))
+            "#,
+        );
+
+        let i32s = |v: &[i32]| v.iter().map(|v| Value::I32(*v)).collect::<Vec<_>>();
+        assert_eq!(
+            store.invoke(&instance, "swap", i32s(&[1, 2])).unwrap(),
+            i32s(&[2, 1])
+        );
+        assert_eq!(
+            store.invoke(&instance, "block", i32s(&[41])).unwrap(),
+            vec![Value::I32(42), Value::I64(7)]
+        );
+        assert_eq!(
+            store.invoke(&instance, "sum", i32s(&[4])).unwrap(),
+            i32s(&[10])
+        );
+        assert_eq!(
+            store.invoke(&instance, "br_table", i32s(&[0])).unwrap(),
+            i32s(&[3, 0])
+        );
+        assert_eq!(
+            store.invoke(&instance, "br_table", i32s(&[5])).unwrap(),
+            i32s(&[1, 2])
+        );
+    }
+
+    #[test]
+    fn test_return_select_drop() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (func $nested (param i32) (result i32 i32)
+                (i32.const 100)
+                (block
+                  (loop
+                    (i32.const 5)
+                    (if (local.get 0)
+                      (then (i32.const 1) (i32.const 2) (return)))
+                    (drop)))
+                (i32.const 3))
+              (func (export "nested") (param i32) (result i32)
+                (i32.add (call $nested (local.get 0))))
+              (func (export "select") (param i32) (result i64)
+                (select (i64.const 1) (i64.const 2) (local.get 0)))
+              (func (export "select_ref") (param i32) (result funcref)
+                (select (result funcref) (ref.func 0) (ref.null func) (local.get 0)))
+              (func (export "is_null") (param externref) (result i32)
+                (ref.is_null (local.get 0)))
+              (elem declare func 0))
+            "#,
+        );
+
+        assert_eq!(
+            store
+                .invoke(&instance, "nested", vec![Value::I32(1)])
+                .unwrap(),
+            vec![Value::I32(3)]
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "nested", vec![Value::I32(0)])
+                .unwrap(),
+            vec![Value::I32(103)]
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "select", vec![Value::I32(0)])
+                .unwrap(),
+            vec![Value::I64(2)]
+        );
+        assert!(matches!(
+            store
+                .invoke(&instance, "select_ref", vec![Value::I32(1)])
+                .unwrap()[..],
+            [Value::FuncRef(Some(_))]
+        ));
+        assert_eq!(
+            store
+                .invoke(&instance, "is_null", vec![Value::ExternRef(None)])
+                .unwrap(),
+            vec![Value::I32(1)]
+        );
+    }
+
+    #[test]
+    fn test_externref() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (table $t (export "objects") 2 externref)
+              (func (export "put") (param i32 externref)
+                (table.set $t (local.get 0) (local.get 1)))
+              (func (export "get") (param i32) (result externref)
+                (table.get $t (local.get 0))))
+            "#,
+        );
+
+        let file = store.new_externref(String::from("file"));
+        let socket = store.new_externref(42u16);
+        store
+            .invoke(&instance, "put", vec![Value::I32(0), file])
+            .unwrap();
+        store
+            .invoke(&instance, "put", vec![Value::I32(1), socket])
+            .unwrap();
+
+        let res = store.invoke(&instance, "get", vec![Value::I32(0)]).unwrap();
+        assert_eq!(res, vec![file]);
+        assert_eq!(store.externref::<String>(res[0]).unwrap(), "file");
+        assert!(store.externref::<u16>(res[0]).is_none());
+
+        let table = store.table(&instance, "objects").unwrap();
+        let v = table.get(1).unwrap();
+        *store.externref_mut::<u16>(v).unwrap() += 1;
+        assert_eq!(store.externref::<u16>(socket), Some(&43));
+        assert!(store.externref::<u16>(Value::ExternRef(None)).is_none());
+    }
+
+    #[test]
+    fn test_branch_unwinding() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (func (export "br_if") (param i32) (result i32)
+                (i32.const 1)
+                (block (result i32)
+                  (i32.const 2)
+                  (i32.const 3)
+                  (i32.const 4)
+                  (br_if 0 (local.get 0))
+                  (drop)
+                  (drop))
+                (i32.add))
+              (func (export "br_table") (param i32) (result i32)
+                (block (result i32)
+                  (block (result i32)
+                    (i32.const 7)
+                    (i32.const 10)
+                    (i32.const 20)
+                    (br_table 0 1 (local.get 0)))
+                  (i32.const 100)
+                  (i32.add)))
+              (func (export "if_br") (param i32) (result i32)
+                (block (result i32)
+                  (if (result i32) (local.get 0)
+                    (then (i32.const 5) (br 1))
+                    (else (i32.const 6)))
+                  (i32.const 1)
+                  (i32.add))))
+            "#,
+        );
+
+        let mut call = |name: &str, arg: i32| store.invoke(&instance, name, vec![Value::I32(arg)]);
+        assert_eq!(call("br_if", 1).unwrap(), vec![Value::I32(5)]);
+        assert_eq!(call("br_if", 0).unwrap(), vec![Value::I32(3)]);
+        assert_eq!(call("br_table", 0).unwrap(), vec![Value::I32(120)]);
+        assert_eq!(call("br_table", 1).unwrap(), vec![Value::I32(20)]);
+        assert_eq!(call("br_table", 7).unwrap(), vec![Value::I32(20)]);
+        assert_eq!(call("if_br", 1).unwrap(), vec![Value::I32(5)]);
+        assert_eq!(call("if_br", 0).unwrap(), vec![Value::I32(7)]);
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // calls between wasm functions must not recurse on the host stack
+        let module = decode_wat(
+            r#"
+            (module
+              (func $sum (export "sum") (param i64) (result i64)
+                (if (result i64) (i64.eqz (local.get 0))
+                  (then (i64.const 0))
+                  (else
+                    (i64.add
+                      (local.get 0)
+                      (call $sum (i64.sub (local.get 0) (i64.const 1))))))))
+            "#,
+        );
+
+        let mut store = Store::new(Config {
+            max_call_depth: 2_000_000,
+            max_stack_size: 1 << 23,
+            ..Default::default()
+        });
+        let instance = store.instantiate(module).unwrap();
+        assert_eq!(
+            store
+                .invoke(&instance, "sum", vec![Value::I64(1_000_000)])
+                .unwrap(),
+            vec![Value::I64(500_000_500_000)]
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let wat = r#"
+            (module
+              (func $f (export "runaway") (call $f))
+              (func $deep (export "deep") (param i32) (result i32)
+                (if (result i32) (local.get 0)
+                  (then (call $deep (i32.sub (local.get 0) (i32.const 1))))
+                  (else (i32.const 7)))))
+            "#;
+        for engine in [Engine::Stack, Engine::Register] {
+            let config = Config {
+                engine,
+                max_call_depth: 100,
+                max_stack_size: 1000,
+                ..Default::default()
+            };
+            let mut store = Store::new(config);
+            let instance = store.instantiate(decode_wat(wat)).unwrap();
+
+            let err = store.invoke(&instance, "runaway", vec![]).unwrap_err();
+            assert_eq!(trap_kind(&err), Some(TrapKind::StackOverflow));
+            assert_eq!(err.to_string(), "call stack exhausted");
+
+            let mut deep = |n| store.invoke(&instance, "deep", vec![Value::I32(n)]);
+            assert_eq!(deep(90).unwrap(), vec![Value::I32(7)]);
+            assert!(deep(100).is_err());
+            // the store stays usable after a trap
+            assert_eq!(deep(1).unwrap(), vec![Value::I32(7)]);
+        }
+
+        // every frame of `deep` holds at least its argument
+        let mut store = Store::new(Config {
+            max_stack_size: 100,
+            ..Default::default()
+        });
+        let instance = store.instantiate(decode_wat(wat)).unwrap();
+        let mut deep = |n| store.invoke(&instance, "deep", vec![Value::I32(n)]);
+        assert_eq!(deep(10).unwrap(), vec![Value::I32(7)]);
+        let err = deep(100).unwrap_err();
+        assert_eq!(trap_kind(&err), Some(TrapKind::StackOverflow));
+    }
+
+    #[test]
+    fn test_many_locals() {
+        let wat = format!(
+            r#"
+            (module
+              (func (export "f") (param i32) (result i32) (local {}) (local i32)
+                (local.set 70001 (local.get 0))
+                (local.get 70001)))
+            "#,
+            "i64 ".repeat(70_000)
+        );
+        for engine in [Engine::Stack, Engine::Register] {
+            let mut store = Store::new(Config {
+                engine,
+                ..Default::default()
+            });
+            let instance = store.instantiate(decode_wat(&wat)).unwrap();
+            let result = store.invoke(&instance, "f", vec![Value::I32(5)]).unwrap();
+            assert_eq!(result, vec![Value::I32(5)]);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow_through_host() {
+        let wat = r#"
+            (module
+              (import "env" "reenter" (func $reenter (param i32) (result i32)))
+              (func $deep (export "deep") (param i32) (result i32)
+                (if (result i32) (local.get 0)
+                  (then (call $deep (i32.sub (local.get 0) (i32.const 1))))
+                  (else (i32.const 7))))
+              (func (export "nested") (param i32) (result i32)
+                (call $reenter (local.get 0)))
+              (func (export "runaway") (param i32) (result i32)
+                (call $reenter (i32.const -1))))
+            "#;
+        let is_overflow = |err: &anyhow::Error| {
+            err.downcast_ref::<Trap>().map(Trap::kind) == Some(TrapKind::StackOverflow)
+        };
+        let instantiate = |config| {
+            let mut store = Store::new(config);
+            let mut linker = Linker::new();
+            let ty = FuncType {
+                params: vec![ValueType::Num(NumType::I32)],
+                results: vec![ValueType::Num(NumType::I32)],
+            };
+            // calls `deep`, or `runaway` again for negative arguments
+            linker
+                .func(&mut store, "env", "reenter", ty, |caller, args| {
+                    let instance = caller.instance().clone();
+                    let name = match args[0] {
+                        Value::I32(n) if n < 0 => "runaway",
+                        _ => "deep",
+                    };
+                    caller.store_mut().invoke(&instance, name, args.to_vec())
+                })
+                .unwrap();
+            let instance = linker.instantiate(&mut store, decode_wat(wat)).unwrap();
+            (store, instance)
+        };
+        for engine in [Engine::Stack, Engine::Register] {
+            // the frames of the invocation waiting for the host function
+            // count against the limit of the nested one
+            let (mut store, instance) = instantiate(Config {
+                engine,
+                max_call_depth: 100,
+                ..Default::default()
+            });
+            let mut invoke = |name, n| store.invoke(&instance, name, vec![Value::I32(n)]);
+            assert_eq!(invoke("deep", 99).unwrap(), vec![Value::I32(7)]);
+            assert_eq!(invoke("nested", 90).unwrap(), vec![Value::I32(7)]);
+            let err = invoke("nested", 99).unwrap_err();
+            assert!(is_overflow(&err));
+            // the backtrace continues past the host function
+            let backtrace = err.downcast_ref::<Trap>().unwrap().backtrace();
+            assert_eq!(backtrace.first().map(FrameInfo::func), Some(1));
+            assert_eq!(backtrace.last().map(FrameInfo::func), Some(2));
+
+            // recursion through the host function is bounded before it
+            // exhausts the native stack
+            let (mut store, instance) = instantiate(Config {
+                engine,
+                ..Default::default()
+            });
+            let mut invoke = |name, n| store.invoke(&instance, name, vec![Value::I32(n)]);
+            assert!(is_overflow(&invoke("runaway", 0).unwrap_err()));
+            assert_eq!(invoke("nested", 1).unwrap(), vec![Value::I32(7)]);
+        }
+    }
+
+    #[test]
+    fn test_trap_locations() {
+        let wat = r#"
+            (module
+              (import "env" "fail" (func $fail))
+              (memory 1)
+              (table 1 funcref)
+              (func $div (export "div") (param i32 i32) (result i32)
+                (i32.div_s (local.get 0) (local.get 1)))
+              (func (export "load") (param i32) (result i32)
+                (nop)
+                (i32.load (local.get 0)))
+              (func (export "indirect")
+                (call_indirect (i32.const 0)))
+              (func (export "nested") (param i32 i32) (result i32)
+                (block (result i32) (call $div (local.get 0) (local.get 1))))
+              (func (export "host")
+                (call $fail))
+              (func (export "dead") (param i32) (result i32)
+                (block (br 0) (i32.const 1) (drop))
+                (i32.div_s (i32.const 1) (local.get 0))))
+            "#;
+        let bin = wat2wasm(wat);
+        for engine in [Engine::Stack, Engine::Register] {
+            let mut store = Store::new(Config {
+                engine,
+                ..Default::default()
+            });
+            let mut linker = Linker::new();
+            let ty = FuncType {
+                params: vec![],
+                results: vec![],
+            };
+            linker
+                .func(&mut store, "env", "fail", ty, |_, _| bail!("disk full"))
+                .unwrap();
+            let module = decode_lazy(&mut std::io::Cursor::new(&bin)).unwrap();
+            let instance = linker.instantiate(&mut store, module).unwrap();
+
+            // locations are checked through the opcode found at the offset
+            let opcode = |offset: Option<u32>| offset.map(|offset| bin[offset as usize]);
+            let mut trap = |name: &str, args: Vec<Value>| {
+                let err = store.invoke(&instance, name, args).unwrap_err();
+                let trap = err.downcast_ref::<Trap>().unwrap();
+                (trap.kind(), trap.func(), opcode(trap.offset()))
+            };
+            let i32s = |v: &[i32]| v.iter().map(|v| Value::I32(*v)).collect::<Vec<_>>();
+            assert_eq!(
+                trap("div", i32s(&[1, 0])),
+                (TrapKind::DivisionByZero, Some(1), Some(0x6d))
+            );
+            assert_eq!(
+                trap("div", i32s(&[i32::MIN, -1])).0,
+                TrapKind::IntegerOverflow
+            );
+            assert_eq!(
+                trap("load", i32s(&[65535])),
+                (TrapKind::OutOfBoundsMemory, Some(2), Some(0x28))
+            );
+            assert_eq!(
+                trap("indirect", vec![]),
+                (TrapKind::UninitializedElement, Some(3), Some(0x11))
+            );
+            // the innermost frame is reported
+            assert_eq!(
+                trap("nested", i32s(&[1, 0])),
+                (TrapKind::DivisionByZero, Some(1), Some(0x6d))
+            );
+            // unreachable instructions are counted as well
+            assert_eq!(
+                trap("dead", i32s(&[0])),
+                (TrapKind::DivisionByZero, Some(6), Some(0x6d))
+            );
+
+            let err = store.invoke(&instance, "div", i32s(&[1, 0])).unwrap_err();
+            assert_eq!(err.to_string(), "integer divide by zero");
+
+            let err = store.invoke(&instance, "host", vec![]).unwrap_err();
+            let trap = err.downcast_ref::<Trap>().unwrap();
+            assert_eq!(
+                (trap.kind(), trap.func(), opcode(trap.offset())),
+                (TrapKind::HostError, Some(5), Some(0x10))
+            );
+            assert_eq!(err.root_cause().to_string(), "disk full");
+        }
+
+        // traps outside of wasm code have no location
+        let module = decode_wat(r#"(module (memory 0) (data (i32.const 0) "a"))"#);
+        let err = Store::default().instantiate(module).err().unwrap();
+        let trap = err.downcast_ref::<Trap>().unwrap();
+        assert_eq!(trap.kind(), TrapKind::OutOfBoundsMemory);
+        assert_eq!(trap.func(), None);
+
+        // errors caused by the embedder are not traps
+        let (mut store, instance) = instantiate_wat(r#"(module (func (export "f")))"#);
+        let err = store
+            .invoke(&instance, "f", vec![Value::I32(0)])
+            .unwrap_err();
+        assert_eq!(trap_kind(&err), None);
+    }
+
+    #[test]
+    fn test_lazy_bodies() {
+        let wat = r#"
+            (module
+              (func $f (param i32) (result i32)
+                (i32.mul (local.get 0) (i32.const 2)))
+              (func (export "g") (param i32) (result i32)
+                (call $f (i32.add (local.get 0) (i32.const 1))))
+              (func (export "invalid") (result i32)
+                (i64.const 0)))
+            "#;
+        let bin = wat2wasm(wat);
+
+        for engine in [Engine::Stack, Engine::Register] {
+            let module = decode_lazy(&mut std::io::Cursor::new(&bin)).unwrap();
+            assert!(module.funcs.iter().all(|f| f.raw.is_some()));
+            assert!(module.funcs[0].body.instructions.is_empty());
+
+            // invalid bodies are only detected once called
+            let mut store = Store::new(Config {
+                engine,
+                ..Default::default()
+            });
+            let instance = store.instantiate(module).unwrap();
+            let result = store.invoke(&instance, "g", vec![Value::I32(3)]).unwrap();
+            assert_eq!(result, vec![Value::I32(8)]);
+            assert!(store.invoke(&instance, "invalid", vec![]).is_err());
+            let result = store.invoke(&instance, "g", vec![Value::I32(4)]).unwrap();
+            assert_eq!(result, vec![Value::I32(10)]);
+        }
+    }
+
+    #[test]
+    fn test_trap_backtrace() {
+        let wat = r#"
+            (module
+              (func $a (param i32) (result i32)
+                (call $b (local.get 0)))
+              (func $b (param i32) (result i32)
+                (i32.div_u (i32.const 1) (local.get 0)))
+              (func (export "c") (param i32) (result i32)
+                (call $a (local.get 0))))
+            "#;
+        let bin = wat2wasm(wat);
+        for engine in [Engine::Stack, Engine::Register] {
+            let invoke = |module| {
+                let mut store = Store::new(Config {
+                    engine,
+                    ..Default::default()
+                });
+                let instance = store.instantiate(module).unwrap();
+                store
+                    .invoke(&instance, "c", vec![Value::I32(0)])
+                    .unwrap_err()
+            };
+            let err = invoke(decode_lazy(&mut std::io::Cursor::new(&bin)).unwrap());
+            let trap = err.downcast_ref::<Trap>().unwrap();
+
+            let frames = trap
+                .backtrace()
+                .iter()
+                .map(|f| (f.func(), f.name(), f.offset()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                frames,
+                vec![
+                    (1, Some("b"), Some(0x2d)),
+                    (0, Some("a"), Some(0x24)),
+                    (2, None, Some(0x33))
+                ]
+            );
+            // i32.div_u and the calls
+            assert_eq!(bin[0x2d], 0x6e);
+            assert_eq!(bin[0x24], 0x10);
+            assert_eq!(bin[0x33], 0x10);
+            assert_eq!(trap.backtrace()[0].to_string(), "func 1 (b) at offset 0x2d");
+
+            // offsets are only recorded when bodies are decoded on demand
+            let err = invoke(decode(&mut std::io::Cursor::new(&bin)).unwrap());
+            let trap = err.downcast_ref::<Trap>().unwrap();
+            assert_eq!(trap.backtrace().len(), 3);
+            assert_eq!(trap.offset(), None);
+            assert_eq!(trap.backtrace()[0].to_string(), "func 1 (b)");
+        }
+    }
+
+    #[test]
+    fn test_call_indirect_and_tables() {
+        let (mut store, instance) = instantiate_wat(
+            r#"
+            (module
+              (type $ii (func (param i32) (result i32)))
+              (table $t 3 4 funcref)
+              (elem (table $t) (i32.const 0) func $double $inc)
+              (elem $p func $nop)
+              (func $double (type $ii) (i32.mul (local.get 0) (i32.const 2)))
+              (func $inc (type $ii) (i32.add (local.get 0) (i32.const 1)))
+              (func $nop)
+              (func (export "call") (param i32 i32) (result i32)
+                (call_indirect $t (type $ii) (local.get 1) (local.get 0)))
+              (func (export "size") (result i32) (table.size $t))
+              (func (export "grow") (param i32) (result i32)
+                (table.grow $t (ref.null func) (local.get 0)))
+              (func (export "copy")
+                (table.copy $t $t (i32.const 1) (i32.const 0) (i32.const 2)))
+              (func (export "init")
+                (table.init $t $p (i32.const 2) (i32.const 0) (i32.const 1))))
+            "#,
+        );
+
+        let mut call = |i, x| store.invoke(&instance, "call", vec![Value::I32(i), Value::I32(x)]);
+        assert_eq!(call(0, 5).unwrap(), vec![Value::I32(10)]);
+        assert_eq!(call(1, 5).unwrap(), vec![Value::I32(6)]);
+        assert!(call(2, 5).is_err());
+        assert!(call(3, 5).is_err());
+
+        store.invoke(&instance, "copy", vec![]).unwrap();
+        assert_eq!(
+            store
+                .invoke(&instance, "call", vec![Value::I32(2), Value::I32(5)])
+                .unwrap(),
+            vec![Value::I32(6)]
+        );
+
+        store.invoke(&instance, "init", vec![]).unwrap();
+        assert!(store
+            .invoke(&instance, "call", vec![Value::I32(2), Value::I32(5)])
+            .is_err());
+
+        assert_eq!(
+            store
+                .invoke(&instance, "grow", vec![Value::I32(1)])
+                .unwrap(),
+            vec![Value::I32(3)]
+        );
+        assert_eq!(
+            store
+                .invoke(&instance, "grow", vec![Value::I32(1)])
+                .unwrap(),
+            vec![Value::I32(-1)]
+        );
+        assert_eq!(
+            store.invoke(&instance, "size", vec![]).unwrap(),
+            vec![Value::I32(4)]
+        );
+    }
+
+    #[test]
+    fn test_register_engine() {
+        let wat = r#"
+            (module
+              (memory 1)
+              (type $ii (func (param i32) (result i32)))
+              (table 2 funcref)
+              (elem (i32.const 0) $fib $collatz)
+              (func $fib (type $ii) (local i32 i32)
+                (local.set 2 (i32.const 1))
+                (block
+                  (loop
+                    (br_if 1 (i32.lt_s (local.get 0) (i32.const 2)))
+                    (local.set 2 (i32.add (local.get 1) (local.tee 1 (local.get 2))))
+                    (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                    (br 0)))
+                (local.get 1))
+              (func $collatz (type $ii) (local i32)
+                (block
+                  (loop
+                    (br_if 1 (i32.eqz (i32.ne (local.get 0) (i32.const 1))))
+                    (local.set 0
+                      (select
+                        (i32.add (i32.mul (local.get 0) (i32.const 3)) (i32.const 1))
+                        (i32.shr_u (local.get 0) (i32.const 1))
+                        (i32.and (local.get 0) (i32.const 1))))
+                    (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+                    (br 0)))
+                (local.get 1))
+              (func (export "dispatch") (param i32 i32) (result i32)
+                (call_indirect (type $ii) (local.get 1) (local.get 0)))
+              (func (export "memory") (param i32) (result i64)
+                (i64.store offset=8 (local.get 0) (i64.extend_i32_s (local.get 0)))
+                (drop (memory.grow (i32.const 0)))
+                (i64.add (i64.load offset=8 (local.get 0)) (i64.const 1)))
+              (func (export "swap") (param i32 i32) (result i32 i32 i32)
+                (block (result i32 i32 i32)
+                  (local.get 1) (local.get 0) (i32.const 0)
+                  (br_if 0 (i32.eqz (local.get 0)))
+                  (drop)
+                  (i32.const 1))))
+            "#;
+        let run = |engine| {
+            let mut store = Store::new(Config {
+                engine,
+                ..Default::default()
+            });
+            let instance = store.instantiate(decode_wat(wat)).unwrap();
+            let mut results = vec![];
+            for x in [0, 1, 2, 7, 27, -1] {
+                for (name, args) in [
+                    ("dispatch", vec![Value::I32(0), Value::I32(x)]),
+                    ("dispatch", vec![Value::I32(1), Value::I32(x.max(1))]),
+                    ("dispatch", vec![Value::I32(2), Value::I32(x)]),
+                    ("memory", vec![Value::I32(x)]),
+                    ("swap", vec![Value::I32(x), Value::I32(3)]),
+                ] {
+                    results.push(store.invoke(&instance, name, args).ok());
+                }
+            }
+            results
+        };
+
+        let expected = run(Engine::Stack);
+        assert_eq!(expected[0], Some(vec![Value::I32(0)]));
+        assert_eq!(expected[21], Some(vec![Value::I32(111)]));
+        assert_eq!(run(Engine::Register), expected);
+    }
+
     #[test]
     fn test_decode_and_exec() {
         let file = std::fs::File::open("tests/test.wasm").unwrap();
@@ -676,8 +2539,8 @@ mod tests {
         let module = decode(&mut reader).unwrap();
 
         let mut store = Store::default();
-        store.instantiate(module);
-        let value = store.execute(Idx::new(1), vec![]).unwrap();
+        let instance = store.instantiate(module).unwrap();
+        let value = store.execute(&instance, Idx::new(1), vec![]).unwrap();
         assert_eq!(value, vec![Value::I32(42)]);
     }
 
@@ -688,9 +2551,9 @@ mod tests {
         let module = decode(&mut reader).unwrap();
 
         let mut store = Store::default();
-        store.instantiate(module);
+        let instance = store.instantiate(module).unwrap();
         let value = store
-            .execute(Idx::new(1), vec![Value::I32(12), Value::I32(23)])
+            .execute(&instance, Idx::new(1), vec![Value::I32(12), Value::I32(23)])
             .unwrap();
         assert_eq!(value, vec![Value::I32(35)]);
     }
@@ -702,9 +2565,9 @@ mod tests {
         let module = decode(&mut reader).unwrap();
 
         let mut store = Store::default();
-        store.instantiate(module);
+        let instance = store.instantiate(module).unwrap();
         let value = store
-            .execute(Idx::new(1), vec![Value::I32(10), Value::I32(3)])
+            .execute(&instance, Idx::new(1), vec![Value::I32(10), Value::I32(3)])
             .unwrap();
         assert_eq!(value, vec![Value::I32(120)]);
     }
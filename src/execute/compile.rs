@@ -0,0 +1,758 @@
+use super::numeric::{Binary, Unary};
+use super::{
+    Address, DataAddr, ElemAddr, FuncAddr, GlobalAddr, MemAddr, ModuleInstance, TableAddr, Value,
+};
+use crate::core::{BlockType, Func, FuncType, Instruction};
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+
+/// How a branch unwinds the operand stack: the topmost `keep` values are moved
+/// down over the `drop` values below them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropKeep {
+    pub drop: u32,
+    pub keep: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub pc: u32,
+    pub drop_keep: DropKeep,
+}
+
+/// An operation of a lowered function body. Indices are resolved to store
+/// addresses and structured control flow is replaced by jumps.
+#[derive(Clone, Debug)]
+pub enum Operation {
+    // control operations
+    Unreachable,
+    Br(Target),
+    BrIf(Target),
+    /// Jumps to the given pc if the operand is zero, used for `if`.
+    BrIfEqz(u32),
+    /// Jumps to the target selected by the operand; the last target is the
+    /// default.
+    BrTable(Box<[Target]>),
+    Return,
+    Call(Address<FuncAddr>),
+    CallIndirect(Rc<FuncType>, Address<TableAddr>),
+
+    // parametric operations
+    Drop,
+    Select,
+
+    // variable operations
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(Address<GlobalAddr>),
+    GlobalSet(Address<GlobalAddr>),
+
+    // table operations
+    TableGet(Address<TableAddr>),
+    TableSet(Address<TableAddr>),
+    TableSize(Address<TableAddr>),
+    TableGrow(Address<TableAddr>),
+    TableFill(Address<TableAddr>),
+    TableCopy {
+        dst: Address<TableAddr>,
+        src: Address<TableAddr>,
+    },
+    TableInit {
+        elem: Address<ElemAddr>,
+        table: Address<TableAddr>,
+    },
+    ElemDrop(Address<ElemAddr>),
+
+    // memory operations
+    /// Loads from the memory at the operand plus the static offset.
+    Load(LoadKind, Address<MemAddr>, u32),
+    Store(StoreKind, Address<MemAddr>, u32),
+    MemorySize(Address<MemAddr>),
+    MemoryGrow(Address<MemAddr>),
+    MemoryInit(Address<MemAddr>, Address<DataAddr>),
+    DataDrop(Address<DataAddr>),
+    MemoryCopy(Address<MemAddr>),
+    MemoryFill(Address<MemAddr>),
+
+    // numeric operations
+    /// Pushes a constant of any type in its raw representation.
+    Const(u64),
+    Unary(Unary),
+    Binary(Binary),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadKind {
+    I32Load,
+    I64Load,
+    F32Load,
+    F64Load,
+    I32Load8S,
+    I32Load8U,
+    I32Load16S,
+    I32Load16U,
+    I64Load8S,
+    I64Load8U,
+    I64Load16S,
+    I64Load16U,
+    I64Load32S,
+    I64Load32U,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind {
+    I32Store,
+    I64Store,
+    F32Store,
+    F64Store,
+    I32Store8,
+    I32Store16,
+    I64Store8,
+    I64Store16,
+    I64Store32,
+}
+
+/// A function body lowered to a flat sequence of operations.
+#[derive(Debug)]
+pub struct Code {
+    /// Index of the function in the function index space of its module.
+    pub func: u32,
+    pub ops: Vec<Operation>,
+    /// Operand stack height below the operands of each operation, which is
+    /// also where its results go.
+    pub bases: Vec<u32>,
+    /// Byte offset in the module of the instruction each operation was
+    /// lowered from, or empty if the offsets of the instructions are unknown.
+    pub offsets: Vec<u32>,
+    pub params: u32,
+    /// Number of declared locals, excluding the parameters.
+    pub locals: u32,
+    pub results: u32,
+    /// Maximum height of the operand stack above the locals.
+    pub max_height: u32,
+}
+
+/// A block whose end has not been lowered yet.
+struct Ctrl {
+    /// Operand stack height below the block parameters.
+    height: u32,
+    /// Number of values carried by a branch to the block.
+    arity: u32,
+    results: u32,
+    /// Target of branches to a loop.
+    start: Option<u32>,
+    /// Branches to the end of the block, as operation index and table slot.
+    fixups: Vec<(usize, usize)>,
+}
+
+struct Compiler<'a> {
+    module: &'a ModuleInstance,
+    funcs: &'a [Rc<FuncType>],
+    ops: Vec<Operation>,
+    bases: Vec<u32>,
+    offsets: Vec<u32>,
+    /// Byte offsets in the module of the instructions of the function, as
+    /// returned by [`decode_body`](crate::decode::decode_body), or empty.
+    instr_offsets: &'a [u32],
+    /// Byte offset of the instruction being lowered, if known.
+    offset: Option<u32>,
+    /// Number of instructions seen so far.
+    instrs: u32,
+    ctrls: Vec<Ctrl>,
+    height: u32,
+    max_height: u32,
+}
+
+/// Lowers the validated `func` of `module`, which has index `idx` in the
+/// module's function index space. `funcs` holds the types of that space.
+/// `offsets` are the byte offsets of the instructions of `func` if it was
+/// decoded with [`decode_body`](crate::decode::decode_body), or empty.
+pub fn compile(
+    func: &Func,
+    offsets: &[u32],
+    idx: u32,
+    module: &ModuleInstance,
+    funcs: &[Rc<FuncType>],
+) -> Result<Code> {
+    let ty = module.get_type(func.type_id);
+    let mut c = Compiler {
+        module,
+        funcs,
+        ops: vec![],
+        bases: vec![],
+        offsets: vec![],
+        instr_offsets: offsets,
+        offset: None,
+        instrs: 0,
+        ctrls: vec![],
+        height: 0,
+        max_height: 0,
+    };
+
+    // branching to the function body is a return
+    let results = ty.results.len() as u32;
+    c.ctrls.push(Ctrl {
+        height: 0,
+        arity: results,
+        results,
+        start: None,
+        fixups: vec![],
+    });
+    c.compile_seq(&func.body.instructions)?;
+    c.end_block();
+    c.offset = c.instr_offset(c.instrs);
+    c.emit(Operation::Return, 0, 0);
+
+    // slots of a frame are addressed with 32-bit indices, which only limits
+    // functions that could never fit on the stack anyway
+    let locals: u64 = func.locals.iter().map(|(n, _)| *n as u64).sum();
+    let slots = ty.params.len() as u64 + locals + c.max_height as u64;
+    ensure!(
+        slots <= u32::MAX as u64,
+        "function {} has too many locals",
+        idx
+    );
+
+    Ok(Code {
+        func: idx,
+        ops: c.ops,
+        bases: c.bases,
+        offsets: c.offsets,
+        params: ty.params.len() as u32,
+        locals: locals as u32,
+        results,
+        max_height: c.max_height,
+    })
+}
+
+/// Counts `instructions`, including those nested in blocks.
+fn count_instrs(instructions: &[Instruction]) -> u32 {
+    instructions
+        .iter()
+        .map(|instr| match instr {
+            Instruction::Block { instructions, .. } | Instruction::Loop { instructions, .. } => {
+                1 + count_instrs(instructions)
+            }
+            Instruction::If {
+                instructions,
+                else_instructions,
+                ..
+            } => 1 + count_instrs(instructions) + count_instrs(else_instructions),
+            _ => 1,
+        })
+        .sum()
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, op: Operation, pop: u32, push: u32) -> usize {
+        self.bases.push(self.height - pop);
+        self.offsets.extend(self.offset);
+        self.height = self.height - pop + push;
+        self.max_height = self.max_height.max(self.height);
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    /// Returns the byte offset of the instruction with the given index, if
+    /// known.
+    fn instr_offset(&self, index: u32) -> Option<u32> {
+        self.instr_offsets.get(index as usize).copied()
+    }
+
+    fn pc(&self) -> u32 {
+        self.ops.len() as u32
+    }
+
+    fn mem(&self) -> Address<MemAddr> {
+        self.module.mem_addrs[0]
+    }
+
+    fn load(&mut self, kind: LoadKind, offset: u32) {
+        self.emit(Operation::Load(kind, self.mem(), offset), 1, 1);
+    }
+
+    fn store(&mut self, kind: StoreKind, offset: u32) {
+        self.emit(Operation::Store(kind, self.mem(), offset), 2, 0);
+    }
+
+    fn block_arity(&self, block_type: &BlockType) -> (u32, u32) {
+        match block_type {
+            BlockType::ValType(ty) => (0, ty.is_some() as u32),
+            BlockType::Type(idx) => {
+                let ty = self.module.get_type(*idx);
+                (ty.params.len() as u32, ty.results.len() as u32)
+            }
+        }
+    }
+
+    /// Returns the target of a branch to label `depth` emitted as operation
+    /// `op`, recording a fixup if the target is not known yet.
+    fn target(&mut self, depth: u32, op: usize, slot: usize) -> Target {
+        let height = self.height;
+        let ctrl = self.ctrls.iter_mut().rev().nth(depth as usize).unwrap();
+        let drop_keep = DropKeep {
+            drop: height - ctrl.height - ctrl.arity,
+            keep: ctrl.arity,
+        };
+        let pc = match ctrl.start {
+            Some(pc) => pc,
+            None => {
+                ctrl.fixups.push((op, slot));
+                0
+            }
+        };
+        Target { pc, drop_keep }
+    }
+
+    fn patch(&mut self, op: usize, slot: usize, pc: u32) {
+        match &mut self.ops[op] {
+            Operation::Br(target) | Operation::BrIf(target) => target.pc = pc,
+            Operation::BrTable(targets) => targets[slot].pc = pc,
+            Operation::BrIfEqz(target) => *target = pc,
+            op => unreachable!("{:?} is not a branch", op),
+        }
+    }
+
+    fn push_ctrl(&mut self, block_type: &BlockType, start: Option<u32>) {
+        let (params, results) = self.block_arity(block_type);
+        self.ctrls.push(Ctrl {
+            height: self.height - params,
+            arity: if start.is_some() { params } else { results },
+            results,
+            start,
+            fixups: vec![],
+        });
+    }
+
+    fn end_block(&mut self) {
+        let ctrl = self.ctrls.pop().unwrap();
+        let pc = self.pc();
+        for (op, slot) in ctrl.fixups {
+            self.patch(op, slot, pc);
+        }
+        self.height = ctrl.height + ctrl.results;
+    }
+
+    fn compile_seq(&mut self, instructions: &[Instruction]) -> Result<()> {
+        for (i, instr) in instructions.iter().enumerate() {
+            self.compile_instr(instr)?;
+            // the rest of the sequence is unreachable and never lowered, but
+            // still counted; the stack is left as if the block ended normally
+            if matches!(
+                instr,
+                Instruction::Unreachable
+                    | Instruction::Br(_)
+                    | Instruction::BrTable(..)
+                    | Instruction::Return
+            ) {
+                let ctrl = self.ctrls.last().unwrap();
+                self.height = ctrl.height + ctrl.results;
+                self.instrs += count_instrs(&instructions[i + 1..]);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_instr(&mut self, instr: &Instruction) -> Result<()> {
+        let module = self.module;
+        self.offset = self.instr_offset(self.instrs);
+        self.instrs += 1;
+        match instr {
+            Instruction::Unreachable => {
+                self.emit(Operation::Unreachable, 0, 0);
+            }
+            Instruction::Nop => {}
+            Instruction::Block {
+                block_type,
+                instructions,
+            } => {
+                self.push_ctrl(block_type, None);
+                self.compile_seq(instructions)?;
+                self.end_block();
+            }
+            Instruction::Loop {
+                block_type,
+                instructions,
+            } => {
+                self.push_ctrl(block_type, Some(self.pc()));
+                self.compile_seq(instructions)?;
+                self.end_block();
+            }
+            Instruction::If {
+                block_type,
+                instructions,
+                else_instructions,
+            } => {
+                let cond = self.emit(Operation::BrIfEqz(0), 1, 0);
+                self.push_ctrl(block_type, None);
+                self.compile_seq(instructions)?;
+                if else_instructions.is_empty() {
+                    self.ctrls.last_mut().unwrap().fixups.push((cond, 0));
+                } else {
+                    let op = self.ops.len();
+                    let target = self.target(0, op, 0);
+                    self.emit(Operation::Br(target), 0, 0);
+
+                    let pc = self.pc();
+                    self.patch(cond, 0, pc);
+                    let (params, _) = self.block_arity(block_type);
+                    self.height = self.ctrls.last().unwrap().height + params;
+                    self.compile_seq(else_instructions)?;
+                }
+                self.end_block();
+            }
+            Instruction::Br(idx) => {
+                let target = self.target(idx.get(), self.ops.len(), 0);
+                self.emit(Operation::Br(target), 0, 0);
+            }
+            Instruction::BrIf(idx) => {
+                self.height -= 1;
+                let target = self.target(idx.get(), self.ops.len(), 0);
+                self.emit(Operation::BrIf(target), 0, 0);
+            }
+            Instruction::BrTable(labels, default) => {
+                self.height -= 1;
+                let op = self.ops.len();
+                let targets = labels
+                    .iter()
+                    .chain(std::iter::once(default))
+                    .enumerate()
+                    .map(|(slot, idx)| self.target(idx.get(), op, slot))
+                    .collect();
+                self.emit(Operation::BrTable(targets), 0, 0);
+            }
+            Instruction::Return => {
+                self.emit(Operation::Return, 0, 0);
+            }
+            Instruction::Call(idx) => {
+                let ty = &self.funcs[idx.get() as usize];
+                let (params, results) = (ty.params.len() as u32, ty.results.len() as u32);
+                let addr = module.func_addrs[idx.get() as usize];
+                self.emit(Operation::Call(addr), params, results);
+            }
+            Instruction::CallIndirect { ty, table } => {
+                let ty = module.get_type(*ty);
+                let (params, results) = (ty.params.len() as u32, ty.results.len() as u32);
+                let table = module.table_addrs[table.get() as usize];
+                self.emit(Operation::CallIndirect(ty, table), params + 1, results);
+            }
+
+            Instruction::RefNull(ty) => {
+                self.emit(Operation::Const(Value::null(*ty).to_raw()), 0, 1);
+            }
+            Instruction::RefIsNull => {
+                self.emit(Operation::Unary(Unary::RefIsNull), 1, 1);
+            }
+            Instruction::RefFunc(idx) => {
+                let addr = module.func_addrs[idx.get() as usize];
+                self.emit(Operation::Const(Value::FuncRef(Some(addr)).to_raw()), 0, 1);
+            }
+            Instruction::Drop => {
+                self.emit(Operation::Drop, 1, 0);
+            }
+            Instruction::Select(_) => {
+                self.emit(Operation::Select, 3, 1);
+            }
+
+            Instruction::LocalGet(idx) => {
+                self.emit(Operation::LocalGet(idx.get()), 0, 1);
+            }
+            Instruction::LocalSet(idx) => {
+                self.emit(Operation::LocalSet(idx.get()), 1, 0);
+            }
+            Instruction::LocalTee(idx) => {
+                self.emit(Operation::LocalTee(idx.get()), 1, 1);
+            }
+            Instruction::GlobalGet(idx) => {
+                let addr = module.global_addrs[idx.get() as usize];
+                self.emit(Operation::GlobalGet(addr), 0, 1);
+            }
+            Instruction::GlobalSet(idx) => {
+                let addr = module.global_addrs[idx.get() as usize];
+                self.emit(Operation::GlobalSet(addr), 1, 0);
+            }
+
+            Instruction::TableGet(idx) => {
+                let addr = module.table_addrs[idx.get() as usize];
+                self.emit(Operation::TableGet(addr), 1, 1);
+            }
+            Instruction::TableSet(idx) => {
+                let addr = module.table_addrs[idx.get() as usize];
+                self.emit(Operation::TableSet(addr), 2, 0);
+            }
+            Instruction::TableSize(idx) => {
+                let addr = module.table_addrs[idx.get() as usize];
+                self.emit(Operation::TableSize(addr), 0, 1);
+            }
+            Instruction::TableGrow(idx) => {
+                let addr = module.table_addrs[idx.get() as usize];
+                self.emit(Operation::TableGrow(addr), 2, 1);
+            }
+            Instruction::TableFill(idx) => {
+                let addr = module.table_addrs[idx.get() as usize];
+                self.emit(Operation::TableFill(addr), 3, 0);
+            }
+            Instruction::TableCopy { dst, src } => {
+                let dst = module.table_addrs[dst.get() as usize];
+                let src = module.table_addrs[src.get() as usize];
+                self.emit(Operation::TableCopy { dst, src }, 3, 0);
+            }
+            Instruction::TableInit { elem, table } => {
+                let elem = module.elem_addrs[elem.get() as usize];
+                let table = module.table_addrs[table.get() as usize];
+                self.emit(Operation::TableInit { elem, table }, 3, 0);
+            }
+            Instruction::ElemDrop(idx) => {
+                let addr = module.elem_addrs[idx.get() as usize];
+                self.emit(Operation::ElemDrop(addr), 0, 0);
+            }
+
+            Instruction::I32Load(m) => {
+                self.load(LoadKind::I32Load, m.offset);
+            }
+            Instruction::I64Load(m) => {
+                self.load(LoadKind::I64Load, m.offset);
+            }
+            Instruction::F32Load(m) => {
+                self.load(LoadKind::F32Load, m.offset);
+            }
+            Instruction::F64Load(m) => {
+                self.load(LoadKind::F64Load, m.offset);
+            }
+            Instruction::I32Load8S(m) => {
+                self.load(LoadKind::I32Load8S, m.offset);
+            }
+            Instruction::I32Load8U(m) => {
+                self.load(LoadKind::I32Load8U, m.offset);
+            }
+            Instruction::I32Load16S(m) => {
+                self.load(LoadKind::I32Load16S, m.offset);
+            }
+            Instruction::I32Load16U(m) => {
+                self.load(LoadKind::I32Load16U, m.offset);
+            }
+            Instruction::I64Load8S(m) => {
+                self.load(LoadKind::I64Load8S, m.offset);
+            }
+            Instruction::I64Load8U(m) => {
+                self.load(LoadKind::I64Load8U, m.offset);
+            }
+            Instruction::I64Load16S(m) => {
+                self.load(LoadKind::I64Load16S, m.offset);
+            }
+            Instruction::I64Load16U(m) => {
+                self.load(LoadKind::I64Load16U, m.offset);
+            }
+            Instruction::I64Load32S(m) => {
+                self.load(LoadKind::I64Load32S, m.offset);
+            }
+            Instruction::I64Load32U(m) => {
+                self.load(LoadKind::I64Load32U, m.offset);
+            }
+            Instruction::I32Store(m) => {
+                self.store(StoreKind::I32Store, m.offset);
+            }
+            Instruction::I64Store(m) => {
+                self.store(StoreKind::I64Store, m.offset);
+            }
+            Instruction::F32Store(m) => {
+                self.store(StoreKind::F32Store, m.offset);
+            }
+            Instruction::F64Store(m) => {
+                self.store(StoreKind::F64Store, m.offset);
+            }
+            Instruction::I32Store8(m) => {
+                self.store(StoreKind::I32Store8, m.offset);
+            }
+            Instruction::I32Store16(m) => {
+                self.store(StoreKind::I32Store16, m.offset);
+            }
+            Instruction::I64Store8(m) => {
+                self.store(StoreKind::I64Store8, m.offset);
+            }
+            Instruction::I64Store16(m) => {
+                self.store(StoreKind::I64Store16, m.offset);
+            }
+            Instruction::I64Store32(m) => {
+                self.store(StoreKind::I64Store32, m.offset);
+            }
+            Instruction::MemorySize => {
+                self.emit(Operation::MemorySize(self.mem()), 0, 1);
+            }
+            Instruction::MemoryGrow => {
+                self.emit(Operation::MemoryGrow(self.mem()), 1, 1);
+            }
+            Instruction::MemoryInit(idx) => {
+                let data = module.data_addrs[idx.get() as usize];
+                self.emit(Operation::MemoryInit(self.mem(), data), 3, 0);
+            }
+            Instruction::DataDrop(idx) => {
+                let data = module.data_addrs[idx.get() as usize];
+                self.emit(Operation::DataDrop(data), 0, 0);
+            }
+            Instruction::MemoryCopy => {
+                self.emit(Operation::MemoryCopy(self.mem()), 3, 0);
+            }
+            Instruction::MemoryFill => {
+                self.emit(Operation::MemoryFill(self.mem()), 3, 0);
+            }
+
+            Instruction::I32Const(v) => {
+                self.emit(Operation::Const(Value::I32(*v).to_raw()), 0, 1);
+            }
+            Instruction::I64Const(v) => {
+                self.emit(Operation::Const(Value::I64(*v).to_raw()), 0, 1);
+            }
+            Instruction::F32Const(v) => {
+                self.emit(Operation::Const(Value::F32(*v).to_raw()), 0, 1);
+            }
+            Instruction::F64Const(v) => {
+                self.emit(Operation::Const(Value::F64(*v).to_raw()), 0, 1);
+            }
+            Instruction::I32BinOp(op) => {
+                self.emit(Operation::Binary(Binary::I32Bin(op.clone())), 2, 1);
+            }
+            Instruction::I32RelOp(op) => {
+                self.emit(Operation::Binary(Binary::I32Rel(op.clone())), 2, 1);
+            }
+            Instruction::I64BinOp(op) => {
+                self.emit(Operation::Binary(Binary::I64Bin(op.clone())), 2, 1);
+            }
+            Instruction::I64RelOp(op) => {
+                self.emit(Operation::Binary(Binary::I64Rel(op.clone())), 2, 1);
+            }
+            Instruction::F32BinOp(op) => {
+                self.emit(Operation::Binary(Binary::F32Bin(op.clone())), 2, 1);
+            }
+            Instruction::F32RelOp(op) => {
+                self.emit(Operation::Binary(Binary::F32Rel(op.clone())), 2, 1);
+            }
+            Instruction::F64BinOp(op) => {
+                self.emit(Operation::Binary(Binary::F64Bin(op.clone())), 2, 1);
+            }
+            Instruction::F64RelOp(op) => {
+                self.emit(Operation::Binary(Binary::F64Rel(op.clone())), 2, 1);
+            }
+            instr => {
+                let op = unary_op(instr)?;
+                self.emit(Operation::Unary(op), 1, 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lowers the numeric instructions that take one operand and produce one
+/// result.
+fn unary_op(instr: &Instruction) -> Result<Unary> {
+    let op = match instr {
+        Instruction::I32Extend8S => Unary::I32Extend8S,
+        Instruction::I32Extend16S => Unary::I32Extend16S,
+        Instruction::I32UnOp(op) => Unary::I32UnOp(op.clone()),
+        Instruction::I32Eqz => Unary::I32Eqz,
+        Instruction::I64UnOp(op) => Unary::I64UnOp(op.clone()),
+        Instruction::I64Extend8S => Unary::I64Extend8S,
+        Instruction::I64Extend16S => Unary::I64Extend16S,
+        Instruction::I64Extend32S => Unary::I64Extend32S,
+        Instruction::I64Eqz => Unary::I64Eqz,
+        Instruction::F32UnOp(op) => Unary::F32UnOp(op.clone()),
+        Instruction::F64UnOp(op) => Unary::F64UnOp(op.clone()),
+        Instruction::I32WrapI64 => Unary::I32WrapI64,
+        Instruction::I32TruncF32S => Unary::I32TruncF32S,
+        Instruction::I32TruncF32U => Unary::I32TruncF32U,
+        Instruction::I32TruncF64S => Unary::I32TruncF64S,
+        Instruction::I32TruncF64U => Unary::I32TruncF64U,
+        Instruction::I64ExtendI32S => Unary::I64ExtendI32S,
+        Instruction::I64ExtendI32U => Unary::I64ExtendI32U,
+        Instruction::I64TruncF32S => Unary::I64TruncF32S,
+        Instruction::I64TruncF32U => Unary::I64TruncF32U,
+        Instruction::I64TruncF64S => Unary::I64TruncF64S,
+        Instruction::I64TruncF64U => Unary::I64TruncF64U,
+        Instruction::F32ConvertI32S => Unary::F32ConvertI32S,
+        Instruction::F32ConvertI32U => Unary::F32ConvertI32U,
+        Instruction::F32ConvertI64S => Unary::F32ConvertI64S,
+        Instruction::F32ConvertI64U => Unary::F32ConvertI64U,
+        Instruction::F32DemoteF64 => Unary::F32DemoteF64,
+        Instruction::F64ConvertI32S => Unary::F64ConvertI32S,
+        Instruction::F64ConvertI32U => Unary::F64ConvertI32U,
+        Instruction::F64ConvertI64S => Unary::F64ConvertI64S,
+        Instruction::F64ConvertI64U => Unary::F64ConvertI64U,
+        Instruction::F64PromoteF32 => Unary::F64PromoteF32,
+        Instruction::I32ReinterpretF32 => Unary::I32ReinterpretF32,
+        Instruction::I64ReinterpretF64 => Unary::I64ReinterpretF64,
+        Instruction::F32ReinterpretI32 => Unary::F32ReinterpretI32,
+        Instruction::F64ReinterpretI64 => Unary::F64ReinterpretI64,
+        Instruction::I32TruncSatF32S => Unary::I32TruncSatF32S,
+        Instruction::I32TruncSatF32U => Unary::I32TruncSatF32U,
+        Instruction::I32TruncSatF64S => Unary::I32TruncSatF64S,
+        Instruction::I32TruncSatF64U => Unary::I32TruncSatF64U,
+        Instruction::I64TruncSatF32S => Unary::I64TruncSatF32S,
+        Instruction::I64TruncSatF32U => Unary::I64TruncSatF32U,
+        Instruction::I64TruncSatF64S => Unary::I64TruncSatF64S,
+        Instruction::I64TruncSatF64U => Unary::I64TruncSatF64U,
+        _ => bail!("unsupported instruction: {:?}", instr),
+    };
+    Ok(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Expression, Idx, NumType, ValueType};
+
+    #[test]
+    fn test_branch_targets() {
+        let i32 = ValueType::Num(NumType::I32);
+        let module = ModuleInstance {
+            types: vec![Rc::new(FuncType {
+                params: vec![],
+                results: vec![i32],
+            })],
+            ..Default::default()
+        };
+        // (block (result i32) (i32.const 1) (i32.const 2) (br 0)) (loop (br 0))
+        let func = Func {
+            type_id: Idx::new(0),
+            locals: vec![],
+            body: Expression {
+                instructions: vec![
+                    Instruction::Block {
+                        block_type: BlockType::ValType(Some(i32)),
+                        instructions: vec![
+                            Instruction::I32Const(1),
+                            Instruction::I32Const(2),
+                            Instruction::Br(Idx::new(0)),
+                            Instruction::Drop,
+                        ],
+                    },
+                    Instruction::Loop {
+                        block_type: BlockType::ValType(None),
+                        instructions: vec![Instruction::Br(Idx::new(0))],
+                    },
+                ],
+            },
+            raw: None,
+        };
+
+        let code = compile(&func, &[], 0, &module, &[]).unwrap();
+        assert_eq!(code.ops.len(), 5);
+        assert!(matches!(
+            code.ops[2],
+            Operation::Br(Target {
+                pc: 3,
+                drop_keep: DropKeep { drop: 1, keep: 1 },
+            })
+        ));
+        assert!(matches!(
+            code.ops[3],
+            Operation::Br(Target {
+                pc: 3,
+                drop_keep: DropKeep { drop: 0, keep: 0 },
+            })
+        ));
+        assert!(matches!(code.ops[4], Operation::Return));
+        assert_eq!(code.max_height, 2);
+    }
+}
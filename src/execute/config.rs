@@ -0,0 +1,37 @@
+/// Selects how a [`Store`](super::Store) executes wasm functions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Executes the lowered stack bytecode directly. It stays close to the
+    /// specification and serves as the reference for the other engines.
+    #[default]
+    Stack,
+    /// Translates the lowered bytecode to a register machine, folding locals
+    /// and constants into operands and fusing common instruction sequences.
+    Register,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub engine: Engine,
+    /// Maximum number of nested wasm calls, including those of invocations
+    /// made by host functions called from wasm.
+    pub max_call_depth: usize,
+    /// Maximum number of value slots, covering the locals and operands of all
+    /// frames, including those of invocations made by host functions called
+    /// from wasm.
+    pub max_stack_size: usize,
+    /// Maximum number of invocations nested in host functions called from
+    /// wasm. Unlike wasm calls, each of them recurses on the native stack.
+    pub max_host_depth: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            engine: Engine::default(),
+            max_call_depth: 100_000,
+            max_stack_size: 1 << 20,
+            max_host_depth: 100,
+        }
+    }
+}
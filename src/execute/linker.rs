@@ -0,0 +1,108 @@
+use super::{Extern, MemoryInstance, ModuleInstance, Store, Value};
+use crate::core::{FuncType, Module};
+use anyhow::{anyhow, ensure, Result};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Gives host functions access to the store they are called from and to the
+/// exports of the calling instance.
+pub struct Caller<'a> {
+    store: &'a mut Store,
+    instance: Rc<ModuleInstance>,
+}
+
+impl<'a> Caller<'a> {
+    pub(super) fn new(store: &'a mut Store, instance: Rc<ModuleInstance>) -> Self {
+        Caller { store, instance }
+    }
+
+    pub fn store(&self) -> &Store {
+        self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut Store {
+        self.store
+    }
+
+    /// Returns the calling instance. Functions invoked directly by the
+    /// embedder see an instance without exports.
+    pub fn instance(&self) -> &Rc<ModuleInstance> {
+        &self.instance
+    }
+
+    pub fn memory_mut(&mut self, name: &str) -> Result<&mut MemoryInstance> {
+        self.store.memory_mut(&self.instance, name)
+    }
+}
+
+/// Resolves module imports by `(module, name)` to externs defined by the
+/// embedder.
+#[derive(Default)]
+pub struct Linker {
+    items: HashMap<(String, String), Extern>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, module: &str, name: &str, ext: Extern) -> Result<&mut Self> {
+        let key = (module.to_string(), name.to_string());
+        ensure!(
+            !self.items.contains_key(&key),
+            "import already defined: {}.{}",
+            module,
+            name
+        );
+        self.items.insert(key, ext);
+        Ok(self)
+    }
+
+    /// Allocates a host function in `store` and defines it as `module.name`.
+    pub fn func(
+        &mut self,
+        store: &mut Store,
+        module: &str,
+        name: &str,
+        ty: FuncType,
+        func: impl Fn(&mut Caller, &[Value]) -> Result<Vec<Value>> + 'static,
+    ) -> Result<&mut Self> {
+        let addr = store.new_func(ty, func);
+        self.define(module, name, Extern::Func(addr))
+    }
+
+    /// Defines all exports of `instance` under the module name `module`.
+    pub fn register(&mut self, module: &str, instance: &ModuleInstance) -> Result<&mut Self> {
+        for e in instance.exports() {
+            self.define(module, &e.name, e.value)?;
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<Extern> {
+        self.items
+            .get(&(module.to_string(), name.to_string()))
+            .copied()
+    }
+
+    /// Resolves the imports of `module` and instantiates it in `store`.
+    pub fn instantiate(&self, store: &mut Store, module: Module) -> Result<Rc<ModuleInstance>> {
+        let imports = module
+            .imports
+            .iter()
+            .map(|import| {
+                self.get(import.module.as_str(), import.name.as_str())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "unknown import: {}.{}",
+                            import.module.as_str(),
+                            import.name.as_str()
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        store.instantiate_with_imports(module, &imports)
+    }
+}
@@ -0,0 +1,142 @@
+use super::{Trap, TrapKind};
+use crate::core::MemoryType;
+use anyhow::{ensure, Context, Result};
+
+pub const PAGE_SIZE: usize = 65536;
+pub const MAX_PAGES: u32 = 65536;
+
+pub struct MemoryInstance {
+    data: Vec<u8>,
+    max: Option<u32>,
+}
+
+impl MemoryInstance {
+    /// Creates a memory of `ty.min` pages, failing if they cannot be
+    /// allocated.
+    pub fn new(ty: &MemoryType) -> Result<Self> {
+        let len = ty.min as usize * PAGE_SIZE;
+        let mut data = Vec::new();
+        data.try_reserve_exact(len)
+            .with_context(|| format!("failed to allocate memory of {} pages", ty.min))?;
+        data.resize(len, 0);
+        Ok(MemoryInstance { data, max: ty.max })
+    }
+
+    /// Returns the current size in pages.
+    pub fn size(&self) -> u32 {
+        (self.data.len() / PAGE_SIZE) as u32
+    }
+
+    /// Grows the memory by `delta` pages and returns the previous size, or
+    /// `None` if the new size would exceed the maximum or cannot be allocated.
+    pub fn grow(&mut self, delta: u32) -> Option<u32> {
+        let size = self.size();
+        let new_size = size.checked_add(delta)?;
+        if new_size > self.max.unwrap_or(MAX_PAGES).min(MAX_PAGES) {
+            return None;
+        }
+
+        let len = new_size as usize * PAGE_SIZE;
+        self.data.try_reserve_exact(len - self.data.len()).ok()?;
+        self.data.resize(len, 0);
+        Some(size)
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn load<const N: usize>(&self, addr: u64) -> Result<[u8; N]> {
+        let range = self.range(addr, N)?;
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(bytes)
+    }
+
+    pub fn store(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        let range = self.range(addr, bytes.len())?;
+        self.data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Copies `n` bytes from `src` to `dst`. The regions may overlap.
+    pub fn copy(&mut self, dst: u64, src: u64, n: usize) -> Result<()> {
+        let src = self.range(src, n)?;
+        let dst = self.range(dst, n)?;
+        self.data.copy_within(src, dst.start);
+        Ok(())
+    }
+
+    pub fn fill(&mut self, addr: u64, value: u8, n: usize) -> Result<()> {
+        let range = self.range(addr, n)?;
+        self.data[range].fill(value);
+        Ok(())
+    }
+
+    fn range(&self, addr: u64, len: usize) -> Result<std::ops::Range<usize>> {
+        let end = addr + len as u64;
+        ensure!(
+            end <= self.data.len() as u64,
+            Trap::new(TrapKind::OutOfBoundsMemory)
+        );
+        Ok(addr as usize..end as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Limits;
+
+    #[test]
+    fn test_grow() {
+        let mut mem = MemoryInstance::new(&Limits {
+            min: 1,
+            max: Some(2),
+        })
+        .unwrap();
+        assert_eq!(mem.size(), 1);
+        assert_eq!(mem.grow(1), Some(1));
+        assert_eq!(mem.size(), 2);
+        assert_eq!(mem.grow(1), None);
+        assert_eq!(mem.grow(0), Some(2));
+
+        let mut mem = MemoryInstance::new(&Limits { min: 1, max: None }).unwrap();
+        assert_eq!(mem.grow(MAX_PAGES), None);
+        assert_eq!(mem.grow(u32::MAX), None);
+        assert_eq!(mem.size(), 1);
+    }
+
+    #[test]
+    fn test_load_store() {
+        let mut mem = MemoryInstance::new(&Limits { min: 1, max: None }).unwrap();
+        mem.store(8, &0x12345678u32.to_le_bytes()).unwrap();
+        assert_eq!(mem.load::<4>(8).unwrap(), [0x78, 0x56, 0x34, 0x12]);
+        assert!(mem.load::<4>(PAGE_SIZE as u64 - 3).is_err());
+        assert!(mem.store(PAGE_SIZE as u64, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_copy_fill() {
+        let mut mem = MemoryInstance::new(&Limits { min: 1, max: None }).unwrap();
+        mem.store(0, &[1, 2, 3, 4]).unwrap();
+        mem.copy(1, 0, 4).unwrap();
+        assert_eq!(mem.load::<5>(0).unwrap(), [1, 1, 2, 3, 4]);
+        mem.copy(0, 1, 4).unwrap();
+        assert_eq!(mem.load::<5>(0).unwrap(), [1, 2, 3, 4, 4]);
+
+        mem.fill(2, 0xff, 2).unwrap();
+        assert_eq!(mem.load::<5>(0).unwrap(), [1, 2, 0xff, 0xff, 4]);
+        assert!(mem.fill(PAGE_SIZE as u64 - 1, 0, 2).is_err());
+        assert!(mem.copy(0, PAGE_SIZE as u64, 1).is_err());
+        assert!(mem.copy(PAGE_SIZE as u64, 0, 0).is_ok());
+    }
+}
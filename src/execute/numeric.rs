@@ -0,0 +1,456 @@
+use super::{Trap, TrapKind};
+use crate::core::{FBinOp, FRelOp, FUnOp, IBinOp, IRelOp, IUnOp};
+use anyhow::{ensure, Result};
+
+pub trait Float: Copy + PartialOrd + std::ops::Add<Output = Self> {
+    fn is_nan(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+}
+
+impl Float for f32 {
+    fn is_nan(self) -> bool {
+        self.is_nan()
+    }
+
+    fn is_sign_negative(self) -> bool {
+        self.is_sign_negative()
+    }
+}
+
+impl Float for f64 {
+    fn is_nan(self) -> bool {
+        self.is_nan()
+    }
+
+    fn is_sign_negative(self) -> bool {
+        self.is_sign_negative()
+    }
+}
+
+/// `fmin` as defined by the specification: NaN operands propagate and -0 is
+/// considered smaller than +0, unlike `f32::min`.
+pub fn min<T: Float>(a: T, b: T) -> T {
+    if a.is_nan() || b.is_nan() {
+        a + b
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// `fmax` as defined by the specification: NaN operands propagate and +0 is
+/// considered larger than -0, unlike `f32::max`.
+pub fn max<T: Float>(a: T, b: T) -> T {
+    if a.is_nan() || b.is_nan() {
+        a + b
+    } else if a == b {
+        if a.is_sign_negative() {
+            b
+        } else {
+            a
+        }
+    } else if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+fn trunc(v: f64) -> Result<f64> {
+    ensure!(!v.is_nan(), Trap::new(TrapKind::InvalidConversion));
+    Ok(v.trunc())
+}
+
+pub fn trunc_i32(v: f64) -> Result<i32> {
+    let t = trunc(v)?;
+    ensure!(
+        (-2147483648.0..=2147483647.0).contains(&t),
+        Trap::new(TrapKind::IntegerOverflow)
+    );
+    Ok(t as i32)
+}
+
+pub fn trunc_u32(v: f64) -> Result<u32> {
+    let t = trunc(v)?;
+    ensure!(
+        t > -1.0 && t < 4294967296.0,
+        Trap::new(TrapKind::IntegerOverflow)
+    );
+    Ok(t as u32)
+}
+
+pub fn trunc_i64(v: f64) -> Result<i64> {
+    let t = trunc(v)?;
+    ensure!(
+        (-9223372036854775808.0..9223372036854775808.0).contains(&t),
+        Trap::new(TrapKind::IntegerOverflow)
+    );
+    Ok(t as i64)
+}
+
+pub fn trunc_u64(v: f64) -> Result<u64> {
+    let t = trunc(v)?;
+    ensure!(
+        t > -1.0 && t < 18446744073709551616.0,
+        Trap::new(TrapKind::IntegerOverflow)
+    );
+    Ok(t as u64)
+}
+
+/// A numeric or reference operation taking one operand and producing one
+/// result. Both operate on the untagged representation of values.
+#[derive(Clone, Debug)]
+pub enum Unary {
+    I32Extend8S,
+    I32Extend16S,
+    I32UnOp(IUnOp),
+    I32Eqz,
+    I64UnOp(IUnOp),
+    I64Extend8S,
+    I64Extend16S,
+    I64Extend32S,
+    I64Eqz,
+    F32UnOp(FUnOp),
+    F64UnOp(FUnOp),
+    I32WrapI64,
+    I32TruncF32S,
+    I32TruncF32U,
+    I32TruncF64S,
+    I32TruncF64U,
+    I64ExtendI32S,
+    I64ExtendI32U,
+    I64TruncF32S,
+    I64TruncF32U,
+    I64TruncF64S,
+    I64TruncF64U,
+    F32ConvertI32S,
+    F32ConvertI32U,
+    F32ConvertI64S,
+    F32ConvertI64U,
+    F32DemoteF64,
+    F64ConvertI32S,
+    F64ConvertI32U,
+    F64ConvertI64S,
+    F64ConvertI64U,
+    F64PromoteF32,
+    I32ReinterpretF32,
+    I64ReinterpretF64,
+    F32ReinterpretI32,
+    F64ReinterpretI64,
+    I32TruncSatF32S,
+    I32TruncSatF32U,
+    I32TruncSatF64S,
+    I32TruncSatF64U,
+    I64TruncSatF32S,
+    I64TruncSatF32U,
+    I64TruncSatF64S,
+    I64TruncSatF64U,
+    RefIsNull,
+}
+
+/// A numeric operation taking two operands and producing one result.
+#[derive(Clone, Debug)]
+pub enum Binary {
+    I32Bin(IBinOp),
+    I32Rel(IRelOp),
+    I64Bin(IBinOp),
+    I64Rel(IRelOp),
+    F32Bin(FBinOp),
+    F32Rel(FRelOp),
+    F64Bin(FBinOp),
+    F64Rel(FRelOp),
+}
+
+fn from_i32(v: i32) -> u64 {
+    v as u32 as u64
+}
+
+fn from_f32(v: f32) -> u64 {
+    v.to_bits() as u64
+}
+
+fn from_f64(v: f64) -> u64 {
+    v.to_bits()
+}
+
+fn from_bool(v: bool) -> u64 {
+    v as u64
+}
+
+impl Unary {
+    pub fn eval(&self, v: u64) -> Result<u64> {
+        let (i, l) = (v as i32, v as i64);
+        let (f, d) = (f32::from_bits(v as u32), f64::from_bits(v));
+        let res = match self {
+            Unary::I32Extend8S => from_i32(i as i8 as i32),
+            Unary::I32Extend16S => from_i32(i as i16 as i32),
+            Unary::I32UnOp(op) => match op {
+                IUnOp::Clz => i.leading_zeros() as u64,
+                IUnOp::Ctz => i.trailing_zeros() as u64,
+                IUnOp::Popcnt => i.count_ones() as u64,
+            },
+            Unary::I32Eqz => from_bool(i == 0),
+            Unary::I64UnOp(op) => match op {
+                IUnOp::Clz => l.leading_zeros() as u64,
+                IUnOp::Ctz => l.trailing_zeros() as u64,
+                IUnOp::Popcnt => l.count_ones() as u64,
+            },
+            Unary::I64Extend8S => l as i8 as u64,
+            Unary::I64Extend16S => l as i16 as u64,
+            Unary::I64Extend32S => l as i32 as u64,
+            Unary::I64Eqz => from_bool(l == 0),
+            Unary::F32UnOp(op) => from_f32(match op {
+                FUnOp::Abs => f.abs(),
+                FUnOp::Neg => -f,
+                FUnOp::Ceil => f.ceil(),
+                FUnOp::Floor => f.floor(),
+                FUnOp::Trunc => f.trunc(),
+                FUnOp::Nearest => f.round_ties_even(),
+                FUnOp::Sqrt => f.sqrt(),
+            }),
+            Unary::F64UnOp(op) => from_f64(match op {
+                FUnOp::Abs => d.abs(),
+                FUnOp::Neg => -d,
+                FUnOp::Ceil => d.ceil(),
+                FUnOp::Floor => d.floor(),
+                FUnOp::Trunc => d.trunc(),
+                FUnOp::Nearest => d.round_ties_even(),
+                FUnOp::Sqrt => d.sqrt(),
+            }),
+            Unary::I32WrapI64 => from_i32(l as i32),
+            Unary::I32TruncF32S => from_i32(trunc_i32(f as f64)?),
+            Unary::I32TruncF32U => trunc_u32(f as f64)? as u64,
+            Unary::I32TruncF64S => from_i32(trunc_i32(d)?),
+            Unary::I32TruncF64U => trunc_u32(d)? as u64,
+            Unary::I64ExtendI32S => i as i64 as u64,
+            Unary::I64ExtendI32U => i as u32 as u64,
+            Unary::I64TruncF32S => trunc_i64(f as f64)? as u64,
+            Unary::I64TruncF32U => trunc_u64(f as f64)?,
+            Unary::I64TruncF64S => trunc_i64(d)? as u64,
+            Unary::I64TruncF64U => trunc_u64(d)?,
+            Unary::F32ConvertI32S => from_f32(i as f32),
+            Unary::F32ConvertI32U => from_f32(i as u32 as f32),
+            Unary::F32ConvertI64S => from_f32(l as f32),
+            Unary::F32ConvertI64U => from_f32(l as u64 as f32),
+            Unary::F32DemoteF64 => from_f32(d as f32),
+            Unary::F64ConvertI32S => from_f64(i as f64),
+            Unary::F64ConvertI32U => from_f64(i as u32 as f64),
+            Unary::F64ConvertI64S => from_f64(l as f64),
+            Unary::F64ConvertI64U => from_f64(l as u64 as f64),
+            Unary::F64PromoteF32 => from_f64(f as f64),
+            // the untagged representations already are the bit patterns
+            Unary::I32ReinterpretF32
+            | Unary::I64ReinterpretF64
+            | Unary::F32ReinterpretI32
+            | Unary::F64ReinterpretI64 => v,
+            // `as` casts from float to int saturate and map NaN to 0, which is
+            // exactly the semantics of `trunc_sat`.
+            Unary::I32TruncSatF32S => from_i32(f as i32),
+            Unary::I32TruncSatF32U => f as u32 as u64,
+            Unary::I32TruncSatF64S => from_i32(d as i32),
+            Unary::I32TruncSatF64U => d as u32 as u64,
+            Unary::I64TruncSatF32S => f as i64 as u64,
+            Unary::I64TruncSatF32U => f as u64,
+            Unary::I64TruncSatF64S => d as i64 as u64,
+            Unary::I64TruncSatF64U => d as u64,
+            Unary::RefIsNull => from_bool(v == 0),
+        };
+        Ok(res)
+    }
+}
+
+impl Binary {
+    pub fn eval(&self, a: u64, b: u64) -> Result<u64> {
+        let res = match self {
+            Binary::I32Bin(op) => from_i32(i32_binop(op, a as i32, b as i32)?),
+            Binary::I32Rel(op) => from_bool(i32_relop(op, a as i32, b as i32)),
+            Binary::I64Bin(op) => i64_binop(op, a as i64, b as i64)? as u64,
+            Binary::I64Rel(op) => from_bool(i64_relop(op, a as i64, b as i64)),
+            Binary::F32Bin(op) => {
+                let (a, b) = (f32::from_bits(a as u32), f32::from_bits(b as u32));
+                from_f32(match op {
+                    FBinOp::Add => a + b,
+                    FBinOp::Sub => a - b,
+                    FBinOp::Mul => a * b,
+                    FBinOp::Div => a / b,
+                    FBinOp::Min => min(a, b),
+                    FBinOp::Max => max(a, b),
+                    FBinOp::Copysign => a.copysign(b),
+                })
+            }
+            Binary::F32Rel(op) => {
+                let (a, b) = (f32::from_bits(a as u32), f32::from_bits(b as u32));
+                from_bool(frelop(op, a, b))
+            }
+            Binary::F64Bin(op) => {
+                let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+                from_f64(match op {
+                    FBinOp::Add => a + b,
+                    FBinOp::Sub => a - b,
+                    FBinOp::Mul => a * b,
+                    FBinOp::Div => a / b,
+                    FBinOp::Min => min(a, b),
+                    FBinOp::Max => max(a, b),
+                    FBinOp::Copysign => a.copysign(b),
+                })
+            }
+            Binary::F64Rel(op) => {
+                let (a, b) = (f64::from_bits(a), f64::from_bits(b));
+                from_bool(frelop(op, a, b))
+            }
+        };
+        Ok(res)
+    }
+}
+
+fn i32_binop(op: &IBinOp, v1: i32, v2: i32) -> Result<i32> {
+    let res = match op {
+        IBinOp::Add => v1.wrapping_add(v2),
+        IBinOp::Sub => v1.wrapping_sub(v2),
+        IBinOp::Mul => v1.wrapping_mul(v2),
+        IBinOp::DivS => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            ensure!(
+                v1 != i32::MIN || v2 != -1,
+                Trap::new(TrapKind::IntegerOverflow)
+            );
+            v1.wrapping_div(v2)
+        }
+        IBinOp::DivU => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            (v1 as u32 / v2 as u32) as i32
+        }
+        IBinOp::RemS => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            v1.wrapping_rem(v2)
+        }
+        IBinOp::RemU => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            (v1 as u32 % v2 as u32) as i32
+        }
+        IBinOp::And => v1 & v2,
+        IBinOp::Or => v1 | v2,
+        IBinOp::Xor => v1 ^ v2,
+        IBinOp::Shl => v1.wrapping_shl(v2 as u32),
+        IBinOp::ShrS => v1.wrapping_shr(v2 as u32),
+        IBinOp::ShrU => (v1 as u32).wrapping_shr(v2 as u32) as i32,
+        IBinOp::Rotl => v1.rotate_left(v2 as u32),
+        IBinOp::Rotr => v1.rotate_right(v2 as u32),
+    };
+    Ok(res)
+}
+
+fn i64_binop(op: &IBinOp, v1: i64, v2: i64) -> Result<i64> {
+    let res = match op {
+        IBinOp::Add => v1.wrapping_add(v2),
+        IBinOp::Sub => v1.wrapping_sub(v2),
+        IBinOp::Mul => v1.wrapping_mul(v2),
+        IBinOp::DivS => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            ensure!(
+                v1 != i64::MIN || v2 != -1,
+                Trap::new(TrapKind::IntegerOverflow)
+            );
+            v1.wrapping_div(v2)
+        }
+        IBinOp::DivU => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            (v1 as u64 / v2 as u64) as i64
+        }
+        IBinOp::RemS => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            v1.wrapping_rem(v2)
+        }
+        IBinOp::RemU => {
+            ensure!(v2 != 0, Trap::new(TrapKind::DivisionByZero));
+            (v1 as u64 % v2 as u64) as i64
+        }
+        IBinOp::And => v1 & v2,
+        IBinOp::Or => v1 | v2,
+        IBinOp::Xor => v1 ^ v2,
+        IBinOp::Shl => v1.wrapping_shl(v2 as u32),
+        IBinOp::ShrS => v1.wrapping_shr(v2 as u32),
+        IBinOp::ShrU => (v1 as u64).wrapping_shr(v2 as u32) as i64,
+        IBinOp::Rotl => v1.rotate_left(v2 as u32),
+        IBinOp::Rotr => v1.rotate_right(v2 as u32),
+    };
+    Ok(res)
+}
+
+fn i32_relop(op: &IRelOp, v1: i32, v2: i32) -> bool {
+    match op {
+        IRelOp::Eq => v1 == v2,
+        IRelOp::Ne => v1 != v2,
+        IRelOp::LtS => v1 < v2,
+        IRelOp::LtU => (v1 as u32) < v2 as u32,
+        IRelOp::GtS => v1 > v2,
+        IRelOp::GtU => v1 as u32 > v2 as u32,
+        IRelOp::LeS => v1 <= v2,
+        IRelOp::LeU => v1 as u32 <= v2 as u32,
+        IRelOp::GeS => v1 >= v2,
+        IRelOp::GeU => v1 as u32 >= v2 as u32,
+    }
+}
+
+fn i64_relop(op: &IRelOp, v1: i64, v2: i64) -> bool {
+    match op {
+        IRelOp::Eq => v1 == v2,
+        IRelOp::Ne => v1 != v2,
+        IRelOp::LtS => v1 < v2,
+        IRelOp::LtU => (v1 as u64) < v2 as u64,
+        IRelOp::GtS => v1 > v2,
+        IRelOp::GtU => v1 as u64 > v2 as u64,
+        IRelOp::LeS => v1 <= v2,
+        IRelOp::LeU => v1 as u64 <= v2 as u64,
+        IRelOp::GeS => v1 >= v2,
+        IRelOp::GeU => v1 as u64 >= v2 as u64,
+    }
+}
+
+fn frelop<T: Float>(op: &FRelOp, v1: T, v2: T) -> bool {
+    match op {
+        FRelOp::Eq => v1 == v2,
+        FRelOp::Ne => v1 != v2,
+        FRelOp::Lt => v1 < v2,
+        FRelOp::Gt => v1 > v2,
+        FRelOp::Le => v1 <= v2,
+        FRelOp::Ge => v1 >= v2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_max() {
+        assert!(min(0.0f32, -0.0).is_sign_negative());
+        assert!(min(-0.0f32, 0.0).is_sign_negative());
+        assert!(!max(-0.0f64, 0.0).is_sign_negative());
+        assert!(min(1.0f32, f32::NAN).is_nan());
+        assert!(max(f64::NAN, 1.0).is_nan());
+        assert_eq!(min(1.0f32, 2.0), 1.0);
+        assert_eq!(max(1.0f64, 2.0), 2.0);
+    }
+
+    #[test]
+    fn test_trunc() {
+        assert_eq!(trunc_i32(-2147483648.9).unwrap(), i32::MIN);
+        assert!(trunc_i32(2147483648.0).is_err());
+        assert_eq!(trunc_u32(-0.9).unwrap(), 0);
+        assert!(trunc_u32(-1.0).is_err());
+        assert!(trunc_i64(9223372036854775808.0).is_err());
+        assert_eq!(
+            trunc_u64(18446744073709549568.0).unwrap(),
+            18446744073709549568
+        );
+        assert!(trunc_u64(f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,752 @@
+use super::compile::{self, LoadKind, Operation, StoreKind, Target};
+use super::numeric::{Binary, Unary};
+use super::{
+    Address, Body, FrameInfo, FuncAddr, FuncInstance, GlobalAddr, MemAddr, ModuleInstance, Store,
+    TableAddr, Trap, TrapKind, Value,
+};
+use crate::core::{FuncType, ValueType};
+use anyhow::{bail, ensure, Result};
+use std::rc::Rc;
+
+/// Where an instruction reads a value from. Registers are numbered from the
+/// frame pointer: parameters and locals first, then one register per operand
+/// stack slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(u32),
+    Const(u64),
+}
+
+/// A branch that moves `n` registers starting at `src` down to `dst` before
+/// jumping to `pc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jump {
+    pub pc: u32,
+    pub src: u32,
+    pub dst: u32,
+    pub n: u32,
+}
+
+impl Jump {
+    fn new(target: &Target, height: u32) -> Self {
+        let keep = target.drop_keep.keep;
+        let src = height - keep;
+        Jump {
+            pc: target.pc,
+            src,
+            dst: src - target.drop_keep.drop,
+            n: keep,
+        }
+    }
+
+    fn take(&self, regs: &mut [u64], fp: usize) -> usize {
+        if self.n > 0 && self.src != self.dst {
+            let src = fp + self.src as usize;
+            regs.copy_within(src..src + self.n as usize, fp + self.dst as usize);
+        }
+        self.pc as usize
+    }
+}
+
+#[derive(Debug)]
+pub enum Instr {
+    Unreachable,
+    Br(Jump),
+    BrIf(Operand, Jump),
+    BrIfEqz(Operand, Jump),
+    /// A comparison fused with the `br_if` consuming its result.
+    BrIfBinary {
+        op: Binary,
+        lhs: Operand,
+        rhs: Operand,
+        jump: Jump,
+    },
+    BrTable(u32, Box<[Jump]>),
+    /// Returns the results starting at the given register.
+    Return(u32),
+    /// Calls a function with the arguments starting at `base`, which is also
+    /// where the results are written.
+    Call {
+        func: Address<FuncAddr>,
+        base: u32,
+    },
+    CallIndirect {
+        ty: Rc<FuncType>,
+        table: Address<TableAddr>,
+        base: u32,
+    },
+    Copy {
+        dst: u32,
+        src: Operand,
+    },
+    /// Selects between the registers `dst` and `dst + 1` by `dst + 2`.
+    Select {
+        dst: u32,
+    },
+    GlobalGet {
+        dst: u32,
+        global: Address<GlobalAddr>,
+    },
+    GlobalSet {
+        src: Operand,
+        global: Address<GlobalAddr>,
+    },
+    Unary {
+        op: Unary,
+        dst: u32,
+        src: Operand,
+    },
+    Binary {
+        op: Binary,
+        dst: u32,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Load {
+        kind: LoadKind,
+        mem: Address<MemAddr>,
+        offset: u32,
+        dst: u32,
+        addr: Operand,
+    },
+    Store {
+        kind: StoreKind,
+        mem: Address<MemAddr>,
+        offset: u32,
+        addr: Operand,
+        value: Operand,
+    },
+    /// A table or bulk memory operation, reading its operands from and
+    /// writing its result to the registers starting at the given one.
+    Other(Operation, u32),
+}
+
+/// A function body translated to register form.
+#[derive(Debug)]
+pub struct Code {
+    /// Index of the function in the function index space of its module.
+    pub func: u32,
+    pub instrs: Vec<Instr>,
+    /// Byte offset in the module of the wasm instruction each instruction was
+    /// translated from, or empty if unknown as in [`compile::Code::offsets`].
+    pub offsets: Vec<u32>,
+    pub params: u32,
+    pub locals: u32,
+    pub results: u32,
+    /// Number of registers used for operand stack slots.
+    pub max_height: u32,
+}
+
+struct Translator {
+    /// Number of registers holding parameters and locals.
+    locals: u32,
+    instrs: Vec<Instr>,
+    offsets: Vec<u32>,
+    /// Byte offset of the wasm instruction being translated, if known.
+    offset: Option<u32>,
+    /// Instructions before this index must not be fused with later ones, as
+    /// a branch may land in between.
+    barrier: usize,
+}
+
+/// Translates lowered stack bytecode to register form.
+pub fn translate(code: &compile::Code) -> Code {
+    let mut targets = vec![false; code.ops.len()];
+    for op in &code.ops {
+        match op {
+            Operation::Br(t) | Operation::BrIf(t) => targets[t.pc as usize] = true,
+            Operation::BrIfEqz(pc) => targets[*pc as usize] = true,
+            Operation::BrTable(ts) => ts.iter().for_each(|t| targets[t.pc as usize] = true),
+            _ => {}
+        }
+    }
+
+    let mut t = Translator {
+        locals: code.params + code.locals,
+        instrs: vec![],
+        offsets: vec![],
+        offset: None,
+        barrier: 0,
+    };
+    // index of the first instruction translated from each operation
+    let mut pcs = Vec::with_capacity(code.ops.len());
+    for (pc, (op, base)) in code.ops.iter().zip(&code.bases).enumerate() {
+        if targets[pc] {
+            t.barrier = t.instrs.len();
+        }
+        pcs.push(t.instrs.len() as u32);
+        t.offset = code.offsets.get(pc).copied();
+        t.translate(op, *base, code.results);
+    }
+
+    let mut instrs = t.instrs;
+    for instr in &mut instrs {
+        match instr {
+            Instr::Br(jump)
+            | Instr::BrIf(_, jump)
+            | Instr::BrIfEqz(_, jump)
+            | Instr::BrIfBinary { jump, .. } => jump.pc = pcs[jump.pc as usize],
+            Instr::BrTable(_, jumps) => jumps.iter_mut().for_each(|j| j.pc = pcs[j.pc as usize]),
+            _ => {}
+        }
+    }
+
+    Code {
+        func: code.func,
+        instrs,
+        offsets: t.offsets,
+        params: code.params,
+        locals: code.locals,
+        results: code.results,
+        max_height: code.max_height,
+    }
+}
+
+impl Translator {
+    fn reg(&self, slot: u32) -> u32 {
+        self.locals + slot
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.instrs.push(instr);
+        self.offsets.extend(self.offset);
+    }
+
+    /// Removes and returns the last instruction if it may be fused with the
+    /// one being translated and `pred` holds for it.
+    fn take_last(&mut self, pred: impl Fn(&Instr) -> bool) -> Option<Instr> {
+        match self.instrs.last() {
+            Some(instr) if self.instrs.len() > self.barrier && pred(instr) => {
+                self.offsets.pop();
+                self.instrs.pop()
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the operand for the value in register `reg`, folding the copy
+    /// of a local or constant that produced it. Stack slots are consumed
+    /// exactly once, so the copy is dead afterwards.
+    fn operand(&mut self, reg: u32) -> Operand {
+        match self.take_last(|i| matches!(i, Instr::Copy { dst, .. } if *dst == reg)) {
+            Some(Instr::Copy { src, .. }) => src,
+            _ => Operand::Reg(reg),
+        }
+    }
+
+    /// Makes the last instruction write to `local` directly if it produced
+    /// the value in `reg`.
+    fn retarget(&mut self, reg: u32, local: u32) -> bool {
+        if self.instrs.len() <= self.barrier {
+            return false;
+        }
+        match self.instrs.last_mut() {
+            Some(
+                Instr::Copy { dst, .. }
+                | Instr::Unary { dst, .. }
+                | Instr::Binary { dst, .. }
+                | Instr::Load { dst, .. }
+                | Instr::GlobalGet { dst, .. },
+            ) if *dst == reg => {
+                *dst = local;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn translate(&mut self, op: &Operation, base: u32, results: u32) {
+        let reg = self.reg(base);
+        match op {
+            Operation::Unreachable => self.emit(Instr::Unreachable),
+            Operation::Br(target) => self.emit(Instr::Br(Jump::new(target, reg))),
+            Operation::BrIf(target) => {
+                let jump = Jump::new(target, reg);
+                // only comparisons are fused, as they cannot trap
+                let fused = self.take_last(|i| match i {
+                    Instr::Binary {
+                        op:
+                            Binary::I32Rel(_)
+                            | Binary::I64Rel(_)
+                            | Binary::F32Rel(_)
+                            | Binary::F64Rel(_),
+                        dst,
+                        ..
+                    } => *dst == reg,
+                    Instr::Unary {
+                        op: Unary::I32Eqz | Unary::I64Eqz,
+                        dst,
+                        ..
+                    } => *dst == reg,
+                    _ => false,
+                });
+                match fused {
+                    Some(Instr::Binary { op, lhs, rhs, .. }) => {
+                        self.emit(Instr::BrIfBinary { op, lhs, rhs, jump })
+                    }
+                    // both are true exactly when the untagged operand is zero
+                    Some(Instr::Unary { src, .. }) => self.emit(Instr::BrIfEqz(src, jump)),
+                    _ => {
+                        let cond = self.operand(reg);
+                        self.emit(Instr::BrIf(cond, jump));
+                    }
+                }
+            }
+            Operation::BrIfEqz(pc) => {
+                let jump = Jump {
+                    pc: *pc,
+                    src: 0,
+                    dst: 0,
+                    n: 0,
+                };
+                let cond = self.operand(reg);
+                self.emit(Instr::BrIfEqz(cond, jump));
+            }
+            Operation::BrTable(targets) => {
+                let jumps = targets.iter().map(|t| Jump::new(t, reg)).collect();
+                self.emit(Instr::BrTable(reg, jumps));
+            }
+            Operation::Return => self.emit(Instr::Return(reg - results)),
+            Operation::Call(func) => self.emit(Instr::Call {
+                func: *func,
+                base: reg,
+            }),
+            Operation::CallIndirect(ty, table) => self.emit(Instr::CallIndirect {
+                ty: ty.clone(),
+                table: *table,
+                base: reg,
+            }),
+
+            Operation::Drop => {}
+            Operation::Select => self.emit(Instr::Select { dst: reg }),
+
+            Operation::LocalGet(i) => self.emit(Instr::Copy {
+                dst: reg,
+                src: Operand::Reg(*i),
+            }),
+            Operation::LocalSet(i) => {
+                if !self.retarget(reg, *i) {
+                    self.emit(Instr::Copy {
+                        dst: *i,
+                        src: Operand::Reg(reg),
+                    });
+                }
+            }
+            Operation::LocalTee(i) => self.emit(Instr::Copy {
+                dst: *i,
+                src: Operand::Reg(reg),
+            }),
+            Operation::GlobalGet(global) => self.emit(Instr::GlobalGet {
+                dst: reg,
+                global: *global,
+            }),
+            Operation::GlobalSet(global) => {
+                let src = self.operand(reg);
+                self.emit(Instr::GlobalSet {
+                    src,
+                    global: *global,
+                });
+            }
+
+            Operation::Load(kind, mem, offset) => {
+                let addr = self.operand(reg);
+                self.emit(Instr::Load {
+                    kind: *kind,
+                    mem: *mem,
+                    offset: *offset,
+                    dst: reg,
+                    addr,
+                });
+            }
+            Operation::Store(kind, mem, offset) => {
+                let value = self.operand(reg + 1);
+                let addr = self.operand(reg);
+                self.emit(Instr::Store {
+                    kind: *kind,
+                    mem: *mem,
+                    offset: *offset,
+                    addr,
+                    value,
+                });
+            }
+
+            Operation::Const(v) => self.emit(Instr::Copy {
+                dst: reg,
+                src: Operand::Const(*v),
+            }),
+            Operation::Unary(op) => {
+                let src = self.operand(reg);
+                self.emit(Instr::Unary {
+                    op: op.clone(),
+                    dst: reg,
+                    src,
+                });
+            }
+            Operation::Binary(op) => {
+                let rhs = self.operand(reg + 1);
+                let lhs = self.operand(reg);
+                self.emit(Instr::Binary {
+                    op: op.clone(),
+                    dst: reg,
+                    lhs,
+                    rhs,
+                });
+            }
+
+            op => self.emit(Instr::Other(op.clone(), reg)),
+        }
+    }
+}
+
+/// An activation of a wasm function in register form.
+struct Frame {
+    code: Rc<Code>,
+    module: Rc<ModuleInstance>,
+    pc: usize,
+    fp: usize,
+}
+
+impl Frame {
+    /// Enters `code`, whose arguments are in the registers starting at `fp`.
+    /// Fails if its registers would extend past `limit`.
+    fn enter(
+        regs: &mut Vec<u64>,
+        code: Rc<Code>,
+        module: Rc<ModuleInstance>,
+        fp: usize,
+        limit: usize,
+    ) -> Result<Self> {
+        let params = fp + code.params as usize;
+        let locals = params + code.locals as usize;
+        let end = locals + code.max_height as usize;
+        ensure!(end <= limit, Trap::new(TrapKind::StackOverflow));
+        if regs.len() < end {
+            regs.resize(end, 0);
+        }
+        regs[params..locals].fill(0);
+        Ok(Frame {
+            code,
+            module,
+            pc: 0,
+            fp,
+        })
+    }
+
+    /// Describes the function and the instruction being executed.
+    fn info(&self) -> FrameInfo {
+        let func = self.code.func;
+        let offset = self.code.offsets.get(self.pc - 1).copied();
+        FrameInfo::new(func, self.module.func_name(func), offset)
+    }
+}
+
+fn get(regs: &[u64], fp: usize, operand: Operand) -> u64 {
+    match operand {
+        Operand::Reg(r) => regs[fp + r as usize],
+        Operand::Const(v) => v,
+    }
+}
+
+impl Store {
+    /// Runs `code` with the arguments in the first registers of `regs`,
+    /// leaving its results in their place.
+    pub(super) fn run_register(
+        &mut self,
+        regs: &mut Vec<u64>,
+        code: Rc<Code>,
+        module: Rc<ModuleInstance>,
+    ) -> Result<()> {
+        let mut frames = Vec::new();
+        let limit = self.max_slots();
+        let mut frame = Frame::enter(regs, code, module, 0, limit)?;
+        self.run_register_frames(regs, &mut frames, &mut frame)
+            .map_err(|err| {
+                let frames = std::iter::once(&frame).chain(frames.iter().rev());
+                Trap::trace(err, frames.map(Frame::info))
+            })
+    }
+
+    /// Runs the dispatch loop, leaving `frame` at the trapping instruction if
+    /// it fails.
+    fn run_register_frames(
+        &mut self,
+        regs: &mut Vec<u64>,
+        frames: &mut Vec<Frame>,
+        frame: &mut Frame,
+    ) -> Result<()> {
+        loop {
+            let instr = &frame.code.instrs[frame.pc];
+            let fp = frame.fp;
+            frame.pc += 1;
+            match instr {
+                Instr::Unreachable => bail!(Trap::new(TrapKind::Unreachable)),
+                Instr::Br(jump) => frame.pc = jump.take(regs, fp),
+                Instr::BrIf(cond, jump) => {
+                    if get(regs, fp, *cond) != 0 {
+                        frame.pc = jump.take(regs, fp);
+                    }
+                }
+                Instr::BrIfEqz(cond, jump) => {
+                    if get(regs, fp, *cond) == 0 {
+                        frame.pc = jump.take(regs, fp);
+                    }
+                }
+                Instr::BrIfBinary { op, lhs, rhs, jump } => {
+                    if op.eval(get(regs, fp, *lhs), get(regs, fp, *rhs))? != 0 {
+                        frame.pc = jump.take(regs, fp);
+                    }
+                }
+                Instr::BrTable(index, jumps) => {
+                    let i = regs[fp + *index as usize] as u32 as usize;
+                    frame.pc = jumps[i.min(jumps.len() - 1)].take(regs, fp);
+                }
+                Instr::Return(src) => {
+                    let src = fp + *src as usize;
+                    regs.copy_within(src..src + frame.code.results as usize, fp);
+                    match frames.pop() {
+                        Some(caller) => *frame = caller,
+                        None => return Ok(()),
+                    }
+                }
+                Instr::Call { func, base } => {
+                    let (func, base) = (*func, fp + *base as usize);
+                    self.call_register(regs, frames, frame, func, base)?;
+                }
+                Instr::CallIndirect { ty, table, base } => {
+                    let base = fp + *base as usize;
+                    let i = regs[base + ty.params.len()] as u32;
+                    let table = &self.tables[table.address as usize];
+                    ensure!(i < table.size(), Trap::new(TrapKind::UndefinedElement));
+
+                    let Value::FuncRef(Some(addr)) = table.get(i)? else {
+                        bail!(Trap::new(TrapKind::UninitializedElement));
+                    };
+                    ensure!(
+                        *self.funcs[addr.address as usize].ty() == **ty,
+                        Trap::new(TrapKind::IndirectCallTypeMismatch)
+                    );
+                    self.call_register(regs, frames, frame, addr, base)?;
+                }
+
+                Instr::Copy { dst, src } => regs[fp + *dst as usize] = get(regs, fp, *src),
+                Instr::Select { dst } => {
+                    let dst = fp + *dst as usize;
+                    if regs[dst + 2] as u32 == 0 {
+                        regs[dst] = regs[dst + 1];
+                    }
+                }
+                Instr::GlobalGet { dst, global } => {
+                    let v = self.globals[global.address as usize].get();
+                    regs[fp + *dst as usize] = v.to_raw();
+                }
+                Instr::GlobalSet { src, global } => {
+                    let global = &mut self.globals[global.address as usize];
+                    global.value = Value::from_raw(get(regs, fp, *src), global.ty.value_type);
+                }
+                Instr::Unary { op, dst, src } => {
+                    regs[fp + *dst as usize] = op.eval(get(regs, fp, *src))?;
+                }
+                Instr::Binary { op, dst, lhs, rhs } => {
+                    let v = op.eval(get(regs, fp, *lhs), get(regs, fp, *rhs))?;
+                    regs[fp + *dst as usize] = v;
+                }
+                Instr::Load {
+                    kind,
+                    mem,
+                    offset,
+                    dst,
+                    addr,
+                } => {
+                    let addr = get(regs, fp, *addr) as u32;
+                    regs[fp + *dst as usize] = self.load(*kind, *mem, addr, *offset)?;
+                }
+                Instr::Store {
+                    kind,
+                    mem,
+                    offset,
+                    addr,
+                    value,
+                } => {
+                    let addr = get(regs, fp, *addr) as u32;
+                    let v = get(regs, fp, *value);
+                    self.store(*kind, *mem, addr, *offset, v)?;
+                }
+                Instr::Other(op, base) => {
+                    self.execute_other(op, &mut regs[fp + *base as usize..])?
+                }
+            }
+        }
+    }
+
+    /// Calls the function at `addr` with the arguments in the registers
+    /// starting at the absolute index `base`.
+    fn call_register(
+        &mut self,
+        regs: &mut Vec<u64>,
+        frames: &mut Vec<Frame>,
+        frame: &mut Frame,
+        addr: Address<FuncAddr>,
+        base: usize,
+    ) -> Result<()> {
+        self.prepare(addr)?;
+        match &self.funcs[addr.address as usize] {
+            FuncInstance::Wasm {
+                body: Body::Register(code),
+                module,
+                ..
+            } => {
+                ensure!(
+                    frames.len() + 1 < self.max_depth(),
+                    Trap::new(TrapKind::StackOverflow)
+                );
+                let limit = self.max_slots();
+                let callee = Frame::enter(regs, code.clone(), module.clone(), base, limit)?;
+                frames.push(std::mem::replace(frame, callee));
+            }
+            FuncInstance::Wasm { .. } => unreachable!("function lowered for another engine"),
+            FuncInstance::Host { ty, func } => {
+                let (ty, func) = (ty.clone(), func.clone());
+                let args = regs[base..base + ty.params.len()]
+                    .iter()
+                    .zip(&ty.params)
+                    .map(|(raw, ty)| Value::from_raw(*raw, *ty))
+                    .collect::<Vec<_>>();
+                let usage = (frames.len() + 1, base);
+                let values =
+                    self.call_host_nested(usage, &func, &ty, frame.module.clone(), &args)?;
+                let end = base + values.len();
+                if regs.len() < end {
+                    regs.resize(end, 0);
+                }
+                for (reg, v) in regs[base..end].iter_mut().zip(values) {
+                    *reg = v.to_raw();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_other(&mut self, op: &Operation, regs: &mut [u64]) -> Result<()> {
+        let ref_type = |store: &Store, table: Address<TableAddr>| {
+            ValueType::Ref(store.tables[table.address as usize].ty().elem_type)
+        };
+        match op {
+            Operation::TableGet(table) => {
+                let v = self.tables[table.address as usize].get(regs[0] as u32)?;
+                regs[0] = v.to_raw();
+            }
+            Operation::TableSet(table) => {
+                let v = Value::from_raw(regs[1], ref_type(self, *table));
+                self.tables[table.address as usize].set(regs[0] as u32, v)?;
+            }
+            Operation::TableSize(table) => {
+                regs[0] = self.tables[table.address as usize].size() as u64;
+            }
+            Operation::TableGrow(table) => {
+                let v = Value::from_raw(regs[0], ref_type(self, *table));
+                let res = self.tables[table.address as usize].grow(regs[1] as u32, v);
+                regs[0] = res.map_or(-1, |size| size as i32) as u32 as u64;
+            }
+            Operation::TableFill(table) => {
+                let v = Value::from_raw(regs[1], ref_type(self, *table));
+                self.tables[table.address as usize].fill(regs[0] as u32, v, regs[2] as u32)?;
+            }
+            Operation::TableCopy { dst, src } => {
+                let (d, s, n) = (regs[0] as u32, regs[1] as u32, regs[2] as u32);
+                let src = &self.tables[src.address as usize];
+                let values = src.elements()[src.range(s, n)?].to_vec();
+                self.tables[dst.address as usize]
+                    .elements_mut(d, n)?
+                    .copy_from_slice(&values);
+            }
+            Operation::TableInit { elem, table } => {
+                let (d, s, n) = (regs[0] as u32, regs[1] as u32, regs[2] as u32);
+                self.init_table(*table, *elem, d, s, n)?;
+            }
+            Operation::ElemDrop(elem) => self.drop_elem(*elem),
+            Operation::MemorySize(mem) => {
+                regs[0] = self.mems[mem.address as usize].size() as u64;
+            }
+            Operation::MemoryGrow(mem) => {
+                let res = self.mems[mem.address as usize].grow(regs[0] as u32);
+                regs[0] = res.map_or(-1, |size| size as i32) as u32 as u64;
+            }
+            Operation::MemoryInit(mem, data) => {
+                let (d, s, n) = (regs[0] as u32, regs[1] as u32, regs[2] as u32);
+                self.init_memory(*mem, *data, d, s, n)?;
+            }
+            Operation::DataDrop(data) => self.drop_data(*data),
+            Operation::MemoryCopy(mem) => {
+                let (d, s, n) = (regs[0] as u32, regs[1] as u32, regs[2] as u32);
+                self.mems[mem.address as usize].copy(d as u64, s as u64, n as usize)?;
+            }
+            Operation::MemoryFill(mem) => {
+                let (d, v, n) = (regs[0] as u32, regs[1] as u8, regs[2] as u32);
+                self.mems[mem.address as usize].fill(d as u64, v, n as usize)?;
+            }
+            op => unreachable!("{:?} has a register form", op),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockType, Expression, Func, IBinOp, IRelOp, Idx, Instruction, NumType};
+
+    #[test]
+    fn test_fusion() {
+        let i32 = ValueType::Num(NumType::I32);
+        let module = ModuleInstance {
+            types: vec![Rc::new(FuncType {
+                params: vec![i32, i32],
+                results: vec![],
+            })],
+            ..Default::default()
+        };
+        // (loop
+        //   (local.set 2 (i32.add (local.get 0) (local.get 1)))
+        //   (br_if 0 (i32.lt_s (local.get 2) (i32.const 10))))
+        let func = Func {
+            type_id: Idx::new(0),
+            locals: vec![(1, i32)],
+            body: Expression {
+                instructions: vec![Instruction::Loop {
+                    block_type: BlockType::ValType(None),
+                    instructions: vec![
+                        Instruction::LocalGet(Idx::new(0)),
+                        Instruction::LocalGet(Idx::new(1)),
+                        Instruction::I32BinOp(IBinOp::Add),
+                        Instruction::LocalSet(Idx::new(2)),
+                        Instruction::LocalGet(Idx::new(2)),
+                        Instruction::I32Const(10),
+                        Instruction::I32RelOp(IRelOp::LtS),
+                        Instruction::BrIf(Idx::new(0)),
+                    ],
+                }],
+            },
+            raw: None,
+        };
+
+        let code = translate(&compile::compile(&func, &[], 0, &module, &[]).unwrap());
+        assert!(matches!(
+            code.instrs[..],
+            [
+                Instr::Binary {
+                    op: Binary::I32Bin(IBinOp::Add),
+                    dst: 2,
+                    lhs: Operand::Reg(0),
+                    rhs: Operand::Reg(1),
+                },
+                Instr::BrIfBinary {
+                    op: Binary::I32Rel(IRelOp::LtS),
+                    lhs: Operand::Reg(2),
+                    rhs: Operand::Const(10),
+                    jump: Jump { pc: 0, n: 0, .. },
+                },
+                Instr::Return(3),
+            ]
+        ));
+    }
+}
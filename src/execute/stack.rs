@@ -1,123 +1,129 @@
-use super::Value;
-use crate::core::{Idx, Instruction, LocalIdx, ValueType};
-use anyhow::{bail, ensure, Result};
-use std::collections::VecDeque;
-
-pub enum StackEntry<'a> {
-    Value(Value),
-    Label(Label<'a>),
-    // Frame(Frame),
+use super::compile::{Code, DropKeep};
+use super::{FrameInfo, ModuleInstance, Trap, TrapKind, Value};
+use crate::core::ValueType;
+use anyhow::{ensure, Result};
+use std::rc::Rc;
+
+/// The operand stack shared by all frames of an invocation. Locals of a frame
+/// live directly below its operands.
+///
+/// Validation guarantees the type of every slot, so values are stored as
+/// untagged raw bits and only converted back to a [`Value`] at the boundary to
+/// the embedder.
+pub struct Stack {
+    values: Vec<u64>,
+    /// Maximum number of slots frames may occupy.
+    limit: usize,
 }
 
-pub struct Label<'a> {
-    _arity: usize,
-    _instr: &'a Vec<Instruction>,
-}
-
-impl<'a> Label<'a> {
-    pub fn new(arity: usize, instr: &'a Vec<Instruction>) -> Self {
-        Label {
-            _arity: arity,
-            _instr: instr,
+impl Stack {
+    pub fn new(limit: usize) -> Self {
+        Stack {
+            values: Vec::new(),
+            limit,
         }
     }
-}
 
-#[derive(Default)]
-pub struct Stack<'a> {
-    data: VecDeque<StackEntry<'a>>,
-}
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
 
-impl<'a> Stack<'a> {
-    pub fn push_value(&mut self, value: Value) {
-        self.data.push_front(StackEntry::Value(value));
+    pub fn push(&mut self, value: u64) {
+        self.values.push(value);
     }
 
-    pub fn push_label(&mut self, label: Label<'a>) {
-        self.data.push_front(StackEntry::Label(label));
+    pub fn pop(&mut self) -> u64 {
+        self.values.pop().expect("operand stack underflow")
     }
 
     pub fn push_i32(&mut self, value: i32) {
-        self.push_value(Value::I32(value));
+        self.push(value as u32 as u64);
     }
 
-    pub fn push_i64(&mut self, value: i64) {
-        self.push_value(Value::I64(value));
+    pub fn pop_i32(&mut self) -> i32 {
+        self.pop() as i32
     }
 
-    pub fn pop_value(&mut self) -> Result<Value> {
-        let Some(StackEntry::Value(value)) = self.data.pop_front() else {
-            bail!("expected value on stack");
-        };
-
-        Ok(value)
+    pub fn push_values(&mut self, values: &[Value]) {
+        self.values
+            .extend(values.iter().copied().map(Value::to_raw));
     }
 
-    pub fn pop_and_check_value(&mut self, ty: ValueType) -> Result<Value> {
-        let value = self.pop_value()?;
-        ensure!(
-            value.get_type() == ty,
-            "failed to pop value: expected type {:?}",
-            ty
-        );
-        Ok(value)
+    /// Pops values of the given `types`, returning them in stack order.
+    pub fn pop_values(&mut self, types: &[ValueType]) -> Vec<Value> {
+        let start = self.values.len() - types.len();
+        self.values
+            .drain(start..)
+            .zip(types)
+            .map(|(raw, ty)| Value::from_raw(raw, *ty))
+            .collect()
     }
 
-    pub fn pop_and_check_values(&mut self, types: &[ValueType]) -> Result<Vec<Value>> {
-        types
-            .iter()
-            .map(|ty| self.pop_and_check_value(*ty))
-            .collect::<Result<Vec<Value>>>()
+    pub fn get(&self, i: usize) -> u64 {
+        self.values[i]
     }
 
-    pub fn pop_i32(&mut self) -> Result<i32> {
-        let Ok(Value::I32(value)) = self.pop_value() else {
-            bail!("expected i32 on stack");
-        };
-
-        Ok(value)
+    pub fn set(&mut self, i: usize, value: u64) {
+        self.values[i] = value;
     }
 
-    pub fn pop_i64(&mut self) -> Result<i64> {
-        let Ok(Value::I64(value)) = self.pop_value() else {
-            bail!("expected i64 on stack");
-        };
-
-        Ok(value)
+    /// Moves the topmost `keep` values down over the `drop` values below them.
+    pub fn drop_keep(&mut self, drop_keep: DropKeep) {
+        let drop = drop_keep.drop as usize;
+        if drop == 0 {
+            return;
+        }
+        let len = self.values.len();
+        let keep = drop_keep.keep as usize;
+        self.values.copy_within(len - keep.., len - keep - drop);
+        self.values.truncate(len - drop);
     }
 
-    pub fn pop_label(&mut self) -> Result<Label<'a>> {
-        let Some(StackEntry::Label(label)) = self.data.pop_front() else {
-            bail!("expected label on stack");
-        };
-
-        Ok(label)
+    /// Pushes `n` zeroed slots, which is the default value of every type.
+    pub fn push_zeros(&mut self, n: usize) {
+        self.values.resize(self.values.len() + n, 0);
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+    pub fn reserve(&mut self, n: usize) {
+        self.values.reserve(n);
     }
 }
 
-#[derive(Default)]
+/// An activation of a wasm function.
 pub struct Frame {
-    locals: Vec<Value>,
+    pub code: Rc<Code>,
+    pub module: Rc<ModuleInstance>,
+    /// Index of the next operation to execute.
+    pub pc: usize,
+    /// Stack index of the first local.
+    pub fp: usize,
 }
 
 impl Frame {
-    pub fn new(locals: Vec<Value>) -> Self {
-        Frame { locals }
-    }
-
-    pub fn get_local(&self, idx: Idx<LocalIdx>) -> Value {
-        self.locals[idx.get() as usize]
+    /// Enters `code`, whose arguments are on top of `stack`, by allocating its
+    /// declared locals. Fails if its locals and operands would not fit within
+    /// the limit of `stack`.
+    pub fn enter(stack: &mut Stack, code: Rc<Code>, module: Rc<ModuleInstance>) -> Result<Self> {
+        let fp = stack.len() - code.params as usize;
+        let size = code.locals as usize + code.max_height as usize;
+        ensure!(
+            stack.len() + size <= stack.limit,
+            Trap::new(TrapKind::StackOverflow)
+        );
+        stack.push_zeros(code.locals as usize);
+        stack.reserve(code.max_height as usize);
+        Ok(Frame {
+            code,
+            module,
+            pc: 0,
+            fp,
+        })
     }
 
-    pub fn set_local(&mut self, idx: Idx<LocalIdx>, value: Value) {
-        if idx.get() as usize >= self.locals.len() {
-            self.locals.resize(idx.get() as usize + 1, Value::I32(0));
-        }
-
-        self.locals[idx.get() as usize] = value;
+    /// Describes the function and the operation being executed.
+    pub fn info(&self) -> FrameInfo {
+        let func = self.code.func;
+        let offset = self.code.offsets.get(self.pc - 1).copied();
+        FrameInfo::new(func, self.module.func_name(func), offset)
     }
 }
@@ -0,0 +1,109 @@
+use super::{Trap, TrapKind, Value};
+use crate::core::TableType;
+use anyhow::{ensure, Result};
+
+/// Upper bound on the number of elements a table may grow to when its type
+/// does not declare a smaller maximum.
+pub const MAX_TABLE_SIZE: u32 = 10_000_000;
+
+pub struct TableInstance {
+    ty: TableType,
+    elem: Vec<Value>,
+}
+
+impl TableInstance {
+    pub fn new(ty: &TableType, init: Value) -> Self {
+        TableInstance {
+            ty: ty.clone(),
+            elem: vec![init; ty.limits.min as usize],
+        }
+    }
+
+    pub fn ty(&self) -> &TableType {
+        &self.ty
+    }
+
+    pub fn size(&self) -> u32 {
+        self.elem.len() as u32
+    }
+
+    /// Grows the table by `delta` elements initialized to `init` and returns
+    /// the previous size, or `None` if the new size would exceed the maximum.
+    pub fn grow(&mut self, delta: u32, init: Value) -> Option<u32> {
+        let size = self.size();
+        let new_size = size.checked_add(delta)?;
+        if new_size > self.ty.limits.max.unwrap_or(u32::MAX).min(MAX_TABLE_SIZE) {
+            return None;
+        }
+
+        self.elem.resize(new_size as usize, init);
+        self.ty.limits.min = new_size;
+        Some(size)
+    }
+
+    pub fn get(&self, idx: u32) -> Result<Value> {
+        let range = self.range(idx, 1)?;
+        Ok(self.elem[range.start])
+    }
+
+    pub fn set(&mut self, idx: u32, value: Value) -> Result<()> {
+        let range = self.range(idx, 1)?;
+        self.elem[range.start] = value;
+        Ok(())
+    }
+
+    pub fn fill(&mut self, idx: u32, value: Value, n: u32) -> Result<()> {
+        let range = self.range(idx, n)?;
+        self.elem[range].fill(value);
+        Ok(())
+    }
+
+    pub fn elements(&self) -> &[Value] {
+        &self.elem
+    }
+
+    pub fn elements_mut(&mut self, idx: u32, n: u32) -> Result<&mut [Value]> {
+        let range = self.range(idx, n)?;
+        Ok(&mut self.elem[range])
+    }
+
+    pub fn range(&self, idx: u32, n: u32) -> Result<std::ops::Range<usize>> {
+        let end = idx as u64 + n as u64;
+        ensure!(
+            end <= self.elem.len() as u64,
+            Trap::new(TrapKind::OutOfBoundsTable)
+        );
+        Ok(idx as usize..end as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Limits, RefType};
+    use crate::execute::Address;
+
+    #[test]
+    fn test_grow_and_access() {
+        let ty = TableType {
+            limits: Limits {
+                min: 1,
+                max: Some(3),
+            },
+            elem_type: RefType::Funcref,
+        };
+        let f = |addr| Value::FuncRef(Some(Address::new(addr)));
+        let mut table = TableInstance::new(&ty, Value::FuncRef(None));
+
+        assert_eq!(table.grow(2, f(0)), Some(1));
+        assert_eq!(table.grow(1, f(0)), None);
+        assert_eq!(table.size(), 3);
+        assert_eq!(table.get(0).unwrap(), Value::FuncRef(None));
+        assert_eq!(table.get(2).unwrap(), f(0));
+
+        table.set(0, f(1)).unwrap();
+        assert_eq!(table.get(0).unwrap(), f(1));
+        assert!(table.get(3).is_err());
+        assert!(table.fill(2, f(2), 2).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+
+/// The reason wasm code trapped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapKind {
+    Unreachable,
+    IntegerOverflow,
+    DivisionByZero,
+    InvalidConversion,
+    OutOfBoundsMemory,
+    OutOfBoundsTable,
+    UndefinedElement,
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    /// The call depth or the value stack exceeded the limits of the
+    /// [`Config`](super::Config).
+    StackOverflow,
+    /// A host function called by wasm code returned an error, which is the
+    /// [`source`](std::error::Error::source) of the trap.
+    HostError,
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TrapKind::Unreachable => "unreachable",
+            TrapKind::IntegerOverflow => "integer overflow",
+            TrapKind::DivisionByZero => "integer divide by zero",
+            TrapKind::InvalidConversion => "invalid conversion to integer",
+            TrapKind::OutOfBoundsMemory => "out of bounds memory access",
+            TrapKind::OutOfBoundsTable => "out of bounds table access",
+            TrapKind::UndefinedElement => "undefined element",
+            TrapKind::UninitializedElement => "uninitialized element",
+            TrapKind::IndirectCallTypeMismatch => "indirect call type mismatch",
+            TrapKind::StackOverflow => "call stack exhausted",
+            TrapKind::HostError => "host function error",
+        };
+        f.write_str(message)
+    }
+}
+
+/// A wasm function that was executing when a trap occurred.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    func: u32,
+    name: Option<String>,
+    offset: Option<u32>,
+}
+
+impl FrameInfo {
+    pub(super) fn new(func: u32, name: Option<&str>, offset: Option<u32>) -> Self {
+        FrameInfo {
+            func,
+            name: name.map(String::from),
+            offset,
+        }
+    }
+
+    /// Returns the index of the function in the function index space of its
+    /// module.
+    pub fn func(&self) -> u32 {
+        self.func
+    }
+
+    /// Returns the name of the function from the `name` section, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the byte offset in the module of the executing instruction,
+    /// which lies within the code section, as shown by disassemblers such as
+    /// `wasm-objdump -d`. In callers, this is the call instruction. Offsets
+    /// are only known for functions decoded with
+    /// [`decode_lazy`](crate::decode::decode_lazy).
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
+}
+
+impl fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "func {} ({})", self.func, name)?,
+            None => write!(f, "func {}", self.func)?,
+        }
+        match self.offset {
+            Some(offset) => write!(f, " at offset {:#x}", offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An error raised by wasm code at runtime, as opposed to one caused by
+/// misuse of the embedding API. Traps are reported through [`anyhow::Error`]
+/// and can be recovered with `downcast_ref::<Trap>()`.
+#[derive(Debug)]
+pub struct Trap {
+    kind: TrapKind,
+    backtrace: Vec<FrameInfo>,
+    source: Option<anyhow::Error>,
+}
+
+impl Trap {
+    pub fn new(kind: TrapKind) -> Self {
+        Trap {
+            kind,
+            backtrace: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub(super) fn host(source: anyhow::Error) -> Self {
+        Trap {
+            source: Some(source),
+            ..Trap::new(TrapKind::HostError)
+        }
+    }
+
+    pub fn kind(&self) -> TrapKind {
+        self.kind
+    }
+
+    /// Returns the index of the trapping function in the function index
+    /// space of its module, or `None` if the trap did not occur in wasm code,
+    /// e.g. while initializing segments during instantiation.
+    pub fn func(&self) -> Option<u32> {
+        self.backtrace.first().map(FrameInfo::func)
+    }
+
+    /// Returns the byte offset in the module of the trapping instruction, see
+    /// [`FrameInfo::offset`].
+    pub fn offset(&self) -> Option<u32> {
+        self.backtrace.first().and_then(FrameInfo::offset)
+    }
+
+    /// Returns the wasm functions that were executing when the trap occurred,
+    /// innermost first. Frames of host functions are not included, but wasm
+    /// functions they called are.
+    pub fn backtrace(&self) -> &[FrameInfo] {
+        &self.backtrace
+    }
+
+    /// Appends `frames`, ordered from the innermost, to the backtrace of `err`
+    /// if it is a trap.
+    pub(super) fn trace(
+        mut err: anyhow::Error,
+        frames: impl IntoIterator<Item = FrameInfo>,
+    ) -> anyhow::Error {
+        if let Some(trap) = err.downcast_mut::<Trap>() {
+            trap.backtrace.extend(frames);
+        }
+        err
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl std::error::Error for Trap {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|err| err.as_ref())
+    }
+}
@@ -2,4 +2,7 @@
 
 pub mod core;
 pub mod decode;
+pub mod disassemble;
+pub mod encode;
 pub mod execute;
+pub mod validate;
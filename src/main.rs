@@ -1,3 +1,14 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io::BufReader;
+use wasm_runtime::{decode::decode_lazy, disassemble::disassemble};
+
+/// Prints the disassembly of the module at the path given as argument.
+fn main() -> Result<()> {
+    let Some(path) = std::env::args().nth(1) else {
+        bail!("usage: wasm-runtime <module.wasm>");
+    };
+    let module = decode_lazy(&mut BufReader::new(File::open(path)?))?;
+    print!("{}", disassemble(&module)?);
+    Ok(())
 }
@@ -0,0 +1,394 @@
+use self::context::Context;
+use self::instruction::InstrValidator;
+use crate::core::{
+    DataMode, ElementMode, ExportDesc, Expression, Func, Global, ImportDesc, Instruction, Limits,
+    Module, NumType, TableType, ValueType,
+};
+use anyhow::{bail, ensure, Context as _, Result};
+use std::collections::HashSet;
+
+mod context;
+mod instruction;
+
+const MAX_PAGES: u32 = 65536;
+
+pub fn validate(module: &Module) -> Result<()> {
+    let ctx = Context::new(module);
+
+    for ty in &module.types {
+        for ty in ty.params.iter().chain(&ty.results) {
+            validate_value_type(*ty)?;
+        }
+    }
+
+    for import in &module.imports {
+        match &import.desc {
+            ImportDesc::Func(ty) => {
+                ctx.get_type(*ty)?;
+            }
+            ImportDesc::Table(ty) => validate_table_type(ty)?,
+            ImportDesc::Memory(limits) => validate_memory_type(limits)?,
+            ImportDesc::Global(ty) => validate_value_type(ty.value_type)?,
+        }
+    }
+
+    // bodies whose decoding was deferred are validated once decoded
+    for (i, func) in module.funcs.iter().enumerate() {
+        if func.raw.is_none() {
+            validate_func(&ctx, func).with_context(|| format!("invalid function {}", i))?;
+        }
+    }
+
+    for table in &module.tables {
+        validate_table_type(&table.0)?;
+    }
+
+    for memory in &module.memories {
+        validate_memory_type(&memory.0)?;
+    }
+    ensure!(ctx.mems.len() <= 1, "multiple memories");
+
+    for (i, global) in module.globals.iter().enumerate() {
+        validate_global(&ctx, global).with_context(|| format!("invalid global {}", i))?;
+    }
+
+    for (i, elem) in module.elements.iter().enumerate() {
+        for init in &elem.init {
+            validate_const_expr(&ctx, init, ValueType::Ref(elem.ty))
+                .with_context(|| format!("invalid element segment {}", i))?;
+        }
+
+        if let ElementMode::Active { table, offset } = &elem.mode {
+            let table = ctx.get_table(*table)?;
+            ensure!(table.elem_type == elem.ty, "type mismatch");
+            validate_const_expr(&ctx, offset, ValueType::Num(NumType::I32))
+                .with_context(|| format!("invalid element segment {}", i))?;
+        }
+    }
+
+    for (i, data) in module.datas.iter().enumerate() {
+        if let DataMode::Active { memory, offset } = &data.mode {
+            ctx.ensure_memory(*memory)?;
+            validate_const_expr(&ctx, offset, ValueType::Num(NumType::I32))
+                .with_context(|| format!("invalid data segment {}", i))?;
+        }
+    }
+
+    if let Some(start) = module.start {
+        let ty = ctx.get_func_type(start)?;
+        ensure!(
+            ty.params.is_empty() && ty.results.is_empty(),
+            "start function must have type [] -> []"
+        );
+    }
+
+    let mut names = HashSet::new();
+    for export in &module.exports {
+        ensure!(
+            names.insert(export.name.as_str()),
+            "duplicate export name: {}",
+            export.name.as_str()
+        );
+
+        match export.desc {
+            ExportDesc::Func(idx) => {
+                ctx.get_func_type(idx)?;
+            }
+            ExportDesc::Table(idx) => {
+                ctx.get_table(idx)?;
+            }
+            ExportDesc::Memory(idx) => ctx.ensure_memory(idx)?,
+            ExportDesc::Global(idx) => {
+                ctx.get_global(idx)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates functions of a module separately from the module, e.g. once their
+/// deferred bodies have been decoded.
+pub struct FuncValidator {
+    ctx: Context,
+}
+
+impl FuncValidator {
+    pub fn new(module: &Module) -> Self {
+        FuncValidator {
+            ctx: Context::new(module),
+        }
+    }
+
+    /// Validates `func`, which must be a function of the module this validator
+    /// was created for.
+    pub fn validate(&self, func: &Func) -> Result<()> {
+        validate_func(&self.ctx, func)
+    }
+}
+
+fn validate_func(ctx: &Context, func: &Func) -> Result<()> {
+    let ty = ctx.get_type(func.type_id)?;
+    for (_, ty) in &func.locals {
+        validate_value_type(*ty)?;
+    }
+
+    let locals = ty
+        .params
+        .iter()
+        .map(|ty| (1, *ty))
+        .chain(func.locals.iter().copied());
+
+    let mut validator = InstrValidator::new(ctx, locals);
+    validator.validate_expr(&func.body.instructions, &ty.results)
+}
+
+fn validate_global(ctx: &Context, global: &Global) -> Result<()> {
+    validate_value_type(global.global_type.value_type)?;
+    validate_const_expr(ctx, &global.init, global.global_type.value_type)
+}
+
+fn validate_const_expr(ctx: &Context, expr: &Expression, ty: ValueType) -> Result<()> {
+    for instr in &expr.instructions {
+        match instr {
+            Instruction::I32Const(_)
+            | Instruction::I64Const(_)
+            | Instruction::F32Const(_)
+            | Instruction::F64Const(_)
+            | Instruction::RefNull(_)
+            | Instruction::RefFunc(_) => {}
+            Instruction::GlobalGet(idx) => {
+                let global = ctx.get_global(*idx)?;
+                ensure!(
+                    (idx.get() as usize) < ctx.imported_globals,
+                    "unknown global {}",
+                    idx.get()
+                );
+                ensure!(!global.mutability, "constant expression required");
+            }
+            _ => bail!("constant expression required"),
+        }
+    }
+
+    let mut validator = InstrValidator::new(ctx, []);
+    validator.validate_expr(&expr.instructions, &[ty])
+}
+
+/// Rejects vector types, as the runtime cannot hold values of them.
+fn validate_value_type(ty: ValueType) -> Result<()> {
+    ensure!(
+        !matches!(ty, ValueType::Vec(_)),
+        "vector types are not supported"
+    );
+    Ok(())
+}
+
+fn validate_limits(limits: &Limits, range: u32, message: &str) -> Result<()> {
+    ensure!(limits.min <= range, "{}", message);
+    if let Some(max) = limits.max {
+        ensure!(max <= range, "{}", message);
+        ensure!(
+            limits.min <= max,
+            "size minimum must not be greater than maximum"
+        );
+    }
+    Ok(())
+}
+
+fn validate_table_type(ty: &TableType) -> Result<()> {
+    validate_limits(&ty.limits, u32::MAX, "table size must be at most 2^32-1")
+}
+
+fn validate_memory_type(limits: &Limits) -> Result<()> {
+    validate_limits(
+        limits,
+        MAX_PAGES,
+        "memory size must be at most 65536 pages (4GiB)",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockType, Data, Export, FuncType, IBinOp, Idx, Name};
+
+    fn module_with_func(ty: FuncType, instructions: Vec<Instruction>) -> Module {
+        Module {
+            types: vec![ty],
+            funcs: vec![Func {
+                type_id: Idx::new(0),
+                locals: vec![],
+                body: Expression { instructions },
+                raw: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    const I32: ValueType = ValueType::Num(NumType::I32);
+    const I64: ValueType = ValueType::Num(NumType::I64);
+
+    #[test]
+    fn test_valid_add() {
+        let ty = FuncType {
+            params: vec![I32, I32],
+            results: vec![I32],
+        };
+        let module = module_with_func(
+            ty,
+            vec![
+                Instruction::LocalGet(Idx::from(0)),
+                Instruction::LocalGet(Idx::from(1)),
+                Instruction::I32BinOp(IBinOp::Add),
+            ],
+        );
+        validate(&module).unwrap();
+    }
+
+    #[test]
+    fn test_block_params() {
+        let ty = FuncType {
+            params: vec![I32],
+            results: vec![I32],
+        };
+        let block = |instructions| Instruction::Block {
+            block_type: BlockType::Type(Idx::new(0)),
+            instructions,
+        };
+
+        let module = module_with_func(
+            ty.clone(),
+            vec![Instruction::LocalGet(Idx::from(0)), block(vec![])],
+        );
+        assert!(validate(&module).is_ok());
+
+        // block parameters are not available outside of the block
+        let module = module_with_func(
+            ty,
+            vec![
+                Instruction::LocalGet(Idx::from(0)),
+                block(vec![Instruction::I32BinOp(IBinOp::Add)]),
+            ],
+        );
+        assert!(validate(&module).is_err());
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let ty = FuncType {
+            params: vec![I64],
+            results: vec![I32],
+        };
+        let module = module_with_func(ty, vec![Instruction::LocalGet(Idx::from(0))]);
+        assert!(validate(&module).is_err());
+    }
+
+    #[test]
+    fn test_unknown_local_and_label() {
+        let ty = FuncType {
+            params: vec![],
+            results: vec![],
+        };
+        let module = module_with_func(ty.clone(), vec![Instruction::LocalGet(Idx::from(0))]);
+        assert!(validate(&module).is_err());
+
+        let module = module_with_func(ty, vec![Instruction::Br(Idx::from(1))]);
+        assert!(validate(&module).is_err());
+    }
+
+    #[test]
+    fn test_unreachable_is_polymorphic() {
+        let ty = FuncType {
+            params: vec![],
+            results: vec![I32],
+        };
+        let module = module_with_func(
+            ty,
+            vec![Instruction::Block {
+                block_type: BlockType::ValType(Some(I32)),
+                instructions: vec![Instruction::Unreachable, Instruction::I32BinOp(IBinOp::Add)],
+            }],
+        );
+        validate(&module).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_export() {
+        let ty = FuncType {
+            params: vec![],
+            results: vec![],
+        };
+        let mut module = module_with_func(ty, vec![]);
+        for _ in 0..2 {
+            module.exports.push(Export {
+                name: Name::new("f".to_string()),
+                desc: ExportDesc::Func(Idx::new(0)),
+            });
+        }
+        assert!(validate(&module).is_err());
+    }
+
+    #[test]
+    fn test_start_function_type() {
+        let ty = FuncType {
+            params: vec![I32],
+            results: vec![],
+        };
+        let mut module = module_with_func(ty, vec![]);
+        module.start = Some(Idx::new(0));
+        assert!(validate(&module).is_err());
+    }
+
+    #[test]
+    fn test_data_count_required() {
+        let ty = FuncType {
+            params: vec![],
+            results: vec![],
+        };
+        let mut module = module_with_func(ty, vec![Instruction::DataDrop(Idx::new(0))]);
+        module.datas.push(Data {
+            init: vec![],
+            mode: DataMode::Passive,
+        });
+        assert!(validate(&module).is_err());
+
+        module.data_count = Some(1);
+        validate(&module).unwrap();
+    }
+
+    #[test]
+    fn test_vector_types() {
+        for wat in [
+            "(module (func (param v128)))",
+            "(module (func (result v128) (local v128) local.get 0))",
+            "(module (global v128 (v128.const i64x2 0 0)))",
+            "(module (import \"m\" \"g\" (global v128)))",
+            "(module (func (block (result v128) unreachable) drop))",
+            "(module (func unreachable select (result v128) drop))",
+        ] {
+            let buf = wast::parser::ParseBuffer::new(wat).unwrap();
+            let mut wat = wast::parser::parse::<wast::Wat>(&buf).unwrap();
+            let bin = wat.encode().unwrap();
+            let module = crate::decode::decode(&mut std::io::Cursor::new(bin)).unwrap();
+            let err = validate(&module).unwrap_err();
+            assert_eq!(
+                err.root_cause().to_string(),
+                "vector types are not supported"
+            );
+        }
+    }
+
+    #[test]
+    fn test_decoded_modules() {
+        for path in [
+            "tests/add.wasm",
+            "tests/test.wasm",
+            "tests/combination.wasm",
+        ] {
+            let file = std::fs::File::open(path).unwrap();
+            let mut reader = std::io::BufReader::new(file);
+            let module = crate::decode::decode(&mut reader).unwrap();
+            validate(&module).unwrap();
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::core::{
+    DataIdx, ElemIdx, ExportDesc, FuncIdx, FuncType, GlobalIdx, GlobalType, Idx, ImportDesc,
+    Instruction, MemIdx, MemoryType, Module, RefType, TableIdx, TableType, TypeIdx,
+};
+use anyhow::{anyhow, ensure, Result};
+use std::collections::HashSet;
+
+pub struct Context {
+    pub types: Vec<FuncType>,
+    pub funcs: Vec<Idx<TypeIdx>>,
+    pub tables: Vec<TableType>,
+    pub mems: Vec<MemoryType>,
+    pub globals: Vec<GlobalType>,
+    pub imported_globals: usize,
+    pub elems: Vec<RefType>,
+    pub datas: usize,
+    pub has_data_count: bool,
+    pub refs: HashSet<u32>,
+}
+
+impl Context {
+    pub fn new(module: &Module) -> Self {
+        let mut ctx = Context {
+            types: module.types.clone(),
+            funcs: Vec::new(),
+            tables: Vec::new(),
+            mems: Vec::new(),
+            globals: Vec::new(),
+            imported_globals: 0,
+            elems: module.elements.iter().map(|e| e.ty).collect(),
+            datas: module.datas.len(),
+            has_data_count: module.data_count.is_some(),
+            refs: HashSet::new(),
+        };
+
+        for import in &module.imports {
+            match &import.desc {
+                ImportDesc::Func(ty) => ctx.funcs.push(*ty),
+                ImportDesc::Table(ty) => ctx.tables.push(ty.clone()),
+                ImportDesc::Memory(ty) => ctx.mems.push(ty.clone()),
+                ImportDesc::Global(ty) => ctx.globals.push(ty.clone()),
+            }
+        }
+        ctx.imported_globals = ctx.globals.len();
+
+        ctx.funcs.extend(module.funcs.iter().map(|f| f.type_id));
+        ctx.tables.extend(module.tables.iter().map(|t| t.0.clone()));
+        ctx.mems.extend(module.memories.iter().map(|m| m.0.clone()));
+        ctx.globals
+            .extend(module.globals.iter().map(|g| g.global_type.clone()));
+
+        // functions referenced outside of function bodies may be used by ref.func
+        let exprs = module
+            .globals
+            .iter()
+            .map(|g| &g.init)
+            .chain(module.elements.iter().flat_map(|e| e.init.iter()));
+        for expr in exprs {
+            for instr in &expr.instructions {
+                if let Instruction::RefFunc(idx) = instr {
+                    ctx.refs.insert(idx.get());
+                }
+            }
+        }
+        for export in &module.exports {
+            if let ExportDesc::Func(idx) = export.desc {
+                ctx.refs.insert(idx.get());
+            }
+        }
+
+        ctx
+    }
+
+    pub fn get_type(&self, idx: Idx<TypeIdx>) -> Result<&FuncType> {
+        self.types
+            .get(idx.get() as usize)
+            .ok_or_else(|| anyhow!("unknown type {}", idx.get()))
+    }
+
+    pub fn get_func_type(&self, idx: Idx<FuncIdx>) -> Result<&FuncType> {
+        let ty = self
+            .funcs
+            .get(idx.get() as usize)
+            .ok_or_else(|| anyhow!("unknown function {}", idx.get()))?;
+        self.get_type(*ty)
+    }
+
+    pub fn get_table(&self, idx: Idx<TableIdx>) -> Result<&TableType> {
+        self.tables
+            .get(idx.get() as usize)
+            .ok_or_else(|| anyhow!("unknown table {}", idx.get()))
+    }
+
+    pub fn get_elem(&self, idx: Idx<ElemIdx>) -> Result<RefType> {
+        self.elems
+            .get(idx.get() as usize)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown elem segment {}", idx.get()))
+    }
+
+    pub fn get_global(&self, idx: Idx<GlobalIdx>) -> Result<&GlobalType> {
+        self.globals
+            .get(idx.get() as usize)
+            .ok_or_else(|| anyhow!("unknown global {}", idx.get()))
+    }
+
+    pub fn ensure_data(&self, idx: Idx<DataIdx>) -> Result<()> {
+        ensure!(self.has_data_count, "data count section required");
+        ensure!(
+            (idx.get() as usize) < self.datas,
+            "unknown data segment {}",
+            idx.get()
+        );
+        Ok(())
+    }
+
+    pub fn ensure_memory(&self, idx: Idx<MemIdx>) -> Result<()> {
+        ensure!(
+            (idx.get() as usize) < self.mems.len(),
+            "unknown memory {}",
+            idx.get()
+        );
+        Ok(())
+    }
+}
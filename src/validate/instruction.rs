@@ -0,0 +1,526 @@
+use super::context::Context;
+use super::validate_value_type;
+use crate::core::{
+    BlockType, FuncType, Idx, Instruction, LabelIdx, MemArg, NumType, RefType, ValueType,
+};
+use anyhow::{anyhow, bail, ensure, Result};
+
+const I32: ValueType = ValueType::Num(NumType::I32);
+const I64: ValueType = ValueType::Num(NumType::I64);
+const F32: ValueType = ValueType::Num(NumType::F32);
+const F64: ValueType = ValueType::Num(NumType::F64);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CtrlKind {
+    Block,
+    Loop,
+    If,
+}
+
+struct CtrlFrame {
+    kind: CtrlKind,
+    start_types: Vec<ValueType>,
+    end_types: Vec<ValueType>,
+    height: usize,
+    unreachable: bool,
+}
+
+impl CtrlFrame {
+    fn label_types(&self) -> &[ValueType] {
+        if self.kind == CtrlKind::Loop {
+            &self.start_types
+        } else {
+            &self.end_types
+        }
+    }
+}
+
+/// Type checker for instruction sequences, following the validation algorithm
+/// in the appendix of the specification. `None` on the operand stack stands for
+/// an unknown type produced in unreachable code.
+pub struct InstrValidator<'a> {
+    ctx: &'a Context,
+    /// Runs of locals of the same type, as the index following the run.
+    locals: Vec<(u64, ValueType)>,
+    vals: Vec<Option<ValueType>>,
+    ctrls: Vec<CtrlFrame>,
+}
+
+impl<'a> InstrValidator<'a> {
+    /// Creates a validator for code with the given runs of locals of the same
+    /// type, including the parameters.
+    pub fn new(ctx: &'a Context, locals: impl IntoIterator<Item = (u32, ValueType)>) -> Self {
+        let mut end = 0;
+        let locals = locals
+            .into_iter()
+            .map(|(n, ty)| {
+                end += n as u64;
+                (end, ty)
+            })
+            .collect();
+        InstrValidator {
+            ctx,
+            locals,
+            vals: Vec::new(),
+            ctrls: Vec::new(),
+        }
+    }
+
+    /// Validates a function body or constant expression producing `results`.
+    pub fn validate_expr(
+        &mut self,
+        instructions: &[Instruction],
+        results: &[ValueType],
+    ) -> Result<()> {
+        self.push_ctrl(CtrlKind::Block, vec![], results.to_vec());
+        self.validate_seq(instructions)?;
+        self.pop_ctrl()?;
+        Ok(())
+    }
+
+    fn push_val(&mut self, ty: ValueType) {
+        self.vals.push(Some(ty));
+    }
+
+    fn push_vals(&mut self, types: &[ValueType]) {
+        for ty in types {
+            self.push_val(*ty);
+        }
+    }
+
+    fn pop_val(&mut self) -> Result<Option<ValueType>> {
+        let frame = self.ctrls.last().expect("control stack is empty");
+        if self.vals.len() == frame.height {
+            ensure!(frame.unreachable, "type mismatch");
+            return Ok(None);
+        }
+        Ok(self.vals.pop().expect("checked above"))
+    }
+
+    fn pop_expect(&mut self, expect: ValueType) -> Result<Option<ValueType>> {
+        let actual = self.pop_val()?;
+        if let Some(actual) = actual {
+            ensure!(actual == expect, "type mismatch");
+        }
+        Ok(actual)
+    }
+
+    fn pop_vals(&mut self, types: &[ValueType]) -> Result<Vec<Option<ValueType>>> {
+        let mut vals = types
+            .iter()
+            .rev()
+            .map(|ty| self.pop_expect(*ty))
+            .collect::<Result<Vec<_>>>()?;
+        vals.reverse();
+        Ok(vals)
+    }
+
+    fn push_ctrl(
+        &mut self,
+        kind: CtrlKind,
+        start_types: Vec<ValueType>,
+        end_types: Vec<ValueType>,
+    ) {
+        self.ctrls.push(CtrlFrame {
+            kind,
+            start_types: start_types.clone(),
+            end_types,
+            height: self.vals.len(),
+            unreachable: false,
+        });
+        self.push_vals(&start_types);
+    }
+
+    fn pop_ctrl(&mut self) -> Result<CtrlFrame> {
+        let end_types = self
+            .ctrls
+            .last()
+            .expect("control stack is empty")
+            .end_types
+            .clone();
+        self.pop_vals(&end_types)?;
+
+        let frame = self.ctrls.pop().expect("checked above");
+        ensure!(self.vals.len() == frame.height, "type mismatch");
+        Ok(frame)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.ctrls.last_mut().expect("control stack is empty");
+        self.vals.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label_types(&self, idx: Idx<LabelIdx>) -> Result<Vec<ValueType>> {
+        let depth = idx.get() as usize;
+        ensure!(depth < self.ctrls.len(), "unknown label {}", depth);
+        let frame = &self.ctrls[self.ctrls.len() - 1 - depth];
+        Ok(frame.label_types().to_vec())
+    }
+
+    fn block_type(&self, block_type: &BlockType) -> Result<FuncType> {
+        let ty = match block_type {
+            BlockType::ValType(ty) => {
+                if let Some(ty) = ty {
+                    validate_value_type(*ty)?;
+                }
+                FuncType {
+                    params: vec![],
+                    results: ty.iter().copied().collect(),
+                }
+            }
+            BlockType::Type(idx) => self.ctx.get_type(*idx)?.clone(),
+        };
+        Ok(ty)
+    }
+
+    fn local(&self, idx: u32) -> Result<ValueType> {
+        let i = self.locals.partition_point(|(end, _)| *end <= idx as u64);
+        self.locals
+            .get(i)
+            .map(|(_, ty)| *ty)
+            .ok_or_else(|| anyhow!("unknown local {}", idx))
+    }
+
+    fn unop(&mut self, input: ValueType, output: ValueType) -> Result<()> {
+        self.pop_expect(input)?;
+        self.push_val(output);
+        Ok(())
+    }
+
+    fn binop(&mut self, input: ValueType, output: ValueType) -> Result<()> {
+        self.pop_expect(input)?;
+        self.pop_expect(input)?;
+        self.push_val(output);
+        Ok(())
+    }
+
+    fn load(&mut self, mem_arg: &MemArg, ty: ValueType, width: u32) -> Result<()> {
+        self.check_mem_arg(mem_arg, width)?;
+        self.unop(I32, ty)
+    }
+
+    fn store(&mut self, mem_arg: &MemArg, ty: ValueType, width: u32) -> Result<()> {
+        self.check_mem_arg(mem_arg, width)?;
+        self.pop_expect(ty)?;
+        self.pop_expect(I32)?;
+        Ok(())
+    }
+
+    fn check_mem_arg(&self, mem_arg: &MemArg, width: u32) -> Result<()> {
+        self.ctx.ensure_memory(0.into())?;
+        ensure!(
+            mem_arg.align < 32 && 1u32 << mem_arg.align <= width / 8,
+            "alignment must not be larger than natural"
+        );
+        Ok(())
+    }
+
+    fn validate_seq(&mut self, instructions: &[Instruction]) -> Result<()> {
+        for instr in instructions {
+            self.validate_instr(instr)?;
+        }
+        Ok(())
+    }
+
+    fn validate_instr(&mut self, instr: &Instruction) -> Result<()> {
+        match instr {
+            // control instructions
+            Instruction::Unreachable => self.set_unreachable(),
+            Instruction::Nop => {}
+            Instruction::Block {
+                block_type,
+                instructions,
+            }
+            | Instruction::Loop {
+                block_type,
+                instructions,
+            } => {
+                let ty = self.block_type(block_type)?;
+                let kind = match instr {
+                    Instruction::Loop { .. } => CtrlKind::Loop,
+                    _ => CtrlKind::Block,
+                };
+
+                self.pop_vals(&ty.params)?;
+                self.push_ctrl(kind, ty.params, ty.results);
+                self.validate_seq(instructions)?;
+                let frame = self.pop_ctrl()?;
+                self.push_vals(&frame.end_types);
+            }
+            Instruction::If {
+                block_type,
+                instructions,
+                else_instructions,
+            } => {
+                let ty = self.block_type(block_type)?;
+                self.pop_expect(I32)?;
+
+                self.pop_vals(&ty.params)?;
+                self.push_ctrl(CtrlKind::If, ty.params.clone(), ty.results.clone());
+                self.validate_seq(instructions)?;
+                self.pop_ctrl()?;
+
+                // a missing else branch is validated as an empty one
+                self.push_ctrl(CtrlKind::If, ty.params, ty.results);
+                self.validate_seq(else_instructions)?;
+                let frame = self.pop_ctrl()?;
+                self.push_vals(&frame.end_types);
+            }
+            Instruction::Br(idx) => {
+                let types = self.label_types(*idx)?;
+                self.pop_vals(&types)?;
+                self.set_unreachable();
+            }
+            Instruction::BrIf(idx) => {
+                self.pop_expect(I32)?;
+                let types = self.label_types(*idx)?;
+                self.pop_vals(&types)?;
+                self.push_vals(&types);
+            }
+            Instruction::BrTable(labels, default) => {
+                self.pop_expect(I32)?;
+                let default_types = self.label_types(*default)?;
+                for label in labels {
+                    let types = self.label_types(*label)?;
+                    ensure!(types.len() == default_types.len(), "type mismatch");
+                    let vals = self.pop_vals(&types)?;
+                    self.vals.extend(vals);
+                }
+                self.pop_vals(&default_types)?;
+                self.set_unreachable();
+            }
+            Instruction::Return => {
+                let types = self.ctrls[0].end_types.clone();
+                self.pop_vals(&types)?;
+                self.set_unreachable();
+            }
+            Instruction::Call(idx) => {
+                let ty = self.ctx.get_func_type(*idx)?;
+                self.pop_vals(&ty.params)?;
+                self.push_vals(&ty.results);
+            }
+            Instruction::CallIndirect { ty, table } => {
+                let table = self.ctx.get_table(*table)?;
+                ensure!(table.elem_type == RefType::Funcref, "type mismatch");
+
+                let ty = self.ctx.get_type(*ty)?;
+                self.pop_expect(I32)?;
+                self.pop_vals(&ty.params)?;
+                self.push_vals(&ty.results);
+            }
+
+            // reference instructions
+            Instruction::RefNull(ty) => self.push_val(ValueType::Ref(*ty)),
+            Instruction::RefIsNull => {
+                let ty = self.pop_val()?;
+                ensure!(
+                    matches!(ty, None | Some(ValueType::Ref(_))),
+                    "type mismatch"
+                );
+                self.push_val(I32);
+            }
+            Instruction::RefFunc(idx) => {
+                self.ctx.get_func_type(*idx)?;
+                ensure!(
+                    self.ctx.refs.contains(&idx.get()),
+                    "undeclared function reference"
+                );
+                self.push_val(ValueType::Ref(RefType::Funcref));
+            }
+
+            // parametric instructions
+            Instruction::Drop => {
+                self.pop_val()?;
+            }
+            Instruction::Select(types) => {
+                self.pop_expect(I32)?;
+                if types.is_empty() {
+                    let t1 = self.pop_val()?;
+                    let t2 = self.pop_val()?;
+                    ensure!(
+                        !matches!(t1, Some(ValueType::Ref(_)))
+                            && !matches!(t2, Some(ValueType::Ref(_))),
+                        "type mismatch"
+                    );
+                    let ty = match (t1, t2) {
+                        (None, t) | (t, None) => t,
+                        (Some(t1), Some(t2)) => {
+                            ensure!(t1 == t2, "type mismatch");
+                            Some(t1)
+                        }
+                    };
+                    self.vals.push(ty);
+                } else {
+                    ensure!(types.len() == 1, "invalid result arity");
+                    validate_value_type(types[0])?;
+                    self.pop_expect(types[0])?;
+                    self.pop_expect(types[0])?;
+                    self.push_val(types[0]);
+                }
+            }
+
+            // variable instructions
+            Instruction::LocalGet(idx) => {
+                let ty = self.local(idx.get())?;
+                self.push_val(ty);
+            }
+            Instruction::LocalSet(idx) => {
+                let ty = self.local(idx.get())?;
+                self.pop_expect(ty)?;
+            }
+            Instruction::LocalTee(idx) => {
+                let ty = self.local(idx.get())?;
+                self.unop(ty, ty)?;
+            }
+            Instruction::GlobalGet(idx) => {
+                let ty = self.ctx.get_global(*idx)?.value_type;
+                self.push_val(ty);
+            }
+            Instruction::GlobalSet(idx) => {
+                let global = self.ctx.get_global(*idx)?;
+                ensure!(global.mutability, "global is immutable");
+                let ty = global.value_type;
+                self.pop_expect(ty)?;
+            }
+
+            // table instructions
+            Instruction::TableGet(idx) => {
+                let ty = self.ctx.get_table(*idx)?.elem_type;
+                self.unop(I32, ValueType::Ref(ty))?;
+            }
+            Instruction::TableSet(idx) => {
+                let ty = self.ctx.get_table(*idx)?.elem_type;
+                self.pop_vals(&[I32, ValueType::Ref(ty)])?;
+            }
+            Instruction::TableSize(idx) => {
+                self.ctx.get_table(*idx)?;
+                self.push_val(I32);
+            }
+            Instruction::TableGrow(idx) => {
+                let ty = self.ctx.get_table(*idx)?.elem_type;
+                self.pop_vals(&[ValueType::Ref(ty), I32])?;
+                self.push_val(I32);
+            }
+            Instruction::TableFill(idx) => {
+                let ty = self.ctx.get_table(*idx)?.elem_type;
+                self.pop_vals(&[I32, ValueType::Ref(ty), I32])?;
+            }
+            Instruction::TableCopy { dst, src } => {
+                let dst = self.ctx.get_table(*dst)?.elem_type;
+                let src = self.ctx.get_table(*src)?.elem_type;
+                ensure!(dst == src, "type mismatch");
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::TableInit { elem, table } => {
+                let table = self.ctx.get_table(*table)?.elem_type;
+                let elem = self.ctx.get_elem(*elem)?;
+                ensure!(table == elem, "type mismatch");
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::ElemDrop(idx) => {
+                self.ctx.get_elem(*idx)?;
+            }
+
+            // memory instructions
+            Instruction::I32Load(m) => self.load(m, I32, 32)?,
+            Instruction::I64Load(m) => self.load(m, I64, 64)?,
+            Instruction::F32Load(m) => self.load(m, F32, 32)?,
+            Instruction::F64Load(m) => self.load(m, F64, 64)?,
+            Instruction::I32Load8S(m) | Instruction::I32Load8U(m) => self.load(m, I32, 8)?,
+            Instruction::I32Load16S(m) | Instruction::I32Load16U(m) => self.load(m, I32, 16)?,
+            Instruction::I64Load8S(m) | Instruction::I64Load8U(m) => self.load(m, I64, 8)?,
+            Instruction::I64Load16S(m) | Instruction::I64Load16U(m) => self.load(m, I64, 16)?,
+            Instruction::I64Load32S(m) | Instruction::I64Load32U(m) => self.load(m, I64, 32)?,
+            Instruction::I32Store(m) => self.store(m, I32, 32)?,
+            Instruction::I64Store(m) => self.store(m, I64, 64)?,
+            Instruction::F32Store(m) => self.store(m, F32, 32)?,
+            Instruction::F64Store(m) => self.store(m, F64, 64)?,
+            Instruction::I32Store8(m) => self.store(m, I32, 8)?,
+            Instruction::I32Store16(m) => self.store(m, I32, 16)?,
+            Instruction::I64Store8(m) => self.store(m, I64, 8)?,
+            Instruction::I64Store16(m) => self.store(m, I64, 16)?,
+            Instruction::I64Store32(m) => self.store(m, I64, 32)?,
+            Instruction::MemorySize => {
+                self.ctx.ensure_memory(0.into())?;
+                self.push_val(I32);
+            }
+            Instruction::MemoryGrow => {
+                self.ctx.ensure_memory(0.into())?;
+                self.unop(I32, I32)?;
+            }
+            Instruction::MemoryInit(idx) => {
+                self.ctx.ensure_memory(0.into())?;
+                self.ctx.ensure_data(*idx)?;
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::MemoryCopy | Instruction::MemoryFill => {
+                self.ctx.ensure_memory(0.into())?;
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::DataDrop(idx) => self.ctx.ensure_data(*idx)?,
+
+            // numeric instructions
+            Instruction::I32Const(_) => self.push_val(I32),
+            Instruction::I32Extend8S | Instruction::I32Extend16S | Instruction::I32UnOp(_) => {
+                self.unop(I32, I32)?
+            }
+            Instruction::I32Eqz => self.unop(I32, I32)?,
+            Instruction::I32BinOp(_) | Instruction::I32RelOp(_) => self.binop(I32, I32)?,
+
+            Instruction::I64Const(_) => self.push_val(I64),
+            Instruction::I64UnOp(_)
+            | Instruction::I64Extend8S
+            | Instruction::I64Extend16S
+            | Instruction::I64Extend32S => self.unop(I64, I64)?,
+            Instruction::I64BinOp(_) => self.binop(I64, I64)?,
+            Instruction::I64RelOp(_) => self.binop(I64, I32)?,
+            Instruction::I64Eqz => self.unop(I64, I32)?,
+
+            Instruction::F32Const(_) => self.push_val(F32),
+            Instruction::F32UnOp(_) => self.unop(F32, F32)?,
+            Instruction::F32BinOp(_) => self.binop(F32, F32)?,
+            Instruction::F32RelOp(_) => self.binop(F32, I32)?,
+
+            Instruction::F64Const(_) => self.push_val(F64),
+            Instruction::F64UnOp(_) => self.unop(F64, F64)?,
+            Instruction::F64BinOp(_) => self.binop(F64, F64)?,
+            Instruction::F64RelOp(_) => self.binop(F64, I32)?,
+
+            Instruction::I32WrapI64 => self.unop(I64, I32)?,
+            Instruction::I32TruncF32S
+            | Instruction::I32TruncF32U
+            | Instruction::I32TruncSatF32S
+            | Instruction::I32TruncSatF32U
+            | Instruction::I32ReinterpretF32 => self.unop(F32, I32)?,
+            Instruction::I32TruncF64S
+            | Instruction::I32TruncF64U
+            | Instruction::I32TruncSatF64S
+            | Instruction::I32TruncSatF64U => self.unop(F64, I32)?,
+            Instruction::I64ExtendI32S | Instruction::I64ExtendI32U => self.unop(I32, I64)?,
+            Instruction::I64TruncF32S
+            | Instruction::I64TruncF32U
+            | Instruction::I64TruncSatF32S
+            | Instruction::I64TruncSatF32U => self.unop(F32, I64)?,
+            Instruction::I64TruncF64S
+            | Instruction::I64TruncF64U
+            | Instruction::I64TruncSatF64S
+            | Instruction::I64TruncSatF64U
+            | Instruction::I64ReinterpretF64 => self.unop(F64, I64)?,
+            Instruction::F32ConvertI32S
+            | Instruction::F32ConvertI32U
+            | Instruction::F32ReinterpretI32 => self.unop(I32, F32)?,
+            Instruction::F32ConvertI64S | Instruction::F32ConvertI64U => self.unop(I64, F32)?,
+            Instruction::F32DemoteF64 => self.unop(F64, F32)?,
+            Instruction::F64ConvertI32S | Instruction::F64ConvertI32U => self.unop(I32, F64)?,
+            Instruction::F64ConvertI64S
+            | Instruction::F64ConvertI64U
+            | Instruction::F64ReinterpretI64 => self.unop(I64, F64)?,
+            Instruction::F64PromoteF32 => self.unop(F32, F64)?,
+
+            // vector instructions
+            Instruction::Vector => bail!("vector instructions are not supported"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+(module
+  (func $const-i32 (result i32) (i32.const 0x132))
+  (func $const-i32-i64 (result i32 i64) (i32.const 0x132) (i64.const 0x164))
+  (func $id-f64 (param f64) (result f64) (local.get 0))
+  (func $swap-i32-i32 (param i32 i32) (result i32 i32) (local.get 1) (local.get 0))
+  (func $f64-f32 (param f64 f32) (result f32) (local.get 1))
+  (func (export "type-i32-i64") (result i32 i64) (call $const-i32-i64))
+  (func (export "type-first-f64") (result f64) (call $id-f64 (f64.const 1.64)))
+  (func (export "type-second-f32") (result f32) (call $f64-f32 (f64.const 64) (f32.const 32)))
+  (func (export "as-call-all-operands") (result i32 i32)
+    (call $swap-i32-i32 (call $swap-i32-i32 (i32.const 3) (i32.const 4))))
+  (func $fac-acc (export "fac-acc") (param i64 i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 1))
+      (else (call $fac-acc (i64.sub (local.get 0) (i64.const 1)) (i64.mul (local.get 0) (local.get 1))))))
+  (func $fib (export "fib") (param i64) (result i64)
+    (if (result i64) (i64.le_u (local.get 0) (i64.const 1))
+      (then (i64.const 1))
+      (else
+        (i64.add
+          (call $fib (i64.sub (local.get 0) (i64.const 2)))
+          (call $fib (i64.sub (local.get 0) (i64.const 1)))))))
+  (func $even (export "even") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 44))
+      (else (call $odd (i64.sub (local.get 0) (i64.const 1))))))
+  (func $odd (export "odd") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 99))
+      (else (call $even (i64.sub (local.get 0) (i64.const 1))))))
+  (func $runaway (export "runaway") (call $runaway))
+  (func $mutual-runaway1 (export "mutual-runaway") (call $mutual-runaway2))
+  (func $mutual-runaway2 (call $mutual-runaway1))
+  (memory 1)
+  (func (export "as-select-first") (result i32) (select (call $const-i32) (i32.const 2) (i32.const 3)))
+  (func (export "as-br_table-first") (result i32)
+    (block (result i32) (call $const-i32) (i32.const 2) (br_table 0 0)))
+  (func $func (param i32 i32) (result i32) (local.get 0))
+  (type $check (func (param i32 i32) (result i32)))
+  (table funcref (elem $func))
+  (func (export "as-call_indirect-last") (result i32)
+    (block (result i32) (call_indirect (type $check) (i32.const 1) (i32.const 2) (call $const-i32))))
+  (func (export "as-store-first") (call $const-i32) (i32.const 1) (i32.store))
+  (func (export "as-memory.grow-value") (result i32) (memory.grow (call $const-i32)))
+  (func (export "as-load-operand") (result i32) (i32.load (call $const-i32))))
+(assert_return (invoke "type-i32-i64") (i32.const 0x132) (i64.const 0x164))
+(assert_return (invoke "type-first-f64") (f64.const 1.64))
+(assert_return (invoke "type-second-f32") (f32.const 32))
+(assert_return (invoke "as-call-all-operands") (i32.const 3) (i32.const 4))
+(assert_return (invoke "fac-acc" (i64.const 25) (i64.const 1)) (i64.const 7034535277573963776))
+(assert_return (invoke "fib" (i64.const 20)) (i64.const 10946))
+(assert_return (invoke "even" (i64.const 100)) (i32.const 44))
+(assert_return (invoke "odd" (i64.const 77)) (i32.const 44))
+(assert_exhaustion (invoke "runaway") "call stack exhausted")
+(assert_exhaustion (invoke "mutual-runaway") "call stack exhausted")
+(assert_return (invoke "as-select-first") (i32.const 0x132))
+(assert_return (invoke "as-br_table-first") (i32.const 0x132))
+(assert_trap (invoke "as-call_indirect-last") "undefined element")
+(assert_return (invoke "as-store-first"))
+(assert_return (invoke "as-memory.grow-value") (i32.const 1))
+(assert_return (invoke "as-load-operand") (i32.const 1))
+(assert_invalid (module (func $arity-0-vs-1 (call 1)) (func (param i32))) "type mismatch")
+(assert_invalid (module (func $type-num-vs-num (i32.eqz (call 1))) (func (result i64) (i64.const 1))) "type mismatch")
+(assert_invalid (module (func $unbound-func (call 1))) "unknown function")
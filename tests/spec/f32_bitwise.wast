@@ -0,0 +1,8 @@
+(module
+  (func (export "abs") (param f32) (result f32) (f32.abs (local.get 0)))
+  (func (export "neg") (param f32) (result f32) (f32.neg (local.get 0)))
+  (func (export "copysign") (param f32 f32) (result f32) (f32.copysign (local.get 0) (local.get 1)))
+)
+(assert_return (invoke "abs" (f32.const -nan:0x200000)) (f32.const nan:0x200000))
+(assert_return (invoke "neg" (f32.const nan:0x200000)) (f32.const -nan:0x200000))
+(assert_return (invoke "copysign" (f32.const 1) (f32.const -0)) (f32.const -1))
@@ -0,0 +1,9 @@
+(module
+  (func (export "eq") (param f32 f32) (result i32) (f32.eq (local.get 0) (local.get 1)))
+  (func (export "ne") (param f32 f32) (result i32) (f32.ne (local.get 0) (local.get 1)))
+  (func (export "lt") (param f32 f32) (result i32) (f32.lt (local.get 0) (local.get 1)))
+)
+(assert_return (invoke "eq" (f32.const 0) (f32.const -0)) (i32.const 1))
+(assert_return (invoke "eq" (f32.const nan) (f32.const nan)) (i32.const 0))
+(assert_return (invoke "ne" (f32.const nan) (f32.const nan)) (i32.const 1))
+(assert_return (invoke "lt" (f32.const -1) (f32.const 1)) (i32.const 1))
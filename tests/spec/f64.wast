@@ -0,0 +1,19 @@
+(module
+  (func (export "add") (param f64 f64) (result f64) (f64.add (local.get 0) (local.get 1)))
+  (func (export "div") (param f64 f64) (result f64) (f64.div (local.get 0) (local.get 1)))
+  (func (export "min") (param f64 f64) (result f64) (f64.min (local.get 0) (local.get 1)))
+  (func (export "max") (param f64 f64) (result f64) (f64.max (local.get 0) (local.get 1)))
+  (func (export "nearest") (param f64) (result f64) (f64.nearest (local.get 0)))
+  (func (export "sqrt") (param f64) (result f64) (f64.sqrt (local.get 0)))
+)
+(assert_return (invoke "add" (f64.const 1.5) (f64.const 2.25)) (f64.const 3.75))
+(assert_return (invoke "add" (f64.const inf) (f64.const -inf)) (f64.const nan:canonical))
+(assert_return (invoke "add" (f64.const nan) (f64.const 1)) (f64.const nan:arithmetic))
+(assert_return (invoke "div" (f64.const 1) (f64.const 0)) (f64.const inf))
+(assert_return (invoke "min" (f64.const 0) (f64.const -0)) (f64.const -0))
+(assert_return (invoke "max" (f64.const -0) (f64.const 0)) (f64.const 0))
+(assert_return (invoke "min" (f64.const 1) (f64.const nan)) (f64.const nan:canonical))
+(assert_return (invoke "nearest" (f64.const 2.5)) (f64.const 2))
+(assert_return (invoke "nearest" (f64.const -3.5)) (f64.const -4))
+(assert_return (invoke "sqrt" (f64.const 4)) (f64.const 2))
+(assert_invalid (module (func (result f64) (f64.add (i32.const 0) (f64.const 0)))) "type mismatch")
@@ -0,0 +1,9 @@
+(module
+  (memory 1 1)
+  (func (export "fill") (param i32 i32 i32) (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "load8_u") (param i32) (result i32) (i32.load8_u (local.get 0))))
+(invoke "fill" (i32.const 1) (i32.const 0xff) (i32.const 3))
+(assert_return (invoke "load8_u" (i32.const 0)) (i32.const 0))
+(assert_return (invoke "load8_u" (i32.const 3)) (i32.const 0xff))
+(assert_trap (invoke "fill" (i32.const 0x10000) (i32.const 0) (i32.const 1)) "out of bounds memory access")
+(assert_return (invoke "fill" (i32.const 0x10000) (i32.const 0) (i32.const 0)))
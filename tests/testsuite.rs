@@ -1,32 +1,63 @@
 use paste::paste;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Cursor, Read};
+use std::rc::Rc;
 use wasm_runtime::{
-    core::Module,
-    decode::decode,
-    execute::{Store, Value},
+    core::{FuncType, GlobalType, Limits, NumType, RefType, TableType, ValueType},
+    decode::{decode, DecodeError},
+    encode::encode,
+    execute::{Config, Engine, Extern, Linker, ModuleInstance, Store, Trap, TrapKind, Value},
+    validate::validate,
 };
 use wast::{
-    core::{WastArgCore, WastRetCore},
+    core::{HeapType, ModuleKind, NanPattern, WastArgCore, WastRetCore},
     parser::{parse, ParseBuffer},
-    WastArg, WastDirective, WastExecute, WastInvoke, WastRet,
+    token::Id,
+    QuoteWat, WastArg, WastDirective, WastExecute, WastInvoke, WastRet, Wat,
 };
 
 testsuite!(i32, i64);
 
+// Hand-trimmed versions of spec tests, keeping the cases that exercise the
+// features implemented so far. Passing them does not mean that the full files
+// of the testsuite submodule pass.
+testsuite!(
+    "tests/spec" =>
+    binary,
+    binary_leb128,
+    custom,
+    call,
+    fac,
+    f32,
+    f32_bitwise,
+    f32_cmp,
+    f64,
+    f64_bitwise,
+    f64_cmp,
+    conversions,
+    memory_copy,
+    memory_fill
+);
+
 #[macro_export]
 macro_rules! testsuite {
     ($($f: ident), *) => {
+        testsuite!("tests/testsuite" => $($f), *);
+    };
+    ($dir: literal => $($f: ident), *) => {
         $(paste! {
             #[test]
             fn [< testsuite_ $f >]() {
-                test_wast(format!("tests/testsuite/{}.wast", stringify!($f)).as_str());
+                let filename = format!("{}/{}.wast", $dir, stringify!($f));
+                test_wast(&filename, Engine::Stack);
+                test_wast(&filename, Engine::Register);
             }
         })*
     };
 }
 
-fn test_wast(filename: &str) {
+fn test_wast(filename: &str, engine: Engine) {
     let mut f = File::open(filename).unwrap();
     let mut b = vec![];
     f.read_to_end(&mut b).unwrap();
@@ -34,53 +65,170 @@ fn test_wast(filename: &str) {
 
     let buf = ParseBuffer::new(s).unwrap();
     let wast = parse::<wast::Wast>(&buf).unwrap();
-    let mut module = Module::default();
+    let mut store = Store::new(Config {
+        engine,
+        ..Default::default()
+    });
+    let mut linker = Linker::new();
+    define_spectest(&mut store, &mut linker);
+
+    let mut current = None;
+    let mut instances = HashMap::new();
+    let get_instance = |current: &Option<Rc<ModuleInstance>>,
+                        instances: &HashMap<&str, Rc<ModuleInstance>>,
+                        id: Option<Id>| match id {
+        Some(id) => instances[id.name()].clone(),
+        None => current.clone().unwrap(),
+    };
 
     for dir in wast.directives {
         match dir {
             WastDirective::Wat(mut wat) => {
+                let (id, text) = match &wat {
+                    QuoteWat::Wat(Wat::Module(m)) => (m.id, matches!(m.kind, ModuleKind::Text(_))),
+                    _ => (None, false),
+                };
                 let bin = wat.encode().unwrap();
-                module = decode(&mut Cursor::new(bin)).unwrap();
+                let module = decode(&mut Cursor::new(&bin)).unwrap();
+                // wast emits canonical binaries for text modules, which must
+                // encode back as is
+                if text {
+                    assert_eq!(encode(&module).unwrap(), bin);
+                }
+                let instance = linker.instantiate(&mut store, module).unwrap();
+                if let Some(id) = id {
+                    instances.insert(id.name(), instance.clone());
+                }
+                current = Some(instance);
+            }
+            WastDirective::Register { name, module, .. } => {
+                let instance = get_instance(&current, &instances, module);
+                linker.register(name, &instance).unwrap();
+            }
+            WastDirective::Invoke(WastInvoke {
+                module, name, args, ..
+            }) => {
+                println!("Invoke: name: {}, args: {:?}", name, args);
+
+                let instance = get_instance(&current, &instances, module);
+                let args = args
+                    .into_iter()
+                    .map(|arg| wast_arg_to_value(&mut store, arg))
+                    .collect::<Vec<_>>();
+                store.invoke(&instance, name, args).unwrap();
             }
             WastDirective::AssertReturn { exec, results, .. } => {
-                let mut store = Store::default();
-                store.instantiate(module.clone());
+                let actual = match exec {
+                    WastExecute::Invoke(WastInvoke {
+                        module, name, args, ..
+                    }) => {
+                        println!("AssertReturn: name: {}, args: {:?}", name, args);
+
+                        let instance = get_instance(&current, &instances, module);
+                        let args = args
+                            .into_iter()
+                            .map(|arg| wast_arg_to_value(&mut store, arg))
+                            .collect::<Vec<_>>();
+                        store.invoke(&instance, name, args).unwrap()
+                    }
+                    WastExecute::Get { module, global } => {
+                        println!("AssertReturn: global: {}", global);
 
-                let WastExecute::Invoke(WastInvoke { name, args, .. }) = exec else {
-                    unimplemented!()
+                        let instance = get_instance(&current, &instances, module);
+                        vec![store.global(&instance, global).unwrap().get()]
+                    }
+                    _ => unimplemented!(),
                 };
-                println!("AssertReturn: name: {}, args: {:?}", name, args);
 
-                let args = args.into_iter().map(wast_arg_to_value).collect::<Vec<_>>();
+                assert_eq!(actual.len(), results.len());
+                for (actual, expected) in actual.into_iter().zip(results) {
+                    assert_ret(&store, actual, expected);
+                }
+            }
+            WastDirective::AssertTrap { exec, message, .. } => match exec {
+                WastExecute::Invoke(WastInvoke {
+                    module, name, args, ..
+                }) => {
+                    println!("AssertTrap: name: {}, args: {:?}", name, args);
 
-                let expected = results
-                    .into_iter()
-                    .map(wast_ret_to_value)
-                    .collect::<Vec<_>>();
+                    let instance = get_instance(&current, &instances, module);
+                    let args = args
+                        .into_iter()
+                        .map(|arg| wast_arg_to_value(&mut store, arg))
+                        .collect::<Vec<_>>();
 
-                let actual = store.invoke(name, args).unwrap();
+                    let err = store.invoke(&instance, name, args).unwrap_err();
+                    assert_trap(&err, message);
+                }
+                WastExecute::Wat(mut wat) => {
+                    println!("AssertTrap: {}", message);
 
-                assert_eq!(actual, expected);
-            }
-            WastDirective::AssertTrap { exec, .. } => {
-                let mut store = Store::default();
-                store.instantiate(module.clone());
+                    let bin = wat.encode().unwrap();
+                    let module = decode(&mut Cursor::new(bin)).unwrap();
+                    let err = linker.instantiate(&mut store, module).err().unwrap();
+                    assert_trap(&err, message);
+                }
+                _ => unimplemented!(),
+            },
+            WastDirective::AssertExhaustion { call, message, .. } => {
+                println!(
+                    "AssertExhaustion: name: {}, args: {:?}",
+                    call.name, call.args
+                );
 
-                let WastExecute::Invoke(WastInvoke { name, args, .. }) = exec else {
-                    unimplemented!()
-                };
-                println!("AssertTrap: name: {}, args: {:?}", name, args);
+                let instance = get_instance(&current, &instances, call.module);
+                let args = call
+                    .args
+                    .into_iter()
+                    .map(|arg| wast_arg_to_value(&mut store, arg))
+                    .collect::<Vec<_>>();
 
-                let args = args.into_iter().map(wast_arg_to_value).collect::<Vec<_>>();
+                let err = store.invoke(&instance, call.name, args).unwrap_err();
+                assert_trap(&err, message);
+                let kind = err.downcast_ref::<Trap>().map(Trap::kind);
+                assert_eq!(kind, Some(TrapKind::StackOverflow));
+            }
+            WastDirective::AssertInvalid {
+                mut module,
+                message,
+                ..
+            } => {
+                println!("AssertInvalid: {}", message);
 
-                let res = store.invoke(name, args);
-                assert!(res.is_err());
+                let bin = module.encode().unwrap();
+                let res = decode(&mut Cursor::new(bin)).and_then(|m| validate(&m));
+                assert!(res.is_err(), "expected invalid module: {}", message);
             }
-            WastDirective::AssertInvalid { .. } => {
-                // TODO: implement
+            WastDirective::AssertUnlinkable {
+                mut module,
+                message,
+                ..
+            } => {
+                println!("AssertUnlinkable: {}", message);
+
+                let bin = module.encode().unwrap();
+                let module = decode(&mut Cursor::new(bin)).unwrap();
+                let res = linker.instantiate(&mut store, module);
+                assert!(res.is_err(), "expected unlinkable module: {}", message);
             }
-            WastDirective::AssertMalformed { .. } => {
-                // TODO: implement
+            WastDirective::AssertMalformed {
+                mut module,
+                message,
+                ..
+            } => {
+                println!("AssertMalformed: {}", message);
+
+                // malformed text modules are already rejected by the parser
+                let binary = matches!(
+                    &module,
+                    QuoteWat::Wat(Wat::Module(m)) if matches!(m.kind, ModuleKind::Binary(_))
+                );
+                if !binary {
+                    continue;
+                }
+                let bin = module.encode().unwrap();
+                let err = decode(&mut Cursor::new(bin)).err().unwrap();
+                assert_malformed(&err, message);
             }
             _ => {
                 unimplemented!("{:?}", dir);
@@ -89,18 +237,139 @@ fn test_wast(filename: &str) {
     }
 }
 
-fn wast_arg_to_value(arg: WastArg) -> Value {
+/// Defines the `spectest` module the official test suite imports from.
+fn define_spectest(store: &mut Store, linker: &mut Linker) {
+    let print = |params: Vec<ValueType>| FuncType {
+        params,
+        results: vec![],
+    };
+    let funcs = [
+        ("print", vec![]),
+        ("print_i32", vec![ValueType::Num(NumType::I32)]),
+        ("print_i64", vec![ValueType::Num(NumType::I64)]),
+        ("print_f32", vec![ValueType::Num(NumType::F32)]),
+        ("print_f64", vec![ValueType::Num(NumType::F64)]),
+        (
+            "print_i32_f32",
+            vec![ValueType::Num(NumType::I32), ValueType::Num(NumType::F32)],
+        ),
+        (
+            "print_f64_f64",
+            vec![ValueType::Num(NumType::F64), ValueType::Num(NumType::F64)],
+        ),
+    ];
+    for (name, params) in funcs {
+        linker
+            .func(store, "spectest", name, print(params), |_, args| {
+                println!("spectest: {:?}", args);
+                Ok(vec![])
+            })
+            .unwrap();
+    }
+
+    let globals = [
+        ("global_i32", Value::I32(666)),
+        ("global_i64", Value::I64(666)),
+        ("global_f32", Value::F32(666.6)),
+        ("global_f64", Value::F64(666.6)),
+    ];
+    for (name, value) in globals {
+        let ty = GlobalType {
+            value_type: value.get_type(),
+            mutability: false,
+        };
+        let addr = store.new_global(ty, value).unwrap();
+        linker
+            .define("spectest", name, Extern::Global(addr))
+            .unwrap();
+    }
+
+    let table = TableType {
+        limits: Limits {
+            min: 10,
+            max: Some(20),
+        },
+        elem_type: RefType::Funcref,
+    };
+    let table = store.new_table(table, Value::FuncRef(None)).unwrap();
+    linker
+        .define("spectest", "table", Extern::Table(table))
+        .unwrap();
+
+    let memory = store
+        .new_memory(Limits {
+            min: 1,
+            max: Some(2),
+        })
+        .unwrap();
+    linker
+        .define("spectest", "memory", Extern::Memory(memory))
+        .unwrap();
+}
+
+fn wast_arg_to_value(store: &mut Store, arg: WastArg) -> Value {
     match arg {
         WastArg::Core(WastArgCore::I32(v)) => Value::I32(v),
         WastArg::Core(WastArgCore::I64(v)) => Value::I64(v),
+        WastArg::Core(WastArgCore::F32(v)) => Value::F32(f32::from_bits(v.bits)),
+        WastArg::Core(WastArgCore::F64(v)) => Value::F64(f64::from_bits(v.bits)),
+        WastArg::Core(WastArgCore::RefNull(HeapType::Func)) => Value::FuncRef(None),
+        WastArg::Core(WastArgCore::RefNull(HeapType::Extern)) => Value::ExternRef(None),
+        WastArg::Core(WastArgCore::RefExtern(v)) => store.new_externref(v),
         _ => unimplemented!(),
     }
 }
 
-fn wast_ret_to_value(ret: WastRet) -> Value {
-    match ret {
-        WastRet::Core(WastRetCore::I32(v)) => Value::I32(v),
-        WastRet::Core(WastRetCore::I64(v)) => Value::I64(v),
-        _ => unimplemented!(),
+fn assert_trap(err: &anyhow::Error, message: &str) {
+    match err.downcast_ref::<Trap>() {
+        Some(trap) => assert_eq!(trap.to_string(), message),
+        None => panic!("expected trap {:?}, got {:?}", message, err),
     }
 }
+
+fn assert_malformed(err: &anyhow::Error, message: &str) {
+    let Some(err) = err.downcast_ref::<DecodeError>() else {
+        panic!("expected malformed module {:?}, got {:?}", message, err);
+    };
+    // messages of the reference interpreter are sometimes more specific than
+    // ours, e.g. "unexpected end of section or function"
+    let actual = err.kind().to_string();
+    assert!(
+        actual.starts_with(message) || message.starts_with(&actual),
+        "expected malformed module {:?}, got {:?}",
+        message,
+        actual
+    );
+}
+
+fn assert_ret(store: &Store, actual: Value, expected: WastRet) {
+    let WastRet::Core(expected) = expected else {
+        unimplemented!()
+    };
+
+    let ok = match (actual, &expected) {
+        (Value::I32(a), WastRetCore::I32(e)) => a == *e,
+        (Value::I64(a), WastRetCore::I64(e)) => a == *e,
+        (Value::F32(a), WastRetCore::F32(e)) => match e {
+            NanPattern::CanonicalNan => a.to_bits() & 0x7fff_ffff == 0x7fc0_0000,
+            NanPattern::ArithmeticNan => a.is_nan() && a.to_bits() & 0x0040_0000 != 0,
+            NanPattern::Value(e) => a.to_bits() == e.bits,
+        },
+        (Value::F64(a), WastRetCore::F64(e)) => match e {
+            NanPattern::CanonicalNan => {
+                a.to_bits() & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000
+            }
+            NanPattern::ArithmeticNan => a.is_nan() && a.to_bits() & 0x0008_0000_0000_0000 != 0,
+            NanPattern::Value(e) => a.to_bits() == e.bits,
+        },
+        (Value::FuncRef(None), WastRetCore::RefNull(None | Some(HeapType::Func))) => true,
+        (Value::ExternRef(None), WastRetCore::RefNull(None | Some(HeapType::Extern))) => true,
+        (Value::FuncRef(Some(_)), WastRetCore::RefFunc(_)) => true,
+        (Value::ExternRef(Some(_)), WastRetCore::RefExtern(e)) => {
+            store.externref::<u32>(actual) == Some(e)
+        }
+        _ => false,
+    };
+
+    assert!(ok, "expected {:?}, got {:?}", expected, actual);
+}